@@ -0,0 +1,136 @@
+// KERNEL ↔ USER PROGRAM ABI:
+//
+// Only plain constants and `#[repr]` types live here, so user programs can
+// share this file with the kernel without pulling in anything else.
+//
+// A syscall is made with `syscall`, the number in rax and up to six arguments
+// in rdi, rsi, rdx, r10, r8 and r9. The result comes back in rax: a negative
+// value (as `i64`) is a negated `Errno`, anything else is a success.
+
+/// Syscall numbers, the index into the kernel's dispatch table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum Syscall {
+    /// `write(fd, buf, len) -> bytes written`
+    Write = 0,
+    /// `read(fd, buf, len) -> bytes read`, blocks until at least one byte is available
+    Read = 1,
+    /// `exit(code) -> !`
+    Exit = 2,
+    /// `sleep(milliseconds) -> 0`
+    Sleep = 3,
+    /// `time(clock) -> seconds since the epoch (CLOCK_REALTIME) or milliseconds since boot (CLOCK_MONOTONIC)`
+    Time = 4,
+    /// `mmap(addr, len, prot) -> addr`, anonymous zeroed memory, `addr` 0 lets the kernel choose
+    Mmap = 5,
+    /// `munmap(addr, len) -> 0`
+    Munmap = 6,
+}
+
+impl Syscall {
+    /// Number of syscalls, the size of the dispatch table
+    pub const COUNT: usize = 7;
+
+    pub fn from_number(number: u64) -> Option<Syscall> {
+        Some(match number {
+            0 => Syscall::Write,
+            1 => Syscall::Read,
+            2 => Syscall::Exit,
+            3 => Syscall::Sleep,
+            4 => Syscall::Time,
+            5 => Syscall::Mmap,
+            6 => Syscall::Munmap,
+            _ => return None,
+        })
+    }
+}
+
+/// Error numbers, values match Linux so existing tooling can decode them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum Errno {
+    /// Operation not permitted
+    Perm = 1,
+    /// Bad file descriptor
+    BadF = 9,
+    /// Resource temporarily unavailable
+    Again = 11,
+    /// Out of memory
+    NoMem = 12,
+    /// Bad address
+    Fault = 14,
+    /// Invalid argument
+    Inval = 22,
+    /// Function not implemented
+    NoSys = 38,
+}
+
+impl Errno {
+    /// Encodes the error as syscall return value
+    pub fn to_return_value(self) -> u64 {
+        (-(self as i64)) as u64
+    }
+
+    /// Decodes a syscall return value, `None` if it signals success
+    pub fn from_return_value(value: u64) -> Option<Errno> {
+        Some(match -(value as i64) {
+            1 => Errno::Perm,
+            9 => Errno::BadF,
+            11 => Errno::Again,
+            12 => Errno::NoMem,
+            14 => Errno::Fault,
+            22 => Errno::Inval,
+            38 => Errno::NoSys,
+            _ => return None,
+        })
+    }
+}
+
+/// Keyboard input
+pub const STDIN: u64 = 0;
+/// Console output
+pub const STDOUT: u64 = 1;
+/// Console output, additionally mirrored to serial
+pub const STDERR: u64 = 2;
+
+/// Wall-clock time in seconds since the UNIX epoch
+pub const CLOCK_REALTIME: u64 = 0;
+/// Milliseconds since boot, never goes backwards
+pub const CLOCK_MONOTONIC: u64 = 1;
+
+/// `mmap` protection flags, at least one must be set
+pub const PROT_READ: u64 = 1 << 0;
+pub const PROT_WRITE: u64 = 1 << 1;
+pub const PROT_EXEC: u64 = 1 << 2;
+
+/// Page size used for `mmap` alignment
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Auxiliary vector keys, passed on the initial stack after envp
+pub const AT_NULL: u64 = 0;
+/// Address of the program headers in memory
+pub const AT_PHDR: u64 = 3;
+/// Size of one program header
+pub const AT_PHENT: u64 = 4;
+/// Number of program headers
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+/// Entry point of the program
+pub const AT_ENTRY: u64 = 9;
+
+#[test_case]
+fn test_syscall_numbers_round_trip() {
+    for number in 0..Syscall::COUNT as u64 {
+        let syscall = Syscall::from_number(number).expect("syscall number without variant");
+        assert_eq!(syscall as u64, number);
+    }
+    assert_eq!(Syscall::from_number(Syscall::COUNT as u64), None);
+}
+
+#[test_case]
+fn test_errno_return_value() {
+    let value = Errno::Fault.to_return_value();
+    assert_eq!(value as i64, -14);
+    assert_eq!(Errno::from_return_value(value), Some(Errno::Fault));
+    assert_eq!(Errno::from_return_value(42), None);
+}
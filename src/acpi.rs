@@ -0,0 +1,195 @@
+use crate::memory;
+use alloc::vec::Vec;
+use core::slice;
+use x86_64::PhysAddr;
+
+// ACPI TABLES:
+//
+// Only the tables needed to enumerate CPUs are parsed:
+//
+// RSDP (found by scanning the BIOS areas for "RSD PTR ")
+// └── RSDT (32-bit pointers) or XSDT (64-bit pointers, ACPI 2.0+)
+//     └── MADT ("APIC"): local APIC address and one entry per CPU
+//
+// All tables are read through the physical memory mapping.
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+/// Size of the header every system description table starts with
+const SDT_HEADER_SIZE: usize = 36;
+
+/// Physical address of the real-mode segment of the extended BIOS data area
+const EBDA_SEGMENT_POINTER: u64 = 0x40e;
+const BIOS_AREA: core::ops::Range<u64> = 0xe0000..0x100000;
+
+/// MADT entry types
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+/// Local APIC entry flags
+const LOCAL_APIC_ENABLED: u32 = 1 << 0;
+const LOCAL_APIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// No RSDP in the BIOS areas, e.g. booted via UEFI or without ACPI
+    NoRsdp,
+    /// A table's bytes don't sum to zero
+    BadChecksum,
+    /// The RSDT/XSDT lists no table with the requested signature
+    TableNotFound,
+}
+
+/// A processor listed in the MADT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cpu {
+    pub acpi_id: u8,
+    pub apic_id: u8,
+    /// Usable right away; otherwise it can only be hot-plugged later
+    pub enabled: bool,
+}
+
+/// Multiple APIC description table
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: PhysAddr,
+    pub cpus: Vec<Cpu>,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// ACPI tables are valid if all their bytes sum to 0 (mod 256)
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// # Safety
+/// Physical memory `addr..addr + len` through the physical memory mapping.
+///
+/// Unsafe because the range must be RAM or firmware tables, not device memory.
+unsafe fn physical(addr: u64, len: usize) -> &'static [u8] {
+    let virt = memory::phys_to_virt(PhysAddr::new(addr));
+    slice::from_raw_parts(virt.as_ptr(), len)
+}
+
+/// Searches `range` on 16 byte boundaries for a valid RSDP.
+fn scan_for_rsdp(range: core::ops::Range<u64>) -> Option<u64> {
+    range.step_by(16).find(|&addr| {
+        let candidate = unsafe { physical(addr, 20) };
+        candidate.starts_with(RSDP_SIGNATURE) && checksum_ok(candidate)
+    })
+}
+
+fn find_rsdp() -> Option<u64> {
+    let ebda = u64::from(read_u16(unsafe { physical(EBDA_SEGMENT_POINTER, 2) }, 0)) << 4;
+    if ebda != 0 {
+        if let Some(rsdp) = scan_for_rsdp(ebda..ebda + 1024) {
+            return Some(rsdp);
+        }
+    }
+    scan_for_rsdp(BIOS_AREA)
+}
+
+/// # Safety
+/// The system description table at `addr`, checksum verified.
+///
+/// Unsafe because `addr` must point to a table header.
+unsafe fn table(addr: u64) -> Result<&'static [u8], AcpiError> {
+    let length = read_u32(physical(addr, SDT_HEADER_SIZE), 4) as usize;
+    let table = physical(addr, length);
+    if checksum_ok(table) {
+        Ok(table)
+    } else {
+        Err(AcpiError::BadChecksum)
+    }
+}
+
+/// Finds the table with `signature` through the RSDT or XSDT.
+fn find_table(signature: &[u8; 4]) -> Result<&'static [u8], AcpiError> {
+    let rsdp_addr = find_rsdp().ok_or(AcpiError::NoRsdp)?;
+    let rsdp = unsafe { physical(rsdp_addr, 36) };
+    let revision = rsdp[15];
+    let (root, pointer_size) = if revision >= 2 {
+        (read_u64(rsdp, 24), 8)
+    } else {
+        (u64::from(read_u32(rsdp, 16)), 4)
+    };
+
+    let root = unsafe { table(root)? };
+    for offset in (SDT_HEADER_SIZE..root.len()).step_by(pointer_size) {
+        let addr = if pointer_size == 8 {
+            read_u64(root, offset)
+        } else {
+            u64::from(read_u32(root, offset))
+        };
+        if unsafe { physical(addr, 4) } == signature {
+            return unsafe { table(addr) };
+        }
+    }
+    Err(AcpiError::TableNotFound)
+}
+
+/// Parses a complete MADT, header included.
+fn parse_madt(table: &[u8]) -> Madt {
+    let mut local_apic_address = u64::from(read_u32(table, SDT_HEADER_SIZE));
+    let mut cpus = Vec::new();
+
+    let mut offset = SDT_HEADER_SIZE + 8;
+    while offset + 2 <= table.len() {
+        let (kind, length) = (table[offset], usize::from(table[offset + 1]));
+        if length < 2 || offset + length > table.len() {
+            break;
+        }
+        let entry = &table[offset..offset + length];
+        match kind {
+            MADT_LOCAL_APIC if length >= 8 => {
+                let flags = read_u32(entry, 4);
+                if flags & (LOCAL_APIC_ENABLED | LOCAL_APIC_ONLINE_CAPABLE) != 0 {
+                    cpus.push(Cpu {
+                        acpi_id: entry[2],
+                        apic_id: entry[3],
+                        enabled: flags & LOCAL_APIC_ENABLED != 0,
+                    });
+                }
+            }
+            MADT_LOCAL_APIC_ADDRESS_OVERRIDE if length >= 12 => {
+                local_apic_address = read_u64(entry, 4);
+            }
+            _ => {}
+        }
+        offset += length;
+    }
+
+    Madt {
+        local_apic_address: PhysAddr::new(local_apic_address),
+        cpus,
+    }
+}
+
+/// Reads the CPU list from the MADT.
+///
+/// Must be called after `memory::install`.
+pub fn madt() -> Result<Madt, AcpiError> {
+    let table = find_table(MADT_SIGNATURE)?;
+    Ok(parse_madt(table))
+}
+
+#[test_case]
+fn test_checksum() {
+    assert!(checksum_ok(&[0x10, 0xf0]));
+    assert!(checksum_ok(&[]));
+    assert!(!checksum_ok(&[0x10, 0xef]));
+}
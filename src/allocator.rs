@@ -2,6 +2,7 @@ pub mod bump;
 pub mod fixed_block_size;
 pub mod linked_list;
 
+use crate::sync::{IrqSpinLock, IrqSpinLockGuard};
 use bump::BumpAllocator;
 use core::{
     alloc::{GlobalAlloc, Layout},
@@ -16,21 +17,23 @@ use x86_64::{
     VirtAddr,
 };
 
-/// Wrapper around spin::Mutex to permit trait implementations.
+/// Wrapper around `IrqSpinLock` to permit trait implementations.
 ///
-/// Enables synchronized interior mutability over `A`.
+/// Enables synchronized interior mutability over `A`. Interrupts stay disabled
+/// while the lock is held, so neither an interrupt handler that allocates nor a
+/// thread preempted in the middle of an allocation can deadlock the heap.
 pub struct Locked<A> {
-    inner: spin::Mutex<A>,
+    inner: IrqSpinLock<A>,
 }
 
 impl<A> Locked<A> {
     pub const fn new(inner: A) -> Self {
         Locked {
-            inner: spin::Mutex::new(inner),
+            inner: IrqSpinLock::new(inner),
         }
     }
 
-    pub fn lock(&self) -> spin::MutexGuard<A> {
+    pub fn lock(&self) -> IrqSpinLockGuard<A> {
         self.inner.lock()
     }
 }
@@ -0,0 +1,119 @@
+use crate::memory;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{structures::paging::PhysFrame, PhysAddr};
+
+// LOCAL APIC:
+//
+// Every CPU has a local APIC, its registers appear at the same physical address
+// on each CPU but address that CPU's own APIC. Device interrupts still go
+// through the 8259 PICs to the bootstrap processor, the local APIC is only used
+// to send inter-processor interrupts (IPIs) for now.
+
+const REG_ID: u64 = 0x20;
+const REG_EOI: u64 = 0xb0;
+const REG_SPURIOUS: u64 = 0xf0;
+const REG_ERROR_STATUS: u64 = 0x280;
+const REG_ICR_LOW: u64 = 0x300;
+const REG_ICR_HIGH: u64 = 0x310;
+
+/// Spurious interrupt register: software enable
+const SPURIOUS_APIC_ENABLED: u32 = 1 << 8;
+/// Vector the APIC raises for spurious interrupts, needs a handler that sends no EOI
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// Interrupt command register fields
+const ICR_DELIVERY_FIXED: u32 = 0b000 << 8;
+const ICR_DELIVERY_NMI: u32 = 0b100 << 8;
+const ICR_DELIVERY_INIT: u32 = 0b101 << 8;
+const ICR_DELIVERY_STARTUP: u32 = 0b110 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+
+/// Virtual address of the register page, 0 until `init`
+static BASE: AtomicU64 = AtomicU64::new(0);
+
+fn register(offset: u64) -> *mut u32 {
+    let base = BASE.load(Ordering::Relaxed);
+    assert_ne!(base, 0, "local APIC not initialized");
+    (base + offset) as *mut u32
+}
+
+fn read(offset: u64) -> u32 {
+    unsafe { register(offset).read_volatile() }
+}
+
+fn write(offset: u64, value: u32) {
+    unsafe { register(offset).write_volatile(value) }
+}
+
+/// Maps the local APIC registers at `addr` (from the MADT) and enables the
+/// APIC of the calling CPU.
+///
+/// Must be called once, on the bootstrap processor after `memory::install`.
+pub fn init(addr: PhysAddr) {
+    let base = memory::map_mmio(addr, 4096).expect("mapping the local APIC failed");
+    BASE.store(base.as_u64(), Ordering::Relaxed);
+    enable();
+}
+
+pub fn is_initialized() -> bool {
+    BASE.load(Ordering::Relaxed) != 0
+}
+
+/// Enables the local APIC of the calling CPU, called by every CPU once.
+pub fn enable() {
+    write(
+        REG_SPURIOUS,
+        SPURIOUS_APIC_ENABLED | u32::from(SPURIOUS_VECTOR),
+    );
+}
+
+/// APIC id of the calling CPU
+pub fn id() -> u8 {
+    (read(REG_ID) >> 24) as u8
+}
+
+/// Signals the end of an interrupt delivered by the local APIC.
+pub fn end_of_interrupt() {
+    write(REG_EOI, 0);
+}
+
+/// Sends an IPI and waits until the APIC accepted it.
+fn send_ipi(apic_id: u8, command: u32) {
+    write(REG_ERROR_STATUS, 0);
+    write(REG_ICR_HIGH, u32::from(apic_id) << 24);
+    // writing the low half sends the IPI
+    write(REG_ICR_LOW, command);
+    while read(REG_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Raises interrupt `vector` on the CPU `apic_id`.
+pub fn send_interrupt(apic_id: u8, vector: u8) {
+    send_ipi(
+        apic_id,
+        ICR_DELIVERY_FIXED | ICR_LEVEL_ASSERT | u32::from(vector),
+    );
+}
+
+/// Raises a non-maskable interrupt on the CPU `apic_id`.
+pub fn send_nmi(apic_id: u8) {
+    send_ipi(apic_id, ICR_DELIVERY_NMI | ICR_LEVEL_ASSERT);
+}
+
+/// Resets the CPU `apic_id` into its wait-for-startup state.
+pub fn send_init(apic_id: u8) {
+    send_ipi(apic_id, ICR_DELIVERY_INIT | ICR_LEVEL_ASSERT);
+}
+
+/// Starts the CPU `apic_id` in real mode at the beginning of `frame`, which
+/// must lie below 1 MiB.
+pub fn send_startup(apic_id: u8, frame: PhysFrame) {
+    let vector = frame.start_address().as_u64() >> 12;
+    assert!(vector <= 0xff, "startup code above 1 MiB");
+    send_ipi(
+        apic_id,
+        ICR_DELIVERY_STARTUP | ICR_LEVEL_ASSERT | vector as u32,
+    );
+}
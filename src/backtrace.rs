@@ -0,0 +1,96 @@
+use core::{arch::asm, fmt};
+
+// FRAME POINTER CHAIN:
+//
+// With frame pointers enabled (`"frame-pointer": "always"` in the target spec),
+// every function starts with `push rbp; mov rbp, rsp`, so `rbp` points to:
+//
+// [rbp + 8] → return address into the caller
+// [rbp]     → caller's saved rbp → caller's frame
+//
+// An interrupt handler saves the interrupted `rbp` the same way, but handlers
+// with an IST entry (NMI, double fault, ...) run on a stack of their own, where
+// the walk ends at the first frame pointing to another stack. Reports about
+// interrupted code therefore start from the interrupted `rip` and `rbp`, see
+// `interrupted_rbp` and `print_from`.
+
+/// Upper bound on the number of frames walked, guards against corrupted chains
+pub const MAX_FRAMES: usize = 32;
+/// Largest accepted distance between two frames
+const MAX_FRAME_SIZE: u64 = 1024 * 1024;
+
+/// Calls `f` with the return address of every frame, starting with the frame of
+/// the function calling `walk`.
+#[inline(always)]
+pub fn walk(f: impl FnMut(u64)) {
+    walk_from(current_rbp(), f);
+}
+
+/// Calls `f` with the return address of every frame of the chain starting at
+/// the frame `rbp` points to.
+pub fn walk_from(mut rbp: u64, mut f: impl FnMut(u64)) {
+    for _ in 0..MAX_FRAMES {
+        if rbp == 0 || !rbp.is_multiple_of(8) {
+            break;
+        }
+        // Safety: rbp was checked to be non-null and aligned, and each step must
+        // move up the stack by a bounded amount, so a sane chain stays on the stack
+        let (next, return_address) = unsafe {
+            let frame = rbp as *const u64;
+            (frame.read(), frame.add(1).read())
+        };
+        if return_address == 0 {
+            break;
+        }
+        f(return_address);
+        if next <= rbp || next - rbp > MAX_FRAME_SIZE {
+            break;
+        }
+        rbp = next;
+    }
+}
+
+#[inline(always)]
+fn current_rbp() -> u64 {
+    let rbp: u64;
+    unsafe {
+        asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+    }
+    rbp
+}
+
+/// Frame pointer of the interrupted code, must be called directly in an
+/// `extern "x86-interrupt"` handler.
+///
+/// The handler's prologue pushed the interrupted `rbp`, which is where the
+/// handler's own `rbp` points to.
+#[inline(always)]
+pub fn interrupted_rbp() -> u64 {
+    // Safety: with frame pointers, rbp of the handler points to the saved rbp
+    unsafe { (current_rbp() as *const u64).read() }
+}
+
+/// Writes the return addresses of the current call chain, one per line.
+///
+/// Resolve them with `addr2line -e target/x86_64-atlas/debug/atlas <address>`.
+#[inline(always)]
+pub fn print(writer: &mut impl fmt::Write) {
+    let _ = writeln!(writer, "backtrace:");
+    let mut depth = 0;
+    walk(|return_address| {
+        let _ = writeln!(writer, "  {:>2}: {:#018x}", depth, return_address);
+        depth += 1;
+    });
+}
+
+/// Like `print`, but for the code interrupted at `rip` with the frame pointer
+/// `rbp`, which is listed as frame 0.
+pub fn print_from(writer: &mut impl fmt::Write, rip: u64, rbp: u64) {
+    let _ = writeln!(writer, "backtrace:");
+    let _ = writeln!(writer, "  {:>2}: {:#018x}", 0, rip);
+    let mut depth = 1;
+    walk_from(rbp, |return_address| {
+        let _ = writeln!(writer, "  {:>2}: {:#018x}", depth, return_address);
+        depth += 1;
+    });
+}
@@ -0,0 +1,166 @@
+use core::{
+    arch::{asm, x86_64::__cpuid},
+    mem::offset_of,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use x86_64::{
+    instructions::{interrupts, segmentation::GS},
+    registers::model_specific::{GsBase, KernelGsBase},
+    structures::idt::InterruptStackFrame,
+    PrivilegeLevel, VirtAddr,
+};
+
+/// Upper bound on the number of CPUs tracked by per-CPU tables
+pub const MAX_CPUS: usize = 16;
+
+// CPU AREA:
+//
+// Every CPU's GS base points at its own `CpuArea` while running kernel code, so
+// the current CPU is found with a single `gs:`-relative load instead of a
+// `CPUID`. User programs may load GS, so while they run the GS base is theirs
+// and KERNEL_GS_BASE holds the `CpuArea` pointer:
+//
+// ring 3 ──syscall/interrupt, swapgs──> ring 0 ──swapgs, sysret/iret──> ring 3
+//
+// `syscall_entry` and `run_user` swap themselves, interrupt handlers start with
+// a `KernelGs` guard. The user's GS base is kept per thread, see `UserState`.
+
+/// Data at the start of the GS segment, one per CPU.
+///
+/// Entry code that can't call into Rust uses the slots through `gs:`-relative
+/// offsets, see `SYSCALL_STACK_OFFSET` and the others.
+#[repr(C)]
+pub(crate) struct CpuArea {
+    /// Index into per-CPU tables, at offset 0
+    id: usize,
+    /// Stack `syscall_entry` switches to
+    pub(crate) syscall_stack: AtomicU64,
+    /// Scratch slot for the user stack pointer until `syscall_entry` pushed it
+    pub(crate) user_stack: AtomicU64,
+    /// Kernel stack pointer saved by `userspace::run_user`, 0 while no user
+    /// program runs
+    pub(crate) return_stack: AtomicU64,
+}
+
+pub(crate) const SYSCALL_STACK_OFFSET: usize = offset_of!(CpuArea, syscall_stack);
+pub(crate) const USER_STACK_OFFSET: usize = offset_of!(CpuArea, user_stack);
+pub(crate) const RETURN_STACK_OFFSET: usize = offset_of!(CpuArea, return_stack);
+
+static AREAS: [CpuArea; MAX_CPUS] = {
+    let mut areas = [const {
+        CpuArea {
+            id: 0,
+            syscall_stack: AtomicU64::new(0),
+            user_stack: AtomicU64::new(0),
+            return_stack: AtomicU64::new(0),
+        }
+    }; MAX_CPUS];
+    let mut id = 0;
+    while id < MAX_CPUS {
+        areas[id].id = id;
+        id += 1;
+    }
+    areas
+};
+
+/// Set once the bootstrap processor ran `init`; application processors run
+/// `init` before anything else, so from then on every CPU has a valid GS base.
+static GS_READY: AtomicBool = AtomicBool::new(false);
+
+/// Initial local APIC id of the executing CPU, reported by `CPUID` leaf 1.
+///
+/// Firmware numbers the CPUs from 0, so the id can be used to index per-CPU tables
+/// of size `MAX_CPUS`.
+pub fn apic_id() -> usize {
+    let leaf = __cpuid(1);
+    (leaf.ebx >> 24) as usize
+}
+
+/// Points GS of the calling CPU at its `CpuArea`, user programs start with a
+/// GS base of 0.
+///
+/// Called by every CPU once, before any per-CPU data is accessed.
+pub fn init() {
+    let id = apic_id();
+    assert!(id < MAX_CPUS, "APIC id {} exceeds MAX_CPUS", id);
+    GsBase::write(VirtAddr::from_ptr(&AREAS[id]));
+    KernelGsBase::write(VirtAddr::zero());
+    GS_READY.store(true, Ordering::Release);
+}
+
+/// `CpuArea` of the calling CPU
+pub(crate) fn area() -> &'static CpuArea {
+    &AREAS[current_id()]
+}
+
+/// Whether `base` points at a `CpuArea`, i.e. is the kernel's GS base
+fn is_kernel_gs_base(base: VirtAddr) -> bool {
+    let start = VirtAddr::from_ptr(&AREAS);
+    (start..start + size_of_val(&AREAS) as u64).contains(&base)
+}
+
+/// Loads the kernel's GS base for an interrupt handler, the interrupted GS
+/// base is restored when the guard is dropped.
+///
+/// Must be the first thing a handler does, before anything uses per-CPU data.
+pub struct KernelGs {
+    swapped: bool,
+}
+
+impl KernelGs {
+    /// Runs `swapgs` if the interrupt arrived in ring 3
+    #[inline(always)]
+    pub fn enter(stack_frame: &InterruptStackFrame) -> KernelGs {
+        let swapped = stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3;
+        if swapped {
+            unsafe { GS::swap() };
+        }
+        KernelGs { swapped }
+    }
+
+    /// Like `enter`, for the NMI, machine check, debug and double fault handlers.
+    ///
+    /// These also interrupt the kernel right after a `syscall` and right before
+    /// a `sysretq`, where GS still holds the user's base, so the GS base itself
+    /// is checked.
+    #[inline(always)]
+    pub fn enter_paranoid() -> KernelGs {
+        let swapped = GS_READY.load(Ordering::Relaxed) && !is_kernel_gs_base(GsBase::read());
+        if swapped {
+            unsafe { GS::swap() };
+        }
+        KernelGs { swapped }
+    }
+}
+
+impl Drop for KernelGs {
+    #[inline(always)]
+    fn drop(&mut self) {
+        if self.swapped {
+            // the handler may have enabled interrupts, none may see the user's GS
+            interrupts::disable();
+            unsafe { GS::swap() };
+        }
+    }
+}
+
+/// Id of the executing CPU, equal to its initial APIC id.
+///
+/// Can be used to index per-CPU tables of size `MAX_CPUS`, see `percpu!`.
+pub fn current_id() -> usize {
+    if !GS_READY.load(Ordering::Relaxed) {
+        return apic_id();
+    }
+    let id: usize;
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) id, options(nostack, preserves_flags, readonly));
+    }
+    id
+}
+
+#[test_case]
+fn test_current_id() {
+    assert_eq!(current_id(), apic_id());
+    assert!(is_kernel_gs_base(GsBase::read()));
+    assert!(!is_kernel_gs_base(VirtAddr::zero()));
+}
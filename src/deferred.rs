@@ -0,0 +1,171 @@
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crossbeam_queue::ArrayQueue;
+
+// DEFERRED WORK ("bottom halves"):
+//
+// Interrupt handlers run with interrupts disabled and must not block or allocate,
+// so anything slow (printing, parsing, waking up many tasks) is split off into a
+// `Work` item. The handler pushes the item onto a lock-free `WorkQueue` and returns,
+// and the executor runs the queued items from its main loop with interrupts enabled.
+
+/// A unit of work scheduled from interrupt context.
+///
+/// Plain function pointer plus a word of context, so creating and queueing one
+/// never allocates.
+#[derive(Clone, Copy)]
+pub struct Work {
+    func: fn(usize),
+    arg: usize,
+}
+
+impl Work {
+    pub const fn new(func: fn(usize), arg: usize) -> Self {
+        Work { func, arg }
+    }
+
+    fn run(self) {
+        (self.func)(self.arg)
+    }
+}
+
+/// Counters of a `WorkQueue`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkQueueStats {
+    /// Items successfully queued
+    pub scheduled: u64,
+    /// Items that have been run
+    pub completed: u64,
+    /// Items dropped because the queue was full or not initialized yet
+    pub overflows: u64,
+}
+
+/// Bounded lock-free queue of deferred work items
+pub struct WorkQueue {
+    name: &'static str,
+    capacity: usize,
+    /// Use `OnceCell` so the queue is allocated in `init` and never in an interrupt handler
+    queue: OnceCell<ArrayQueue<Work>>,
+    scheduled: AtomicU64,
+    completed: AtomicU64,
+    overflows: AtomicU64,
+}
+
+impl WorkQueue {
+    pub const fn new(name: &'static str, capacity: usize) -> Self {
+        WorkQueue {
+            name,
+            capacity,
+            queue: OnceCell::uninit(),
+            scheduled: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            overflows: AtomicU64::new(0),
+        }
+    }
+
+    /// Allocates the queue storage, requires the heap to be initialized.
+    pub fn init(&self) {
+        self.queue
+            .try_init_once(|| ArrayQueue::new(self.capacity))
+            .expect("WorkQueue::init should only be called once");
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Queues `work` to be run later with interrupts enabled.
+    ///
+    /// Safe to call from interrupt handlers: never blocks or allocates. If the
+    /// queue is full or uninitialized the work is dropped, counted as an
+    /// overflow and handed back to the caller.
+    pub fn schedule(&self, work: Work) -> Result<(), Work> {
+        let result = match self.queue.try_get() {
+            Ok(queue) => queue.push(work),
+            Err(_) => Err(work),
+        };
+        match result {
+            Ok(()) => {
+                self.scheduled.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.overflows.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        result
+    }
+
+    /// Runs all currently queued items and returns how many were run.
+    ///
+    /// Items scheduled while draining are run as well, so this only returns
+    /// once the queue was observed empty.
+    pub fn run_pending(&self) -> usize {
+        let queue = match self.queue.try_get() {
+            Ok(queue) => queue,
+            Err(_) => return 0,
+        };
+        let mut count = 0;
+        while let Some(work) = queue.pop() {
+            work.run();
+            self.completed.fetch_add(1, Ordering::Relaxed);
+            count += 1;
+        }
+        count
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.queue.try_get().is_ok_and(|queue| !queue.is_empty())
+    }
+
+    /// Whether `init` allocated the queue, work scheduled before is rejected
+    pub fn is_initialized(&self) -> bool {
+        self.queue.is_initialized()
+    }
+
+    pub fn stats(&self) -> WorkQueueStats {
+        WorkQueueStats {
+            scheduled: self.scheduled.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            overflows: self.overflows.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Default queue for work deferred by device interrupt handlers
+pub static SYSTEM_QUEUE: WorkQueue = WorkQueue::new("system", 100);
+
+/// All queues drained by `run_pending`
+static QUEUES: &[&WorkQueue] = &[&SYSTEM_QUEUE];
+
+/// Allocates the storage of all queues, requires the heap to be initialized.
+pub fn init() {
+    for queue in QUEUES {
+        queue.init();
+    }
+}
+
+/// Schedules `work` on the `SYSTEM_QUEUE`.
+///
+/// Safe to call from interrupt handlers, see `WorkQueue::schedule`.
+pub fn schedule(work: Work) -> Result<(), Work> {
+    SYSTEM_QUEUE.schedule(work)
+}
+
+/// Whether `init` ran
+pub fn is_initialized() -> bool {
+    SYSTEM_QUEUE.is_initialized()
+}
+
+/// Runs the pending work of all queues, must be called with interrupts enabled.
+pub fn run_pending() -> usize {
+    QUEUES.iter().map(|queue| queue.run_pending()).sum()
+}
+
+pub fn has_pending() -> bool {
+    QUEUES.iter().any(|queue| queue.has_pending())
+}
+
+/// Per-queue counters, in the order the queues are drained
+pub fn stats() -> impl Iterator<Item = (&'static str, WorkQueueStats)> {
+    QUEUES.iter().map(|queue| (queue.name(), queue.stats()))
+}
@@ -0,0 +1,218 @@
+// ELF64 EXECUTABLES:
+//
+// Only what is needed to load a statically linked x86_64 executable is parsed:
+// the file header and the program header table. Sections and symbols are ignored.
+//
+// ELF File
+// ├── File Header (64 bytes): magic, class, entry point, program header table location
+// ├── Program Header Table: one 56 byte entry per segment
+// └── Segment Data: PT_LOAD segments are copied to `vaddr`, zero-filled up to `mem_size`
+
+const MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_X86_64: u16 = 0x3e;
+
+const FILE_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// Segment that is loaded into memory
+pub const PT_LOAD: u32 = 1;
+/// Location of the program header table itself
+pub const PT_PHDR: u32 = 6;
+
+/// Segment permission flags
+pub const PF_X: u32 = 1 << 0;
+pub const PF_W: u32 = 1 << 1;
+pub const PF_R: u32 = 1 << 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// File ends inside a header or segment
+    Truncated,
+    BadMagic,
+    /// Not a 64-bit little-endian file
+    UnsupportedFormat,
+    /// Not built for x86_64
+    UnsupportedMachine,
+    /// Not an `ET_EXEC` file (relocatable, shared object, core dump)
+    NotExecutable,
+    /// Program header with `file_size > mem_size` or an unsupported entry size
+    BadProgramHeader,
+}
+
+/// Entry of the program header table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub file_size: u64,
+    pub mem_size: u64,
+    pub align: u64,
+}
+
+/// A validated ELF64 executable borrowed from its file contents
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    entry: u64,
+    ph_offset: u64,
+    ph_entry_size: u16,
+    ph_count: u16,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Returns `data[offset..offset + len]` if it lies inside `data`.
+fn range(data: &[u8], offset: u64, len: u64) -> Result<&[u8], ElfError> {
+    let end = offset.checked_add(len).ok_or(ElfError::Truncated)?;
+    data.get(offset as usize..end as usize)
+        .ok_or(ElfError::Truncated)
+}
+
+impl<'a> ElfFile<'a> {
+    /// Validates the file header and every program header.
+    pub fn parse(data: &'a [u8]) -> Result<ElfFile<'a>, ElfError> {
+        if data.len() < FILE_HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if data[0..4] != MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if data[4] != CLASS_64 || data[5] != DATA_LITTLE_ENDIAN {
+            return Err(ElfError::UnsupportedFormat);
+        }
+        if read_u16(data, 16) != TYPE_EXECUTABLE {
+            return Err(ElfError::NotExecutable);
+        }
+        if read_u16(data, 18) != MACHINE_X86_64 {
+            return Err(ElfError::UnsupportedMachine);
+        }
+
+        let file = ElfFile {
+            data,
+            entry: read_u64(data, 24),
+            ph_offset: read_u64(data, 32),
+            ph_entry_size: read_u16(data, 54),
+            ph_count: read_u16(data, 56),
+        };
+        if (file.ph_entry_size as usize) < PROGRAM_HEADER_SIZE {
+            return Err(ElfError::BadProgramHeader);
+        }
+        range(
+            data,
+            file.ph_offset,
+            u64::from(file.ph_entry_size) * u64::from(file.ph_count),
+        )?;
+        for header in file.program_headers() {
+            if header.file_size > header.mem_size {
+                return Err(ElfError::BadProgramHeader);
+            }
+            if header.kind == PT_LOAD {
+                range(data, header.offset, header.file_size)?;
+            }
+        }
+        Ok(file)
+    }
+
+    /// Virtual address execution starts at
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    pub fn program_header_count(&self) -> u16 {
+        self.ph_count
+    }
+
+    pub fn program_header_size(&self) -> u16 {
+        self.ph_entry_size
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        (0..self.ph_count as usize).map(move |index| {
+            let start = self.ph_offset as usize + index * self.ph_entry_size as usize;
+            let header = &self.data[start..start + PROGRAM_HEADER_SIZE];
+            ProgramHeader {
+                kind: read_u32(header, 0),
+                flags: read_u32(header, 4),
+                offset: read_u64(header, 8),
+                vaddr: read_u64(header, 16),
+                file_size: read_u64(header, 32),
+                mem_size: read_u64(header, 40),
+                align: read_u64(header, 48),
+            }
+        })
+    }
+
+    /// Bytes of `header` stored in the file, `file_size` long.
+    pub fn segment_data(&self, header: &ProgramHeader) -> &'a [u8] {
+        // checked in `parse` for PT_LOAD segments
+        range(self.data, header.offset, header.file_size).unwrap_or(&[])
+    }
+
+    /// Virtual address of the program header table once loaded, if it is part
+    /// of a PT_LOAD segment. Passed to the program as `AT_PHDR`.
+    pub fn program_header_address(&self) -> Option<u64> {
+        let mut headers = self.program_headers();
+        if let Some(phdr) = headers.find(|header| header.kind == PT_PHDR) {
+            return Some(phdr.vaddr);
+        }
+        self.program_headers()
+            .filter(|header| header.kind == PT_LOAD)
+            .find(|header| {
+                self.ph_offset >= header.offset && self.ph_offset - header.offset < header.file_size
+            })
+            .map(|header| header.vaddr + (self.ph_offset - header.offset))
+    }
+}
+
+#[test_case]
+fn test_parse_executable() {
+    let mut image = [0u8; FILE_HEADER_SIZE + PROGRAM_HEADER_SIZE + 4];
+    image[0..4].copy_from_slice(&MAGIC);
+    image[4] = CLASS_64;
+    image[5] = DATA_LITTLE_ENDIAN;
+    image[6] = 1;
+    image[16..18].copy_from_slice(&TYPE_EXECUTABLE.to_le_bytes());
+    image[18..20].copy_from_slice(&MACHINE_X86_64.to_le_bytes());
+    image[24..32].copy_from_slice(&0x1000_0078u64.to_le_bytes());
+    image[32..40].copy_from_slice(&(FILE_HEADER_SIZE as u64).to_le_bytes());
+    image[54..56].copy_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    image[56..58].copy_from_slice(&1u16.to_le_bytes());
+    let header = &mut image[FILE_HEADER_SIZE..];
+    header[0..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+    header[4..8].copy_from_slice(&(PF_R | PF_X).to_le_bytes());
+    header[16..24].copy_from_slice(&0x1000_0000u64.to_le_bytes());
+    header[32..40].copy_from_slice(&124u64.to_le_bytes());
+    header[40..48].copy_from_slice(&0x2000u64.to_le_bytes());
+
+    let file = ElfFile::parse(&image).expect("valid executable rejected");
+    assert_eq!(file.entry(), 0x1000_0078);
+    let segment = file.program_headers().next().unwrap();
+    assert_eq!(segment.kind, PT_LOAD);
+    assert_eq!(segment.flags, PF_R | PF_X);
+    assert_eq!(file.segment_data(&segment).len(), 124);
+    assert_eq!(file.program_header_address(), Some(0x1000_0040));
+
+    // segment data past the end of the file
+    image[FILE_HEADER_SIZE + 32..FILE_HEADER_SIZE + 40].copy_from_slice(&125u64.to_le_bytes());
+    assert_eq!(ElfFile::parse(&image).err(), Some(ElfError::Truncated));
+    image[0] = 0;
+    assert_eq!(ElfFile::parse(&image).err(), Some(ElfError::BadMagic));
+}
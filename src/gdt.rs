@@ -1,55 +1,195 @@
+use crate::{memory::stack, percpu};
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
 use lazy_static::lazy_static;
+use x86_64::instructions::interrupts;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+pub const DEBUG_IST_INDEX: u16 = 3;
+pub const PAGE_FAULT_IST_INDEX: u16 = 4;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-
-            // stack_end
-            stack_start + STACK_SIZE.try_into().unwrap()
-        };
-        tss
-    };
+/// Size of the IST stacks, excluding the guard page
+pub const IST_STACK_PAGES: u64 = 5;
+
+/// A TSS the CPU references while the kernel writes its stack pointers
+struct TssCell(UnsafeCell<TaskStateSegment>);
+
+// Safety: only the CPU owning the slot writes it, with interrupts disabled
+unsafe impl Sync for TssCell {}
+
+percpu! {
+    /// TSS of each CPU, referenced by the CPU's GDT.
+    ///
+    /// Only accessed through raw pointers, because the CPU reads it behind the
+    /// kernel's back when an interrupt switches stacks.
+    static TSS: TssCell = TssCell(UnsafeCell::new(TaskStateSegment::new()));
+}
+
+/// Returns the end of the double fault stack.
+///
+/// Unlike the other IST stacks it is a static array: a double fault must be
+/// reportable at any point, including before memory management is set up.
+fn double_fault_stack() -> VirtAddr {
+    const STACK_SIZE: usize = 4096 * 5;
+    static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+    let stack_start = VirtAddr::from_ptr(&raw const STACK);
+
+    // stack_end
+    stack_start + STACK_SIZE.try_into().unwrap()
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+/// Segment selectors of the GDT entries.
+///
+/// The order of the entries is dictated by `SYSCALL`/`SYSRET`: kernel data must
+/// directly follow kernel code, and user data must directly precede user code.
+pub struct Selectors {
+    pub code_selector: SegmentSelector,
+    pub data_selector: SegmentSelector,
+    pub user_data_selector: SegmentSelector,
+    pub user_code_selector: SegmentSelector,
+    pub tss_selector: SegmentSelector,
 }
 
 lazy_static! {
+    /// GDT of the bootstrap processor, first accessed by its `init`
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.append(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
-        (
-            gdt,
-            Selectors {
-                code_selector,
-                tss_selector,
-            },
-        )
+        // Safety: TSS is a static, so the pointer stays valid
+        let selectors = unsafe { build_gdt(&mut gdt, current_tss()) };
+        (gdt, selectors)
     };
 }
 
-pub fn init() {
-    use x86_64::instructions::segmentation::{Segment, CS};
+/// Appends the descriptors in the order `Selectors` documents, so every CPU's
+/// GDT yields the same selectors.
+///
+/// # Safety
+/// `tss` must stay valid for as long as the GDT is loaded on any CPU.
+unsafe fn build_gdt(gdt: &mut GlobalDescriptorTable, tss: *const TaskStateSegment) -> Selectors {
+    let code_selector = gdt.append(Descriptor::kernel_code_segment());
+    let data_selector = gdt.append(Descriptor::kernel_data_segment());
+    let user_data_selector = gdt.append(Descriptor::user_data_segment());
+    let user_code_selector = gdt.append(Descriptor::user_code_segment());
+    let tss_selector = gdt.append(unsafe { Descriptor::tss_segment_unchecked(tss) });
+    Selectors {
+        code_selector,
+        data_selector,
+        user_data_selector,
+        user_code_selector,
+        tss_selector,
+    }
+}
+
+/// Loads `gdt` and its TSS on the calling CPU.
+fn load(gdt: &'static GlobalDescriptorTable, selectors: &Selectors) {
+    use x86_64::instructions::segmentation::{Segment, CS, SS};
     use x86_64::instructions::tables::load_tss;
 
-    GDT.0.load();
+    gdt.load();
     unsafe {
         // reload 'cs' register to avoid old segment selector pointing to a different GDT descriptor
-        CS::set_reg(GDT.1.code_selector);
+        CS::set_reg(selectors.code_selector);
+        SS::set_reg(selectors.data_selector);
         // GDT loaded with a TSS selector, but CPU still needs to be informed to use it
-        load_tss(GDT.1.tss_selector);
+        load_tss(selectors.tss_selector);
+    }
+}
+
+pub fn init() {
+    // until `init_ist_stacks` runs, all IST handlers share the double fault stack
+    let double_fault_stack = double_fault_stack();
+    unsafe {
+        (*current_tss()).interrupt_stack_table = [double_fault_stack; 7];
     }
+
+    load(&GDT.0, &GDT.1);
+}
+
+/// Allocates a guard-paged IST stack and returns its end.
+///
+/// Panics if that fails: a CPU can't take faults safely without its IST stacks,
+/// so there is no point in giving back the stacks allocated before.
+fn alloc_ist_stack() -> VirtAddr {
+    stack::alloc_stack(IST_STACK_PAGES)
+        .expect("IST stack allocation failed")
+        .end()
+}
+
+/// Fills the application processor's TSS with guard-paged IST stacks, gives it
+/// its own GDT referencing the TSS, then loads both.
+///
+/// Called by each AP during startup, after `memory::install`.
+pub fn init_ap() {
+    let tss = current_tss();
+    for index in [
+        DOUBLE_FAULT_IST_INDEX,
+        NMI_IST_INDEX,
+        MACHINE_CHECK_IST_INDEX,
+        DEBUG_IST_INDEX,
+        PAGE_FAULT_IST_INDEX,
+    ] {
+        let stack = alloc_ist_stack();
+        unsafe { (*tss).interrupt_stack_table[usize::from(index)] = stack };
+    }
+
+    // CPUs never go offline, so the GDT lives forever
+    let gdt: &'static mut GlobalDescriptorTable = Box::leak(Box::new(GlobalDescriptorTable::new()));
+    // Safety: TSS is a static, so the pointer stays valid
+    let selectors = unsafe { build_gdt(gdt, tss) };
+    assert_eq!(
+        selectors.tss_selector, GDT.1.tss_selector,
+        "AP GDT layout differs"
+    );
+    load(gdt, &selectors);
+}
+
+/// TSS of the calling CPU
+fn current_tss() -> *mut TaskStateSegment {
+    TSS.get().0.get()
+}
+
+/// Gives the NMI, machine check, debug and page fault handlers their own
+/// guard-paged stacks, so each of them still runs when the interrupted stack
+/// overflowed or another IST handler is active.
+///
+/// Must be called after `memory::install`.
+pub fn init_ist_stacks() {
+    for index in [
+        NMI_IST_INDEX,
+        MACHINE_CHECK_IST_INDEX,
+        DEBUG_IST_INDEX,
+        PAGE_FAULT_IST_INDEX,
+    ] {
+        let stack = alloc_ist_stack();
+        interrupts::without_interrupts(|| unsafe {
+            (*current_tss()).interrupt_stack_table[usize::from(index)] = stack;
+        });
+    }
+}
+
+/// End of the calling CPU's IST stack `index`, see `init_ist_stacks`
+pub fn ist_stack(index: u16) -> VirtAddr {
+    interrupts::without_interrupts(|| unsafe {
+        (*current_tss()).interrupt_stack_table[usize::from(index)]
+    })
+}
+
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
+}
+
+/// Sets the stack the calling CPU switches to when an interrupt arrives in ring 3.
+///
+/// Stored in `privilege_stack_table[0]` (RSP0) of the CPU's TSS.
+pub fn set_kernel_stack(stack_end: VirtAddr) {
+    interrupts::without_interrupts(|| unsafe {
+        let tss = current_tss();
+        (*tss).privilege_stack_table[0] = stack_end;
+    });
 }
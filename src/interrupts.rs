@@ -1,4 +1,16 @@
-use crate::{gdt, hlt_loop, print, println, task};
+pub mod stats;
+
+use crate::{
+    apic, backtrace,
+    cpu::KernelGs,
+    gdt, hlt_loop,
+    memory::{stack, tlb},
+    percpu, println, rtc,
+    sync::IrqSpinLock,
+    task::{self, executor},
+    thread, time, userspace, watchdog,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::lazy_static;
 use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 use pic8259::ChainedPics;
@@ -6,7 +18,10 @@ use spin::Mutex;
 use x86_64::{
     instructions::port::Port,
     registers::control::Cr2,
-    structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+    structures::idt::{
+        ExceptionVector, InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+    },
+    PrivilegeLevel,
 };
 
 #[derive(Debug, Clone, Copy)]
@@ -14,6 +29,11 @@ use x86_64::{
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET, // slot 32
     Keyboard,             // slot 33
+    /// Parallel port, or a spurious interrupt of the primary PIC
+    Irq7 = PIC_1_OFFSET + 7, // slot 39
+    Rtc = PIC_2_OFFSET,   // slot 40
+    /// Secondary ATA channel, or a spurious interrupt of the secondary PIC
+    Irq15 = PIC_2_OFFSET + 7, // slot 47
 }
 
 impl InterruptIndex {
@@ -24,6 +44,11 @@ impl InterruptIndex {
     fn as_usize(self) -> usize {
         usize::from(self.as_u8())
     }
+
+    /// IRQ line of the interrupt on the chained PICs
+    fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
+    }
 }
 
 // PIC = programmable interrupt controller
@@ -32,37 +57,186 @@ pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
 /// unsafe because wrong offsets could cause undefined behavior
-pub static PICS: spin::Mutex<ChainedPics> =
-    spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+pub static PICS: IrqSpinLock<ChainedPics> =
+    IrqSpinLock::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+percpu! {
+    /// Nesting depth of device interrupt handlers on each CPU
+    static IRQ_DEPTH: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Marks the CPU as running a device interrupt handler until dropped.
+///
+/// Exceptions don't count: they run in the context of the code that caused them.
+struct IrqContext;
+
+impl IrqContext {
+    fn enter(vector: u8) -> Self {
+        stats::record(vector);
+        IRQ_DEPTH.get().fetch_add(1, Ordering::Relaxed);
+        IrqContext
+    }
+}
+
+impl Drop for IrqContext {
+    fn drop(&mut self) {
+        IRQ_DEPTH.get().fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Whether the calling CPU is running a device interrupt handler
+pub fn in_interrupt() -> bool {
+    IRQ_DEPTH.get().load(Ordering::Relaxed) != 0
+}
+
+/// IRQ line of the secondary PIC on the primary PIC
+const CASCADE_IRQ: u8 = 2;
+
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_2_COMMAND: u16 = 0xA0;
+/// OCW3 command making the next read of the command port return the in-service register
+const OCW3_READ_ISR: u8 = 0x0B;
+
+/// Reads the in-service registers of both PICs, primary PIC in the low byte.
+///
+/// A bit is set for every IRQ that was delivered to the CPU and has not
+/// received an EOI yet.
+fn read_in_service() -> u16 {
+    // hold the lock so the command sequence isn't interleaved with an EOI
+    let _pics = PICS.lock();
+    let mut primary: Port<u8> = Port::new(PIC_1_COMMAND);
+    let mut secondary: Port<u8> = Port::new(PIC_2_COMMAND);
+    unsafe {
+        primary.write(OCW3_READ_ISR);
+        secondary.write(OCW3_READ_ISR);
+        u16::from(primary.read()) | u16::from(secondary.read()) << 8
+    }
+}
+
+/// Checks whether the PIC actually raised IRQ 7 or 15.
+///
+/// When an IRQ is deasserted before the CPU acknowledges it, the PIC still
+/// delivers its lowest priority line (7 of the respective PIC) but does not
+/// set the in-service bit. Such spurious interrupts must not receive an EOI.
+fn is_spurious(interrupt: InterruptIndex) -> bool {
+    read_in_service() & (1 << interrupt.irq()) == 0
+}
+
+/// Clears the mask bit of the interrupt's IRQ line so the PICs deliver it.
+///
+/// For IRQs on the secondary PIC the cascade line on the primary PIC is unmasked too.
+pub fn unmask_irq(interrupt: InterruptIndex) {
+    let irq = interrupt.irq();
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        unsafe {
+            let [mut primary, mut secondary] = pics.read_masks();
+            if irq < 8 {
+                primary &= !(1 << irq);
+            } else {
+                primary &= !(1 << CASCADE_IRQ);
+                secondary &= !(1 << (irq - 8));
+            }
+            pics.write_masks(primary, secondary);
+        }
+    });
+}
+
+/// Ends the running user program if the exception `vector` was raised in ring 3,
+/// so a faulting program doesn't stop the machine. See `userspace::fault`.
+fn kill_faulting_user_program(vector: ExceptionVector, stack_frame: &InterruptStackFrame) {
+    if stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3 {
+        userspace::fault(vector as u8);
+    }
+}
 
 /// further research on exception handling with CPU instructions
 /// can be found here:
 ///
 /// https://os.phil-opp.com/edition-1/extra/naked-exceptions/
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter(&stack_frame);
+    stats::record(ExceptionVector::Breakpoint as u8);
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
+extern "x86-interrupt" fn debug_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter_paranoid();
+    stats::record(ExceptionVector::Debug as u8);
+    println!("EXCEPTION: DEBUG\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter_paranoid();
+    stats::record(ExceptionVector::NonMaskableInterrupt as u8);
+    watchdog::handle_nmi(&stack_frame, backtrace::interrupted_rbp());
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    let _gs = KernelGs::enter_paranoid();
+    stats::record(ExceptionVector::MachineCheck as u8);
+    panic!("EXCEPTION: MACHINE CHECK\n{:#?}", stack_frame)
+}
+
+extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter(&stack_frame);
+    stats::record(ExceptionVector::Division as u8);
+    kill_faulting_user_program(ExceptionVector::Division, &stack_frame);
+    panic!("EXCEPTION: DIVIDE ERROR\n{:#?}", stack_frame)
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter(&stack_frame);
+    stats::record(ExceptionVector::InvalidOpcode as u8);
+    kill_faulting_user_program(ExceptionVector::InvalidOpcode, &stack_frame);
+    panic!("EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame)
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let _gs = KernelGs::enter(&stack_frame);
+    stats::record(ExceptionVector::GeneralProtection as u8);
+    kill_faulting_user_program(ExceptionVector::GeneralProtection, &stack_frame);
+    panic!(
+        "EXCEPTION: GENERAL PROTECTION FAULT ({:#x})\n{:#?}",
+        error_code, stack_frame
+    )
+}
+
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    let _gs = KernelGs::enter_paranoid();
+    stats::record(ExceptionVector::Double as u8);
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame)
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    print!(".");
-    // Determine if 1st or 2nd PIC setn the interrupt, then use 'command' and 'data'
-    // ports to send an 'end of interrupt' (EOI) signal to respective controllers.
-    // If the 2nd PIC sent the interrupt, both PICs need to be notified because the 2nd
-    // PIC is connected to an input line of the 1st PIC.
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter(&stack_frame);
+    {
+        let _irq = IrqContext::enter(InterruptIndex::Timer.as_u8());
+        time::tick();
+        watchdog::check(&stack_frame, backtrace::interrupted_rbp());
+        // Determine if 1st or 2nd PIC setn the interrupt, then use 'command' and 'data'
+        // ports to send an 'end of interrupt' (EOI) signal to respective controllers.
+        // If the 2nd PIC sent the interrupt, both PICs need to be notified because the 2nd
+        // PIC is connected to an input line of the 1st PIC.
+        unsafe {
+            PICS.lock()
+                .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+        }
     }
+    // may switch to another thread, so only after the EOI and outside of the
+    // interrupt context, which the next thread doesn't run in
+    thread::scheduler::tick();
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+extern "x86-interrupt" fn keyboard_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter(&stack_frame);
+    let _irq = IrqContext::enter(InterruptIndex::Keyboard.as_u8());
     lazy_static! {
         static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
             Mutex::new(Keyboard::new(
@@ -84,33 +258,139 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     }
 }
 
+extern "x86-interrupt" fn rtc_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter(&stack_frame);
+    let _irq = IrqContext::enter(InterruptIndex::Rtc.as_u8());
+    rtc::handle_interrupt();
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Rtc.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn irq7_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter(&stack_frame);
+    if is_spurious(InterruptIndex::Irq7) {
+        // the primary PIC did not set the in-service bit, so no EOI
+        stats::record_spurious(InterruptIndex::Irq7);
+        return;
+    }
+    let _irq = IrqContext::enter(InterruptIndex::Irq7.as_u8());
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Irq7.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn irq15_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter(&stack_frame);
+    if is_spurious(InterruptIndex::Irq15) {
+        // the primary PIC still sees a real interrupt on the cascade line,
+        // so only it gets an EOI
+        stats::record_spurious(InterruptIndex::Irq15);
+        unsafe {
+            PICS.lock()
+                .notify_end_of_interrupt(PIC_1_OFFSET + CASCADE_IRQ);
+        }
+        return;
+    }
+    let _irq = IrqContext::enter(InterruptIndex::Irq15.as_u8());
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Irq15.as_u8());
+    }
+}
+
 extern "x86-interrupt" fn page_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
+    let _gs = KernelGs::enter(&stack_frame);
+    stats::record(ExceptionVector::Page as u8);
+    kill_faulting_user_program(ExceptionVector::Page, &stack_frame);
+    let accessed = Cr2::read();
     println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed address: {:?}", Cr2::read());
+    if let Ok(addr) = accessed {
+        if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+            && stack::is_guard_page(addr)
+        {
+            println!("Kernel stack overflow: hit guard page");
+        }
+    }
+    println!("Accessed address: {:?}", accessed);
     println!("Error code: {:?}", error_code);
     println!("{:#?}", stack_frame);
     hlt_loop();
 }
 
+/// Raised by a local APIC when an interrupt vanished before it was delivered.
+/// Not a real interrupt, so no EOI.
+extern "x86-interrupt" fn apic_spurious_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter(&stack_frame);
+    stats::record(apic::SPURIOUS_VECTOR);
+}
+
+/// Invalidates TLB entries on behalf of another CPU, see `memory::tlb`.
+extern "x86-interrupt" fn tlb_shootdown_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter(&stack_frame);
+    let _irq = IrqContext::enter(tlb::SHOOTDOWN_VECTOR);
+    tlb::handle_request();
+    apic::end_of_interrupt();
+}
+
+/// Wakes an executor from `hlt` to run the shared tasks queued on its CPU, see
+/// `task::executor`. The executor loop does the work.
+extern "x86-interrupt" fn executor_wakeup_handler(stack_frame: InterruptStackFrame) {
+    let _gs = KernelGs::enter(&stack_frame);
+    let _irq = IrqContext::enter(executor::WAKEUP_VECTOR);
+    apic::end_of_interrupt();
+}
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
         idt.breakpoint.set_handler_fn(breakpoint_handler);
+        idt.divide_error.set_handler_fn(divide_error_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.general_protection_fault
+            .set_handler_fn(general_protection_fault_handler);
         unsafe {
+            idt.non_maskable_interrupt
+                .set_handler_fn(nmi_handler)
+                .set_stack_index(gdt::NMI_IST_INDEX);
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
         idt[InterruptIndex::Timer.as_u8()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_u8()].set_handler_fn(keyboard_interrupt_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        idt[InterruptIndex::Irq7.as_u8()].set_handler_fn(irq7_interrupt_handler);
+        idt[InterruptIndex::Rtc.as_u8()].set_handler_fn(rtc_interrupt_handler);
+        idt[InterruptIndex::Irq15.as_u8()].set_handler_fn(irq15_interrupt_handler);
+        idt[apic::SPURIOUS_VECTOR].set_handler_fn(apic_spurious_interrupt_handler);
+        idt[tlb::SHOOTDOWN_VECTOR].set_handler_fn(tlb_shootdown_handler);
+        idt[executor::WAKEUP_VECTOR].set_handler_fn(executor_wakeup_handler);
+        unsafe {
+            idt.debug
+                .set_handler_fn(debug_handler)
+                .set_stack_index(gdt::DEBUG_IST_INDEX);
+            idt.machine_check
+                .set_handler_fn(machine_check_handler)
+                .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+            // the page fault handler must not fault itself, a nested fault would
+            // reuse and clobber its IST stack
+            idt.page_fault
+                .set_handler_fn(page_fault_handler)
+                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+        }
         idt
     };
 }
 
+/// Loads the IDT on the calling CPU. All CPUs share the same table.
 pub fn init_idt() {
     IDT.load();
 }
@@ -0,0 +1,203 @@
+use crate::{apic, cpu, memory::tlb, percpu, serial_print, task::executor};
+use alloc::string::String;
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use super::{InterruptIndex, PIC_1_OFFSET};
+
+const VECTORS: usize = 256;
+
+percpu! {
+    /// Interrupt counts of each CPU, indexed by vector
+    static COUNTS: [AtomicU64; VECTORS] = [const { AtomicU64::new(0) }; VECTORS];
+}
+
+/// Spurious interrupts of the primary (IRQ 7) and secondary (IRQ 15) PIC.
+///
+/// These are not included in `COUNTS`, since no interrupt was actually raised.
+static SPURIOUS: [AtomicU64; 2] = [const { AtomicU64::new(0) }; 2];
+
+/// Called at the start of every interrupt handler.
+///
+/// Must not block or allocate.
+pub(crate) fn record(vector: u8) {
+    COUNTS.get()[usize::from(vector)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Called instead of `record` when a PIC raised a spurious interrupt
+pub(crate) fn record_spurious(interrupt: InterruptIndex) {
+    let pic = match interrupt {
+        InterruptIndex::Irq7 => 0,
+        _ => 1,
+    };
+    SPURIOUS[pic].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of times `vector` was taken on `cpu`
+pub fn count_on(cpu: usize, vector: u8) -> u64 {
+    COUNTS.get_on(cpu).map_or(0, |counts| {
+        counts[usize::from(vector)].load(Ordering::Relaxed)
+    })
+}
+
+/// Number of times `vector` was taken, summed over all CPUs
+pub fn count(vector: u8) -> u64 {
+    (0..cpu::MAX_CPUS).map(|cpu| count_on(cpu, vector)).sum()
+}
+
+/// Spurious interrupts of the primary and secondary PIC, as `[primary, secondary]`
+pub fn spurious_counts() -> [u64; 2] {
+    [
+        SPURIOUS[0].load(Ordering::Relaxed),
+        SPURIOUS[1].load(Ordering::Relaxed),
+    ]
+}
+
+/// Human readable name of an interrupt vector
+pub fn vector_name(vector: u8) -> &'static str {
+    const EXCEPTIONS: [&str; 32] = [
+        "divide error",
+        "debug",
+        "non-maskable interrupt",
+        "breakpoint",
+        "overflow",
+        "bound range exceeded",
+        "invalid opcode",
+        "device not available",
+        "double fault",
+        "coprocessor segment overrun",
+        "invalid TSS",
+        "segment not present",
+        "stack segment fault",
+        "general protection fault",
+        "page fault",
+        "reserved",
+        "x87 floating point",
+        "alignment check",
+        "machine check",
+        "SIMD floating point",
+        "virtualization",
+        "control protection",
+        "reserved",
+        "reserved",
+        "reserved",
+        "reserved",
+        "reserved",
+        "reserved",
+        "hypervisor injection",
+        "VMM communication",
+        "security",
+        "reserved",
+    ];
+
+    match vector {
+        0..=31 => EXCEPTIONS[usize::from(vector)],
+        v if v == InterruptIndex::Timer as u8 => "timer",
+        v if v == InterruptIndex::Keyboard as u8 => "keyboard",
+        v if v == InterruptIndex::Irq7 as u8 => "IRQ 7",
+        v if v == InterruptIndex::Rtc as u8 => "RTC",
+        v if v == InterruptIndex::Irq15 as u8 => "IRQ 15",
+        v if (PIC_1_OFFSET..PIC_1_OFFSET + 16).contains(&v) => "PIC",
+        apic::SPURIOUS_VECTOR => "APIC spurious",
+        tlb::SHOOTDOWN_VECTOR => "TLB shootdown",
+        executor::WAKEUP_VECTOR => "executor wakeup",
+        _ => "unknown",
+    }
+}
+
+/// Writes the counters of every vector that was taken at least once to `out`
+pub fn write_to(out: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(out, "vector  name                        total  per CPU")?;
+    for vector in 0..=u8::MAX {
+        let total = count(vector);
+        if total == 0 {
+            continue;
+        }
+        write!(
+            out,
+            "{:>6}  {:<24} {:>8} ",
+            vector,
+            vector_name(vector),
+            total
+        )?;
+        for cpu in 0..cpu::MAX_CPUS {
+            let count = count_on(cpu, vector);
+            if count != 0 {
+                write!(out, " cpu{}={}", cpu, count)?;
+            }
+        }
+        writeln!(out)?;
+    }
+    let [primary, secondary] = spurious_counts();
+    writeln!(
+        out,
+        "spurious IRQ 7: {}, spurious IRQ 15: {}",
+        primary, secondary
+    )
+}
+
+/// Prints the counters over serial
+pub fn dump() {
+    let mut table = String::new();
+    write_to(&mut table).unwrap();
+    serial_print!("{}", table);
+}
+
+#[cfg(test)]
+use x86_64::structures::idt::ExceptionVector;
+
+#[test_case]
+fn test_breakpoint_counted() {
+    let breakpoint = ExceptionVector::Breakpoint as u8;
+    let cpu = cpu::current_id();
+    let before = count_on(cpu, breakpoint);
+    x86_64::instructions::interrupts::int3();
+    assert_eq!(count_on(cpu, breakpoint), before + 1);
+    assert!(count(breakpoint) > before);
+    assert_eq!(vector_name(breakpoint), "breakpoint");
+}
+
+/// Fixed-size `fmt::Write` target: the lib tests run without a heap
+#[cfg(test)]
+struct TestBuffer {
+    bytes: [u8; 4096],
+    len: usize,
+}
+
+#[cfg(test)]
+impl fmt::Write for TestBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.bytes
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_breakpoint_listed() {
+    x86_64::instructions::interrupts::int3();
+    let mut table = TestBuffer {
+        bytes: [0; 4096],
+        len: 0,
+    };
+    write_to(&mut table).unwrap();
+    let table = core::str::from_utf8(&table.bytes[..table.len]).unwrap();
+    assert!(table
+        .lines()
+        .any(|row| row.split_whitespace().nth(1) == Some("breakpoint")));
+}
+
+#[test_case]
+fn test_timer_counted() {
+    let timer = InterruptIndex::Timer.as_u8();
+    let before = count(timer);
+    while count(timer) == before {
+        x86_64::instructions::hlt();
+    }
+}
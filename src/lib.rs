@@ -6,29 +6,53 @@
 #![reexport_test_harness_main = "test_main"]
 
 #[cfg(test)]
-use bootloader::{entry_point, BootInfo};
+use bootloader::entry_point;
+use bootloader::BootInfo;
 
 extern crate alloc;
 
+pub mod abi;
+pub mod acpi;
 pub mod allocator;
+pub mod apic;
+pub mod backtrace;
+pub mod cpu;
+pub mod deferred;
+pub mod elf;
 pub mod gdt;
 pub mod interrupts;
 pub mod memory;
+pub mod percpu;
+pub mod process;
+pub mod rtc;
 pub mod serial;
+pub mod smp;
+pub mod sync;
+pub mod syscall;
 pub mod task;
+pub mod thread;
+pub mod time;
+pub mod userspace;
 pub mod vga_buffer;
+pub mod watchdog;
 
-use core::panic::PanicInfo;
+use core::{future::Future, panic::PanicInfo};
+use memory::BootInfoFrameAllocator;
+use task::{executor::Executor, Task};
+use x86_64::{structures::paging::OffsetPageTable, VirtAddr};
 
 /// central place for all init routines that can be shared across
 /// various _start() fns in main.rs, lib.rs, and integration tests
 pub fn init() {
+    cpu::init();
     gdt::init();
     interrupts::init_idt();
     unsafe {
         // unsafe because causes undefined behavior if the PIC is misconfigured
         interrupts::PICS.lock().initialize();
     }
+    time::init();
+    rtc::init();
     x86_64::instructions::interrupts::enable();
 }
 
@@ -62,6 +86,31 @@ pub fn test_runner(tests: &[&dyn Testable]) {
     exit_qemu(QemuExitCode::Success);
 }
 
+/// Runs `init`, sets up paging and the kernel heap for an integration test.
+///
+/// Returns the mapper and frame allocator, for tests that go on with
+/// `memory::install`.
+pub fn test_init(
+    boot_info: &'static BootInfo,
+) -> (OffsetPageTable<'static>, BootInfoFrameAllocator) {
+    init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    (mapper, frame_allocator)
+}
+
+/// Runs `future` and the tasks it spawns on a new executor until all are done.
+///
+/// Panics if a task is still blocked once nothing is ready anymore.
+pub fn run_tasks(future: impl Future<Output = ()> + 'static) {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(future));
+    executor.run_until_stalled();
+    assert_eq!(executor.task_count(), 0, "a task is still blocked");
+}
+
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
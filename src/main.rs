@@ -4,19 +4,20 @@
 #![test_runner(atlas::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
-mod serial;
-mod vga_buffer;
-
 extern crate alloc;
 
 use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
 use atlas::{
-    allocator,
+    allocator, deferred, gdt,
     memory::{self, BootInfoFrameAllocator},
-    task::{executor::Executor, keyboard, simple_executor::SimpleExecutor, Task},
+    println, smp,
+    task::{
+        executor::Executor, keyboard, metrics, simple_executor::SimpleExecutor, Priority, Task,
+    },
+    thread, userspace, watchdog,
 };
 use bootloader::{entry_point, BootInfo};
-use core::panic::PanicInfo;
+use core::{panic::PanicInfo, time::Duration};
 use x86_64::{structures::paging::mapper, VirtAddr};
 
 async fn async_number() -> u32 {
@@ -41,6 +42,12 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
     let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    gdt::init_ist_stacks();
+    userspace::init();
+    thread::init();
+    deferred::init();
+    println!("{} CPUs online", smp::init());
 
     // allocate a number on the heap
     let heap_value = Box::new(41);
@@ -66,9 +73,16 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
         Rc::strong_count(&cloned_reference)
     );
 
+    watchdog::enable(Duration::from_secs(5));
+    metrics::set_poll_budget(Some(Duration::from_millis(10)));
+
     let mut executor = Executor::new();
-    executor.spawn(Task::new(example_task()));
-    executor.spawn(Task::new(keyboard::print_keypresses()));
+    executor.spawn(Task::new(example_task()).named("example"));
+    executor.spawn(
+        Task::new(keyboard::print_keypresses())
+            .named("keyboard")
+            .with_priority(Priority::High),
+    );
     executor.run();
 
     #[cfg(test)]
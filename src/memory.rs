@@ -1,8 +1,20 @@
+pub mod address_space;
+pub mod stack;
+pub mod tlb;
+pub mod user;
+
+use alloc::vec::Vec;
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
 use x86_64::structures::paging::{Page, PageTableFlags as Flags};
 use x86_64::{
     registers::control::{Cr0Flags, Cr3, Cr3Flags},
-    structures::paging::{FrameAllocator, Mapper, OffsetPageTable, PageTable, PhysFrame, Size4KiB},
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, PageTable, PhysFrame, Size4KiB,
+    },
     PhysAddr, VirtAddr,
 };
 
@@ -26,6 +38,22 @@ use x86_64::{
 // - No need to manually translate between levels
 // - OS can easily modify page tables
 
+/// Start of the address range user programs live in.
+///
+/// `USER_SPACE_START..USER_SPACE_END` covers level 4 entries 32..128, clear of
+/// the bootloader's mappings (entries 0-31), the heap (136), kernel stacks (170)
+/// and device registers (204).
+pub const USER_SPACE_START: u64 = 0x_1000_0000_0000;
+pub const USER_SPACE_END: u64 = 0x_4000_0000_0000;
+
+/// Memory below 1 MiB is never handed out by `BootInfoFrameAllocator`, it is
+/// kept for code that must run in real mode (see `smp`)
+pub const LOW_MEMORY_END: u64 = 0x10_0000;
+
+/// Virtual region device registers are mapped into by `map_mmio`
+const MMIO_REGION_START: u64 = 0x_6666_6666_0000;
+const MMIO_REGION_SIZE: u64 = 256 * 1024 * 1024; // 256 MiB
+
 /// # Safety
 /// Returns a mutable reference to the active level 4 table.
 ///
@@ -86,6 +114,11 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
     next: usize,
+    /// Frames handed back through `FrameDeallocator`, reused before fresh ones.
+    ///
+    /// Only grows once frames are freed, so allocating frames for the heap
+    /// itself never touches the heap.
+    free_frames: Vec<PhysFrame>,
 }
 
 impl BootInfoFrameAllocator {
@@ -99,6 +132,7 @@ impl BootInfoFrameAllocator {
         BootInfoFrameAllocator {
             memory_map,
             next: 0,
+            free_frames: Vec::new(),
         }
     }
 
@@ -108,8 +142,9 @@ impl BootInfoFrameAllocator {
         let regions = self.memory_map.iter();
         let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
 
-        // map each region to its address range
-        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
+        // map each region to its address range, leaving out low memory
+        let addr_ranges =
+            usable_regions.map(|r| r.range.start_addr().max(LOW_MEMORY_END)..r.range.end_addr());
 
         // transform to an iterator of frame start addresses
         // step by 4KiB because its the page size, so we align to start address of each frame
@@ -120,10 +155,138 @@ impl BootInfoFrameAllocator {
     }
 }
 
+impl BootInfoFrameAllocator {
+    /// A usable frame below `LOW_MEMORY_END`, above the real-mode IVT and BIOS data area.
+    ///
+    /// Always the same frame, low memory is not managed by the allocator.
+    pub fn low_memory_frame(&self) -> Option<PhysFrame> {
+        self.memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .map(|r| r.range.start_addr().max(0x1000)..r.range.end_addr().min(LOW_MEMORY_END))
+            .find(|range| range.end >= range.start + 4096)
+            .map(|range| PhysFrame::containing_address(PhysAddr::new(range.start)))
+    }
+}
+
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        if let Some(frame) = self.free_frames.pop() {
+            return Some(frame);
+        }
         let frame = self.usable_frames().nth(self.next);
         self.next += 1;
         frame
     }
 }
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        self.free_frames.push(frame);
+    }
+}
+
+/// Page table and frame allocator used by the kernel once booted
+pub struct KernelMemory {
+    /// Mapper of the kernel's own (boot) level 4 table
+    pub mapper: OffsetPageTable<'static>,
+    pub frame_allocator: BootInfoFrameAllocator,
+    /// Frame of the table `mapper` works on
+    pub kernel_level_4_frame: PhysFrame,
+}
+
+static KERNEL_MEMORY: OnceCell<Mutex<KernelMemory>> = OnceCell::uninit();
+
+/// Hands the boot page table and frame allocator over to the kernel, so
+/// subsystems can map memory after `kernel_main` returned from its setup.
+///
+/// Must be called once, after the heap has been initialized.
+pub fn install(mapper: OffsetPageTable<'static>, frame_allocator: BootInfoFrameAllocator) {
+    let (kernel_level_4_frame, _) = Cr3::read();
+    KERNEL_MEMORY
+        .try_init_once(|| {
+            Mutex::new(KernelMemory {
+                mapper,
+                frame_allocator,
+                kernel_level_4_frame,
+            })
+        })
+        .expect("memory::install should only be called once");
+    tlb::init();
+}
+
+/// Runs `f` with exclusive access to the kernel's paging state.
+///
+/// Interrupts are disabled meanwhile, so an interrupt handler can never
+/// deadlock on the lock. Panics if `install` was not called.
+pub fn with_kernel_memory<R>(f: impl FnOnce(&mut KernelMemory) -> R) -> R {
+    let memory = KERNEL_MEMORY
+        .try_get()
+        .expect("kernel memory not installed");
+    interrupts::without_interrupts(|| f(&mut memory.lock()))
+}
+
+/// Runs `f` with a mapper for the page table currently loaded in CR3 (a user
+/// program's address space or the kernel's own) and the frame allocator.
+pub fn with_active_mapper<R>(
+    f: impl FnOnce(&mut OffsetPageTable, &mut BootInfoFrameAllocator) -> R,
+) -> R {
+    with_kernel_memory(|memory| {
+        let (level_4_frame, _) = Cr3::read();
+        if level_4_frame == memory.kernel_level_4_frame {
+            return f(&mut memory.mapper, &mut memory.frame_allocator);
+        }
+        let phys_offset = memory.mapper.phys_offset();
+        let mut mapper = unsafe { address_space::mapper_for(level_4_frame, phys_offset) };
+        f(&mut mapper, &mut memory.frame_allocator)
+    })
+}
+
+/// Loads the kernel's own page table, e.g. after a user program exited.
+pub fn activate_kernel_address_space() {
+    let frame = with_kernel_memory(|memory| memory.kernel_level_4_frame);
+    unsafe { tlb::switch_address_space(frame) };
+}
+
+/// Virtual address of physical address `addr` in the physical memory mapping
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    with_kernel_memory(|memory| memory.mapper.phys_offset() + addr.as_u64())
+}
+
+/// Next unused address of the MMIO region
+static MMIO_NEXT: AtomicU64 = AtomicU64::new(MMIO_REGION_START);
+
+/// Maps the device registers at `addr..addr + size` uncached into the kernel
+/// address space and returns the virtual address of `addr`.
+///
+/// Only address spaces created afterwards see the mapping, so devices should
+/// be mapped during boot. Returns `None` if the MMIO region is exhausted.
+pub fn map_mmio(addr: PhysAddr, size: u64) -> Option<VirtAddr> {
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(addr);
+    let last_frame = PhysFrame::<Size4KiB>::containing_address(addr + (size.max(1) - 1));
+    let frames = PhysFrame::range_inclusive(first_frame, last_frame);
+    let reserved = frames.count() as u64 * 4096;
+    let start = MMIO_NEXT.fetch_add(reserved, Ordering::Relaxed);
+    if start + reserved > MMIO_REGION_START + MMIO_REGION_SIZE {
+        return None;
+    }
+
+    let flags = Flags::PRESENT
+        | Flags::WRITABLE
+        | Flags::NO_CACHE
+        | Flags::WRITE_THROUGH
+        | Flags::NO_EXECUTE;
+    with_kernel_memory(|memory| {
+        for (index, frame) in frames.enumerate() {
+            let page = Page::containing_address(VirtAddr::new(start + index as u64 * 4096));
+            unsafe {
+                memory
+                    .mapper
+                    .map_to(page, frame, flags, &mut memory.frame_allocator)
+                    .ok()?
+                    .flush();
+            }
+        }
+        Some(VirtAddr::new(start) + (addr - first_frame.start_address()))
+    })
+}
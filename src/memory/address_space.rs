@@ -0,0 +1,165 @@
+use super::{tlb, with_kernel_memory, USER_SPACE_END, USER_SPACE_START};
+use x86_64::{
+    registers::control::Cr3,
+    structures::paging::{
+        mapper::TranslateResult, FrameAllocator, FrameDeallocator, OffsetPageTable, PageSize,
+        PageTable, PageTableFlags, PhysFrame, Size4KiB, Translate,
+    },
+    VirtAddr,
+};
+
+// ADDRESS SPACES:
+//
+// Every user program gets its own level 4 table. The entries outside the user
+// range are copied from the kernel's table, so both share the same level 3
+// tables and every kernel mapping below them:
+//
+// Level 4 Table (per address space)
+// ├── Entry 0-31:    shared with the kernel (bootloader, kernel image, ...)
+// ├── Entry 32-127:  private user mappings
+// └── Entry 128-511: shared with the kernel (heap, stacks, physical memory, ...)
+//
+// Kernel mappings created later under a new level 4 entry are not visible in
+// existing address spaces.
+
+/// Level 4 entries reserved for user mappings
+const USER_LEVEL_4_ENTRIES: core::ops::Range<usize> =
+    (USER_SPACE_START >> 39) as usize..(USER_SPACE_END >> 39) as usize;
+
+/// # Safety
+/// Creates a mapper for the level 4 table in `level_4_frame`.
+///
+/// Unsafe because the caller must guarantee that the frame holds a valid level 4
+/// table, that all physical memory is mapped at `phys_offset`, and that no other
+/// mapper for the same table is used at the same time.
+pub(super) unsafe fn mapper_for(
+    level_4_frame: PhysFrame,
+    phys_offset: VirtAddr,
+) -> OffsetPageTable<'static> {
+    let table = table_at(level_4_frame, phys_offset);
+    OffsetPageTable::new(table, phys_offset)
+}
+
+/// # Safety
+/// Same requirements as `mapper_for`.
+unsafe fn table_at(frame: PhysFrame, phys_offset: VirtAddr) -> &'static mut PageTable {
+    let virt = phys_offset + frame.start_address().as_u64();
+    &mut *virt.as_mut_ptr()
+}
+
+/// Page tables of a user program, freed with all user memory on drop
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+    phys_offset: VirtAddr,
+}
+
+impl AddressSpace {
+    /// Creates an address space with the kernel's mappings and an empty user range.
+    ///
+    /// Returns `None` if no frame for the level 4 table is available.
+    pub fn new() -> Option<AddressSpace> {
+        with_kernel_memory(|memory| {
+            let level_4_frame = memory.frame_allocator.allocate_frame()?;
+            let phys_offset = memory.mapper.phys_offset();
+            let table = unsafe { table_at(level_4_frame, phys_offset) };
+            table.zero();
+            for (index, entry) in memory.mapper.level_4_table().iter().enumerate() {
+                if !USER_LEVEL_4_ENTRIES.contains(&index) {
+                    table[index] = entry.clone();
+                }
+            }
+            Some(AddressSpace {
+                level_4_frame,
+                phys_offset,
+            })
+        })
+    }
+
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// Mapper for this address space, usable while it is not the active one.
+    pub fn mapper(&mut self) -> OffsetPageTable<'_> {
+        unsafe { mapper_for(self.level_4_frame, self.phys_offset) }
+    }
+
+    /// Copies `bytes` to `addr` in this address space through the physical
+    /// memory mapping, so the address space does not need to be active.
+    ///
+    /// Returns `false` if part of the destination is not mapped.
+    pub fn write(&mut self, addr: VirtAddr, bytes: &[u8]) -> bool {
+        let phys_offset = self.phys_offset;
+        let mapper = self.mapper();
+        let mut written = 0;
+        while written < bytes.len() {
+            let dst = addr + written as u64;
+            let (frame, offset) = match mapper.translate(dst) {
+                TranslateResult::Mapped { frame, offset, .. } => (frame, offset),
+                _ => return false,
+            };
+            let size = (Size4KiB::SIZE - offset).min((bytes.len() - written) as u64) as usize;
+            let dst_ptr: *mut u8 =
+                (phys_offset + frame.start_address().as_u64() + offset).as_mut_ptr();
+            unsafe {
+                dst_ptr.copy_from_nonoverlapping(bytes[written..].as_ptr(), size);
+            }
+            written += size;
+        }
+        true
+    }
+
+    /// # Safety
+    /// Loads this address space into CR3.
+    ///
+    /// Unsafe because the caller must switch back before the address space is dropped.
+    pub unsafe fn activate(&self) {
+        tlb::switch_address_space(self.level_4_frame);
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        assert_ne!(
+            Cr3::read().0,
+            self.level_4_frame,
+            "dropping the active address space"
+        );
+        let level_4_frame = self.level_4_frame;
+        let phys_offset = self.phys_offset;
+        with_kernel_memory(|memory| unsafe {
+            let level_4 = table_at(level_4_frame, phys_offset);
+            for index in USER_LEVEL_4_ENTRIES {
+                free_table(&level_4[index], 3, phys_offset, &mut memory.frame_allocator);
+            }
+            memory.frame_allocator.deallocate_frame(level_4_frame);
+        });
+    }
+}
+
+/// # Safety
+/// Frees the table `entry` points to, all tables below it and all mapped frames.
+///
+/// `level` is the level of the table the entry points to.
+unsafe fn free_table(
+    entry: &x86_64::structures::paging::page_table::PageTableEntry,
+    level: u8,
+    phys_offset: VirtAddr,
+    frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+) {
+    if !entry.flags().contains(PageTableFlags::PRESENT) {
+        return;
+    }
+    let frame = match entry.frame() {
+        Ok(frame) => frame,
+        // user mappings never use huge pages
+        Err(_) => return,
+    };
+    if level > 0 {
+        let table = table_at(frame, phys_offset);
+        for entry in table.iter() {
+            free_table(entry, level - 1, phys_offset, frame_deallocator);
+        }
+    }
+    frame_deallocator.deallocate_frame(frame);
+}
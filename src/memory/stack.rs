@@ -0,0 +1,153 @@
+use super::{tlb::TlbBatch, with_kernel_memory};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{
+    structures::paging::{
+        page::PageRangeInclusive, FrameAllocator, FrameDeallocator, Mapper, Page, PageSize,
+        PageTableFlags, Size4KiB,
+    },
+    VirtAddr,
+};
+
+// KERNEL STACK LAYOUT:
+//
+// Stacks are carved out of a dedicated virtual region, each one preceded by an
+// unmapped guard page:
+//
+// STACK_REGION_START
+// ├── guard page  (unmapped)
+// ├── stack 0     (mapped, grows down towards its guard page)
+// ├── guard page  (unmapped)
+// ├── stack 1
+// └── ...
+//
+// Overflowing a stack hits its guard page and raises a page fault instead of
+// silently corrupting whatever lies below it.
+
+/// Start of the virtual region reserved for kernel stacks
+pub const STACK_REGION_START: u64 = 0x_5555_5555_0000;
+pub const STACK_REGION_SIZE: u64 = 256 * 1024 * 1024; // 256 MiB
+
+/// Next unused address of the stack region
+static NEXT: AtomicU64 = AtomicU64::new(STACK_REGION_START);
+
+const REGION_PAGES: usize = (STACK_REGION_SIZE / Size4KiB::SIZE) as usize;
+
+/// Guard pages of the stacks not freed yet, one bit per page of the region
+static GUARD_PAGES: [AtomicU64; REGION_PAGES / 64] =
+    [const { AtomicU64::new(0) }; REGION_PAGES / 64];
+
+/// Word and bit of the page containing `addr` in `GUARD_PAGES`
+fn guard_bit(addr: VirtAddr) -> (&'static AtomicU64, u64) {
+    let index = ((addr.as_u64() - STACK_REGION_START) / Size4KiB::SIZE) as usize;
+    (&GUARD_PAGES[index / 64], 1 << (index % 64))
+}
+
+/// Virtual address range of an allocated stack, excluding its guard page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackBounds {
+    start: VirtAddr,
+    end: VirtAddr,
+}
+
+impl StackBounds {
+    /// Lowest usable address
+    pub fn start(&self) -> VirtAddr {
+        self.start
+    }
+
+    /// Initial stack pointer, stacks grow downwards from here
+    pub fn end(&self) -> VirtAddr {
+        self.end
+    }
+}
+
+/// Allocates a stack of `pages` mapped pages plus one guard page below it.
+///
+/// Returns `None` if the stack region or physical memory is exhausted.
+pub fn alloc_stack(pages: u64) -> Option<StackBounds> {
+    let reserved = (pages + 1) * Size4KiB::SIZE;
+    let guard_start = NEXT.fetch_add(reserved, Ordering::Relaxed);
+    if guard_start + reserved > STACK_REGION_START + STACK_REGION_SIZE {
+        return None;
+    }
+
+    let start = VirtAddr::new(guard_start + Size4KiB::SIZE);
+    let end = start + pages * Size4KiB::SIZE;
+    let first_page = Page::<Size4KiB>::containing_address(start);
+    let last_page = Page::<Size4KiB>::containing_address(end - 1u64);
+
+    let mapped = with_kernel_memory(|memory| {
+        for page in Page::range_inclusive(first_page, last_page) {
+            let Some(frame) = memory.frame_allocator.allocate_frame() else {
+                return Err(page);
+            };
+            let flags =
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+            match unsafe {
+                memory
+                    .mapper
+                    .map_to(page, frame, flags, &mut memory.frame_allocator)
+            } {
+                Ok(flush) => flush.flush(),
+                Err(_) => {
+                    unsafe { memory.frame_allocator.deallocate_frame(frame) };
+                    return Err(page);
+                }
+            }
+        }
+        Ok(())
+    });
+    if let Err(failed) = mapped {
+        // give back what was mapped before the failure, the virtual range is lost
+        if failed > first_page {
+            unmap_pages(Page::range_inclusive(first_page, failed - 1));
+        }
+        return None;
+    }
+
+    let (word, bit) = guard_bit(VirtAddr::new(guard_start));
+    word.fetch_or(bit, Ordering::Relaxed);
+    Some(StackBounds { start, end })
+}
+
+/// Unmaps the stack `bounds` and frees its frames.
+///
+/// The virtual range is not reused, so a dangling pointer into the stack still
+/// faults, though no longer as a stack overflow. The caller must ensure nothing
+/// runs on the stack anymore.
+pub fn free_stack(bounds: StackBounds) {
+    let (word, bit) = guard_bit(bounds.start - Size4KiB::SIZE);
+    word.fetch_and(!bit, Ordering::Relaxed);
+
+    let first_page = Page::<Size4KiB>::containing_address(bounds.start);
+    let last_page = Page::<Size4KiB>::containing_address(bounds.end - 1u64);
+    unmap_pages(Page::range_inclusive(first_page, last_page));
+}
+
+/// Unmaps `pages` and frees their frames
+fn unmap_pages(pages: PageRangeInclusive<Size4KiB>) {
+    let mut tlb = TlbBatch::kernel();
+    with_kernel_memory(|memory| {
+        for page in pages {
+            if let Ok((frame, flush)) = memory.mapper.unmap(page) {
+                flush.ignore();
+                tlb.add(page);
+                unsafe { memory.frame_allocator.deallocate_frame(frame) };
+            }
+        }
+    });
+    // only after the kernel memory lock is released, see `TlbBatch`
+    drop(tlb);
+}
+
+/// Checks whether a not-present page fault at `addr` hit the guard page of a
+/// stack that wasn't freed yet, i.e. whether it is a stack overflow.
+///
+/// Safe to call from the page fault handler, it takes no locks.
+pub fn is_guard_page(addr: VirtAddr) -> bool {
+    if !(STACK_REGION_START..STACK_REGION_START + STACK_REGION_SIZE).contains(&addr.as_u64()) {
+        return false;
+    }
+    let (word, bit) = guard_bit(addr);
+    word.load(Ordering::Relaxed) & bit != 0
+}
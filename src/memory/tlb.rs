@@ -0,0 +1,254 @@
+use crate::{apic, cpu, percpu, smp};
+use core::{
+    hint,
+    sync::atomic::{fence, AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+use x86_64::{
+    instructions::tlb,
+    registers::control::Cr3,
+    structures::paging::{Page, PhysFrame},
+    VirtAddr,
+};
+
+// TLB SHOOTDOWN:
+//
+// Every CPU caches translations in its own TLB and `invlpg` only invalidates the
+// local one. When a mapping other CPUs may have cached is changed or removed,
+// they are interrupted to invalidate it as well:
+//
+// initiator                                 other CPUs
+// ─────────                                 ──────────
+// change page tables, collect the pages
+// post a request, send SHOOTDOWN_VECTOR ──→ invalidate the pages
+// wait until every target acknowledged  ←── clear own bit in `pending`
+//
+// Kernel mappings are shared by all address spaces, so every online CPU is a
+// target. User mappings only concern CPUs that have the address space loaded,
+// which is why CR3 must only be switched through `switch_address_space`.
+// No mapping uses the GLOBAL flag, so reloading CR3 flushes everything.
+
+/// Vector of the shootdown IPI
+pub const SHOOTDOWN_VECTOR: u8 = 0xfd;
+/// Pages invalidated one by one, larger batches flush the whole TLB
+const MAX_BATCH: usize = 32;
+/// `Request::level_4_frame` of kernel mappings
+const KERNEL_SCOPE: u64 = u64::MAX;
+
+/// The request currently being processed, protected by `REQUEST_LOCK`
+struct Request {
+    /// CPUs that still have to flush, one bit per CPU id
+    pending: AtomicU64,
+    /// Address space the pages belong to, or `KERNEL_SCOPE`
+    level_4_frame: AtomicU64,
+    /// Number of pages, more than `MAX_BATCH` means flush everything
+    count: AtomicUsize,
+    pages: [AtomicU64; MAX_BATCH],
+}
+
+static REQUEST: Request = Request {
+    pending: AtomicU64::new(0),
+    level_4_frame: AtomicU64::new(0),
+    count: AtomicUsize::new(0),
+    pages: [const { AtomicU64::new(0) }; MAX_BATCH],
+};
+static REQUEST_LOCK: AtomicBool = AtomicBool::new(false);
+
+percpu! {
+    /// Level 4 table loaded on each CPU, 0 if not known yet
+    static ACTIVE_LEVEL_4: AtomicU64 = AtomicU64::new(0);
+    /// Shootdown requests handled by each CPU
+    static RECEIVED: AtomicU64 = AtomicU64::new(0);
+}
+
+/// Records the page table the calling CPU booted with.
+///
+/// Called by every CPU once, after `memory::install` on the bootstrap processor.
+pub(crate) fn init() {
+    let (frame, _) = Cr3::read();
+    ACTIVE_LEVEL_4
+        .get()
+        .store(frame.start_address().as_u64(), Ordering::SeqCst);
+}
+
+/// # Safety
+/// Loads `level_4_frame` into CR3 and records it for shootdowns.
+///
+/// Unsafe because the table must map the running code and stay alive while loaded.
+pub(crate) unsafe fn switch_address_space(level_4_frame: PhysFrame) {
+    // published before the switch: an initiator that misses it changed the
+    // tables before this CPU could cache anything from them
+    ACTIVE_LEVEL_4
+        .get()
+        .store(level_4_frame.start_address().as_u64(), Ordering::SeqCst);
+    let (_, flags) = Cr3::read();
+    Cr3::write(level_4_frame, flags);
+}
+
+/// Pages whose mappings changed, invalidated on every CPU that may have cached
+/// them when the batch is dropped.
+///
+/// Drop it after releasing locks other CPUs may spin on with interrupts disabled,
+/// such as the kernel memory lock: they can't acknowledge the shootdown meanwhile.
+pub struct TlbBatch {
+    /// Level 4 table of the address space, `KERNEL_SCOPE` for kernel mappings
+    /// and `None` if no other CPU can have cached the pages
+    scope: Option<u64>,
+    pages: [Page; MAX_BATCH],
+    count: usize,
+}
+
+impl TlbBatch {
+    /// Batch for kernel mappings, which every CPU shares
+    pub fn kernel() -> Self {
+        Self::with_scope(Some(KERNEL_SCOPE))
+    }
+
+    /// Batch for user mappings of the address space with `level_4_frame`
+    pub fn user(level_4_frame: PhysFrame) -> Self {
+        Self::with_scope(Some(level_4_frame.start_address().as_u64()))
+    }
+
+    /// Batch for user mappings of the address space loaded on this CPU
+    pub fn active() -> Self {
+        Self::user(Cr3::read().0)
+    }
+
+    /// Batch only flushing this CPU, for mappings no other CPU could have used
+    pub fn local() -> Self {
+        Self::with_scope(None)
+    }
+
+    fn with_scope(scope: Option<u64>) -> Self {
+        TlbBatch {
+            scope,
+            pages: [Page::containing_address(VirtAddr::zero()); MAX_BATCH],
+            count: 0,
+        }
+    }
+
+    pub fn add(&mut self, page: Page) {
+        if self.count < MAX_BATCH {
+            self.pages[self.count] = page;
+        }
+        self.count += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn flush_local(&self) {
+        if self.count > MAX_BATCH {
+            tlb::flush_all();
+        } else {
+            for page in &self.pages[..self.count] {
+                tlb::flush(page.start_address());
+            }
+        }
+    }
+}
+
+impl Drop for TlbBatch {
+    fn drop(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        self.flush_local();
+        if let Some(scope) = self.scope {
+            shootdown(scope, self);
+        }
+    }
+}
+
+/// CPUs other than the caller that may cache mappings of `scope`
+fn targets(scope: u64) -> u64 {
+    // pairs with the store in `switch_address_space`
+    fence(Ordering::SeqCst);
+    let me = cpu::current_id();
+    let online = smp::online_mask() & !(1 << me);
+    if scope == KERNEL_SCOPE {
+        return online;
+    }
+    ACTIVE_LEVEL_4
+        .iter()
+        .filter(|(_, active)| {
+            let active = active.load(Ordering::SeqCst);
+            active == 0 || active == scope
+        })
+        .fold(0, |mask, (cpu, _)| mask | (1 << cpu))
+        & online
+}
+
+/// Asks the `targets` CPUs to invalidate `batch` and waits until all of them did.
+fn shootdown(scope: u64, batch: &TlbBatch) {
+    let targets = targets(scope);
+    if targets == 0 {
+        return;
+    }
+
+    while REQUEST_LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        // the owner may be waiting for this CPU, which can't take the IPI if
+        // interrupts are disabled
+        handle_request();
+        hint::spin_loop();
+    }
+
+    REQUEST.level_4_frame.store(scope, Ordering::Relaxed);
+    REQUEST.count.store(batch.count, Ordering::Relaxed);
+    for (slot, page) in REQUEST
+        .pages
+        .iter()
+        .zip(&batch.pages[..batch.count.min(MAX_BATCH)])
+    {
+        slot.store(page.start_address().as_u64(), Ordering::Relaxed);
+    }
+    REQUEST.pending.store(targets, Ordering::Release);
+
+    // CPU ids are APIC ids
+    for cpu in 0..cpu::MAX_CPUS {
+        if targets & (1 << cpu) != 0 {
+            apic::send_interrupt(cpu as u8, SHOOTDOWN_VECTOR);
+        }
+    }
+    while REQUEST.pending.load(Ordering::Acquire) != 0 {
+        hint::spin_loop();
+    }
+
+    REQUEST_LOCK.store(false, Ordering::Release);
+}
+
+/// Invalidates the pages of the current request if it targets this CPU.
+///
+/// Called by the shootdown IPI handler. Safe to call at any time, does nothing
+/// if no request is pending.
+pub(crate) fn handle_request() {
+    let bit = 1 << cpu::current_id();
+    if REQUEST.pending.load(Ordering::Acquire) & bit == 0 {
+        return;
+    }
+    let scope = REQUEST.level_4_frame.load(Ordering::Relaxed);
+    let active = ACTIVE_LEVEL_4.get().load(Ordering::Relaxed);
+    // after switching away, the CR3 write already flushed the address space
+    if scope == KERNEL_SCOPE || scope == active {
+        let count = REQUEST.count.load(Ordering::Relaxed);
+        if count > MAX_BATCH {
+            tlb::flush_all();
+        } else {
+            for page in &REQUEST.pages[..count] {
+                tlb::flush(VirtAddr::new(page.load(Ordering::Relaxed)));
+            }
+        }
+    }
+    RECEIVED.get().fetch_add(1, Ordering::Relaxed);
+    REQUEST.pending.fetch_and(!bit, Ordering::Release);
+}
+
+/// Number of shootdown requests `cpu` handled
+pub fn received(cpu: usize) -> u64 {
+    RECEIVED
+        .get_on(cpu)
+        .map_or(0, |received| received.load(Ordering::Relaxed))
+}
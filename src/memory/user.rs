@@ -0,0 +1,129 @@
+use super::{tlb::TlbBatch, USER_SPACE_END, USER_SPACE_START};
+use x86_64::{
+    structures::paging::{
+        mapper::{MapToError, TranslateResult},
+        page::PageRangeInclusive,
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTableFlags,
+        Size4KiB, Translate,
+    },
+    VirtAddr,
+};
+
+/// Flags of intermediate page tables for user mappings, the effective access
+/// rights are controlled by the last level alone
+const PARENT_TABLE_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE);
+
+/// Checks that `addr..addr + len` lies completely inside user space.
+pub fn is_user_range(addr: u64, len: u64) -> bool {
+    match addr.checked_add(len) {
+        Some(end) => addr >= USER_SPACE_START && end <= USER_SPACE_END,
+        None => false,
+    }
+}
+
+/// Pages covering `addr..addr + len`, `len` must not be 0.
+pub fn page_range(addr: u64, len: u64) -> PageRangeInclusive {
+    let first = Page::containing_address(VirtAddr::new(addr));
+    let last = Page::containing_address(VirtAddr::new(addr + len - 1));
+    Page::range_inclusive(first, last)
+}
+
+/// Checks that every page of `addr..addr + len` is mapped accessible to ring 3,
+/// and writable if `write` is set.
+///
+/// Only the flags of the last level are checked, intermediate tables of user
+/// mappings are always created with `PARENT_TABLE_FLAGS`.
+pub fn is_accessible(mapper: &impl Translate, addr: u64, len: u64, write: bool) -> bool {
+    if len == 0 {
+        return is_user_range(addr, len);
+    }
+    if !is_user_range(addr, len) {
+        return false;
+    }
+    let mut required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if write {
+        required |= PageTableFlags::WRITABLE;
+    }
+    page_range(addr, len).all(|page| match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped { flags, .. } => flags.contains(required),
+        _ => false,
+    })
+}
+
+/// Maps every page of `pages` to a freshly allocated, zeroed frame.
+///
+/// `USER_ACCESSIBLE` is added to `flags`. On failure the pages mapped so far
+/// are unmapped again.
+pub fn map_zeroed(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+    pages: PageRangeInclusive,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    for page in pages {
+        let result = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)
+            .and_then(|frame| {
+                // frames are recycled, never leak a previous owner's data
+                let frame_ptr: *mut u8 =
+                    (mapper.phys_offset() + frame.start_address().as_u64()).as_mut_ptr();
+                unsafe {
+                    frame_ptr.write_bytes(0, Size4KiB::SIZE as usize);
+                    mapper.map_to_with_table_flags(
+                        page,
+                        frame,
+                        flags,
+                        PARENT_TABLE_FLAGS,
+                        frame_allocator,
+                    )
+                }
+            });
+        match result {
+            Ok(flush) => flush.flush(),
+            Err(err) => {
+                if page != pages.start {
+                    // the pages were never handed out, so no other CPU used them
+                    unmap(
+                        mapper,
+                        frame_allocator,
+                        Page::range_inclusive(pages.start, page - 1),
+                        &mut TlbBatch::local(),
+                    );
+                }
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Unmaps the user pages of `pages` and frees their frames.
+///
+/// Pages that are not mapped or not `USER_ACCESSIBLE` are skipped. The unmapped
+/// pages are added to `tlb`. Returns the number of pages unmapped.
+pub fn unmap(
+    mapper: &mut OffsetPageTable,
+    frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+    pages: PageRangeInclusive,
+    tlb: &mut TlbBatch,
+) -> usize {
+    let mut unmapped = 0;
+    for page in pages {
+        match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. }
+                if flags.contains(PageTableFlags::USER_ACCESSIBLE) => {}
+            _ => continue,
+        }
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.ignore();
+            tlb.add(page);
+            unsafe { frame_deallocator.deallocate_frame(frame) };
+            unmapped += 1;
+        }
+    }
+    unmapped
+}
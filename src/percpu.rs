@@ -0,0 +1,75 @@
+use crate::cpu;
+
+// PER-CPU VARIABLES:
+//
+// A per-CPU variable holds one slot for each possible CPU, indexed by the id
+// `cpu::current_id` reads through GS. Declare them with `percpu!`:
+//
+//     percpu! {
+//         static EVENTS: AtomicU64 = AtomicU64::new(0);
+//     }
+//
+//     EVENTS.get().fetch_add(1, Ordering::Relaxed);
+//
+// A CPU can be interrupted or (on the BSP) preempted by another thread between
+// looking up its slot and using it, so slots are atomics or locks like any
+// other shared static; per-CPU data only removes the contention.
+
+/// One `T` per CPU, see `percpu!`
+pub struct PerCpu<T> {
+    slots: [T; cpu::MAX_CPUS],
+}
+
+impl<T> PerCpu<T> {
+    pub const fn new(slots: [T; cpu::MAX_CPUS]) -> Self {
+        PerCpu { slots }
+    }
+
+    /// Slot of the executing CPU
+    pub fn get(&self) -> &T {
+        &self.slots[cpu::current_id()]
+    }
+
+    /// Slot of `cpu`, `None` if the id is out of range
+    pub fn get_on(&self, cpu: usize) -> Option<&T> {
+        self.slots.get(cpu)
+    }
+
+    /// All slots with their CPU id
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.slots.iter().enumerate()
+    }
+}
+
+/// Declares statics with one instance per CPU.
+///
+/// The initializer must be a constant expression, it is evaluated once per slot.
+#[macro_export]
+macro_rules! percpu {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)*) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::percpu::PerCpu<$ty> =
+                $crate::percpu::PerCpu::new([const { $init }; $crate::cpu::MAX_CPUS]);
+        )*
+    };
+}
+
+#[cfg(test)]
+use core::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(test)]
+percpu! {
+    static TEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+}
+
+#[test_case]
+fn test_percpu_slots() {
+    TEST_COUNTER.get().fetch_add(2, Ordering::Relaxed);
+    let current = cpu::current_id();
+    for (cpu, slot) in TEST_COUNTER.iter() {
+        let expected = if cpu == current { 2 } else { 0 };
+        assert_eq!(slot.load(Ordering::Relaxed), expected);
+    }
+    assert!(TEST_COUNTER.get_on(cpu::MAX_CPUS).is_none());
+}
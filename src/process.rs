@@ -0,0 +1,455 @@
+pub mod handle;
+
+use crate::{
+    memory::{self, address_space::AddressSpace},
+    percpu, println,
+    thread::{self, ThreadId},
+    userspace::{
+        self,
+        loader::{self, LoadError},
+    },
+};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use handle::{Handle, HandleTable};
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+// PROCESSES:
+//
+// A process is a loaded user program together with everything it owns: its
+// address space, its open handles and its place in the process tree. Until
+// A process runs on the thread that first waits for it, other waiters are
+// parked until it exited.
+//
+// Ready ──wait──> Running ──exit──> Zombie ──wait (parent)──> reaped
+//   │                │                ▲
+//   └──kill──────────┴──kill──────────┘ (a running process is stopped at its next syscall)
+//
+// A zombie keeps only its exit status, the address space is freed on exit.
+// Children of an exiting process are orphaned and detached: nobody waits for
+// them, so they are reaped as soon as they exit.
+
+/// Region `mmap` picks addresses from when no address is requested
+pub const MMAP_START: u64 = 0x_2000_0000_0000;
+const MMAP_END: u64 = 0x_3000_0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pid(u64);
+
+impl Pid {
+    fn new() -> Self {
+        // 0 marks "no process" in `CURRENT`
+        static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+        Pid(NEXT_PID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for Pid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// How a process ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// Called `exit` with this code
+    Exited(i32),
+    /// Stopped by `kill`
+    Killed,
+    /// Stopped by the kernel after raising the exception with this vector
+    Faulted(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Loaded, not started yet
+    Ready,
+    Running,
+    /// Exited, waiting to be reaped by its parent
+    Zombie(ExitStatus),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessError {
+    NotFound,
+    /// The caller is not the parent of the process
+    NotChild,
+}
+
+pub struct Process {
+    pid: Pid,
+    /// `None` for processes spawned by the kernel
+    parent: Option<Pid>,
+    children: Vec<Pid>,
+    name: String,
+    state: State,
+    /// `None` once the process exited
+    address_space: Option<AddressSpace>,
+    entry: VirtAddr,
+    stack_pointer: VirtAddr,
+    pub handles: HandleTable,
+    /// Next address handed out by `mmap`
+    mmap_next: u64,
+    /// Ranges below `mmap_next` given back by `munmap`, by start address
+    mmap_free: BTreeMap<u64, u64>,
+    /// Reaped as soon as it exits instead of becoming a zombie
+    detached: bool,
+    /// Thread parked in `wait` until the process exits
+    waiter: Option<ThreadId>,
+    kill_requested: bool,
+}
+
+impl Process {
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub fn parent(&self) -> Option<Pid> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[Pid] {
+        &self.children
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Reserves `len` bytes of the `mmap` region, returns the start address.
+    ///
+    /// Reuses the first released range that is large enough.
+    pub fn reserve_mmap(&mut self, len: u64) -> Option<u64> {
+        let released = self
+            .mmap_free
+            .iter()
+            .find(|&(&start, &end)| end - start >= len)
+            .map(|(&start, &end)| (start, end));
+        if let Some((start, end)) = released {
+            self.mmap_free.remove(&start);
+            if start + len < end {
+                self.mmap_free.insert(start + len, end);
+            }
+            return Some(start);
+        }
+        let addr = self.mmap_next;
+        let end = addr.checked_add(len).filter(|&end| end <= MMAP_END)?;
+        self.mmap_next = end;
+        Some(addr)
+    }
+
+    /// Gives back the part of `addr..addr + len` that `reserve_mmap` handed out,
+    /// for reuse. Called by `munmap` and when mapping a reserved range failed.
+    pub fn release_mmap(&mut self, addr: u64, len: u64) {
+        let mut start = addr.max(MMAP_START);
+        let mut end = addr.saturating_add(len).min(self.mmap_next);
+        if start >= end {
+            return;
+        }
+        // merge with overlapping and adjacent released ranges
+        let previous = self.mmap_free.range(..=start).next_back();
+        if let Some((&previous_start, &previous_end)) = previous {
+            if previous_end >= start {
+                self.mmap_free.remove(&previous_start);
+                start = previous_start;
+                end = end.max(previous_end);
+            }
+        }
+        while let Some((&next_start, &next_end)) = self.mmap_free.range(start..).next() {
+            if next_start > end {
+                break;
+            }
+            self.mmap_free.remove(&next_start);
+            end = end.max(next_end);
+        }
+        if end == self.mmap_next {
+            self.mmap_next = start;
+        } else {
+            self.mmap_free.insert(start, end);
+        }
+    }
+}
+
+/// Snapshot of a process table entry
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: Pid,
+    pub parent: Option<Pid>,
+    pub name: String,
+    pub state: State,
+    pub handles: usize,
+}
+
+impl fmt::Display for ProcessInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parent = self.parent.map_or(0, Pid::as_u64);
+        write!(f, "{:>5} {:>6} ", self.pid, parent)?;
+        match self.state {
+            State::Ready => write!(f, "{:<14}", "ready")?,
+            State::Running => write!(f, "{:<14}", "running")?,
+            State::Zombie(ExitStatus::Exited(code)) => write!(f, "zombie({:<5})", code)?,
+            State::Zombie(ExitStatus::Killed) => write!(f, "{:<14}", "zombie(killed)")?,
+            State::Zombie(ExitStatus::Faulted(vector)) => write!(f, "zombie(#{:<4})", vector)?,
+        }
+        write!(f, " {:>3} {}", self.handles, self.name)
+    }
+}
+
+static PROCESSES: Mutex<BTreeMap<Pid, Process>> = Mutex::new(BTreeMap::new());
+percpu! {
+    /// Pid of the process each CPU runs in ring 3 (or in a syscall), 0 if none
+    static CURRENT: AtomicU64 = AtomicU64::new(0);
+}
+
+/// Loads the ELF executable `image` as a new process in state `Ready`.
+///
+/// The caller (the current process, or the kernel) becomes its parent.
+pub fn spawn(name: &str, image: &[u8], argv: &[&str], envp: &[&str]) -> Result<Pid, LoadError> {
+    let program = loader::load(image, argv, envp)?;
+    let pid = Pid::new();
+    let parent = current();
+    let process = Process {
+        pid,
+        parent,
+        children: Vec::new(),
+        name: String::from(name),
+        state: State::Ready,
+        address_space: Some(program.address_space),
+        entry: program.entry,
+        stack_pointer: program.stack_pointer,
+        handles: HandleTable::new(),
+        mmap_next: MMAP_START,
+        mmap_free: BTreeMap::new(),
+        detached: false,
+        waiter: None,
+        kill_requested: false,
+    };
+
+    let mut processes = PROCESSES.lock();
+    if let Some(parent) = parent.and_then(|parent| processes.get_mut(&parent)) {
+        parent.children.push(pid);
+    }
+    processes.insert(pid, process);
+    Ok(pid)
+}
+
+/// Process currently running user code or a syscall
+pub fn current() -> Option<Pid> {
+    match CURRENT.get().load(Ordering::Relaxed) {
+        0 => None,
+        pid => Some(Pid(pid)),
+    }
+}
+
+/// Sets the process of the running thread, see `userspace::restore_state`.
+pub(crate) fn set_current(pid: Option<Pid>) {
+    CURRENT
+        .get()
+        .store(pid.map_or(0, Pid::as_u64), Ordering::Relaxed);
+}
+
+/// Runs `f` on the current process, `None` if no process is running.
+pub fn with_current<R>(f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+    let pid = current()?;
+    PROCESSES.lock().get_mut(&pid).map(f)
+}
+
+/// Looks up `fd` in the handle table of the current process.
+///
+/// Programs run without a process (see `loader::exec`) get the standard handles.
+pub fn handle(fd: u64) -> Result<Handle, crate::abi::Errno> {
+    with_current(|process| process.handles.get(fd)).unwrap_or_else(|| HandleTable::new().get(fd))
+}
+
+/// Checks whether the current process should stop, called on syscall entry.
+pub fn kill_requested() -> bool {
+    with_current(|process| process.kill_requested).unwrap_or(false)
+}
+
+/// Waits for the child `pid` to exit, reaps it and returns its exit status.
+///
+/// A child that was not started yet is run to completion on the calling thread,
+/// for one running on another thread the calling thread is parked until it exits.
+pub fn wait(pid: Pid) -> Result<ExitStatus, ProcessError> {
+    let waiter = thread::current();
+    let status = loop {
+        let state = {
+            let mut processes = PROCESSES.lock();
+            let process = processes.get_mut(&pid).ok_or(ProcessError::NotFound)?;
+            if process.parent != current() || process.detached {
+                return Err(ProcessError::NotChild);
+            }
+            let state = process.state;
+            // claimed under the lock, so only one thread starts the process
+            match state {
+                State::Ready => process.state = State::Running,
+                State::Running => process.waiter = Some(waiter),
+                State::Zombie(_) => {}
+            }
+            state
+        };
+        match state {
+            State::Ready => break run(pid),
+            // running on another thread, `exit` unparks us
+            State::Running => thread::park(),
+            State::Zombie(status) => break status,
+        }
+    };
+    reap(pid);
+    Ok(status)
+}
+
+/// Stops the process `pid`.
+///
+/// A process that was not started yet exits right away, a running one at its
+/// next syscall. Killing a zombie does nothing.
+pub fn kill(pid: Pid) -> Result<(), ProcessError> {
+    let not_started = {
+        let mut processes = PROCESSES.lock();
+        let process = processes.get_mut(&pid).ok_or(ProcessError::NotFound)?;
+        match process.state {
+            State::Ready => {
+                // claimed under the lock, so `wait` can't start it anymore
+                process.state = State::Zombie(ExitStatus::Killed);
+                true
+            }
+            State::Running => {
+                process.kill_requested = true;
+                false
+            }
+            State::Zombie(_) => false,
+        }
+    };
+    if not_started {
+        exit(pid, ExitStatus::Killed);
+    }
+    Ok(())
+}
+
+/// Lets the process `pid` be reaped on exit without anyone waiting for it.
+pub fn detach(pid: Pid) -> Result<(), ProcessError> {
+    let zombie = {
+        let mut processes = PROCESSES.lock();
+        let process = processes.get_mut(&pid).ok_or(ProcessError::NotFound)?;
+        if process.parent != current() {
+            return Err(ProcessError::NotChild);
+        }
+        process.detached = true;
+        matches!(process.state, State::Zombie(_))
+    };
+    if zombie {
+        reap(pid);
+    }
+    Ok(())
+}
+
+/// Snapshot of the process table, ordered by pid
+pub fn list() -> Vec<ProcessInfo> {
+    PROCESSES
+        .lock()
+        .values()
+        .map(|process| ProcessInfo {
+            pid: process.pid,
+            parent: process.parent,
+            name: process.name.clone(),
+            state: process.state,
+            handles: process.handles.len(),
+        })
+        .collect()
+}
+
+/// Prints the process table to the console.
+pub fn print_table() {
+    println!("  PID   PPID STATE          FDS NAME");
+    for info in list() {
+        println!("{}", info);
+    }
+}
+
+/// Runs the process `pid`, already marked `Running`, in ring 3 until it exits.
+fn run(pid: Pid) -> ExitStatus {
+    let (entry, stack_pointer) = {
+        let processes = PROCESSES.lock();
+        let process = &processes[&pid];
+        let address_space = process
+            .address_space
+            .as_ref()
+            .expect("ready without memory");
+        unsafe { address_space.activate() };
+        (process.entry, process.stack_pointer)
+    };
+
+    set_current(Some(pid));
+    let status = unsafe { userspace::run(entry, stack_pointer) };
+    set_current(None);
+    memory::activate_kernel_address_space();
+
+    let killed = PROCESSES
+        .lock()
+        .get(&pid)
+        .is_some_and(|process| process.kill_requested);
+    let status = if killed { ExitStatus::Killed } else { status };
+    exit(pid, status);
+    status
+}
+
+/// Turns `pid` into a zombie: frees its memory and orphans its children.
+fn exit(pid: Pid, status: ExitStatus) {
+    let (address_space, children, detached, waiter) = {
+        let mut processes = PROCESSES.lock();
+        let process = processes.get_mut(&pid).expect("exiting unknown process");
+        process.state = State::Zombie(status);
+        let waiter = process.waiter.take();
+        let address_space = process.address_space.take();
+        let children = core::mem::take(&mut process.children);
+        for child in &children {
+            if let Some(child) = processes.get_mut(child) {
+                child.parent = None;
+                child.detached = true;
+            }
+        }
+        (address_space, children, processes[&pid].detached, waiter)
+    };
+    if let Some(waiter) = waiter {
+        thread::unpark(waiter);
+    }
+    // freeing frames takes the memory lock, don't hold the process table meanwhile
+    drop(address_space);
+
+    for child in children {
+        let zombie = PROCESSES
+            .lock()
+            .get(&child)
+            .is_some_and(|child| matches!(child.state, State::Zombie(_)));
+        if zombie {
+            reap(child);
+        }
+    }
+    if detached {
+        reap(pid);
+    }
+}
+
+/// Removes the zombie `pid` from the process table.
+fn reap(pid: Pid) {
+    let mut processes = PROCESSES.lock();
+    if let Some(process) = processes.remove(&pid) {
+        debug_assert!(matches!(process.state, State::Zombie(_)));
+        if let Some(parent) = process.parent.and_then(|parent| processes.get_mut(&parent)) {
+            parent.children.retain(|&child| child != pid);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use crate::abi::{self, Errno};
+use alloc::collections::BTreeMap;
+
+/// Kernel object a process refers to through a small integer (file descriptor)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handle {
+    /// Keyboard input
+    Keyboard,
+    /// VGA text console
+    Console,
+    /// VGA text console, additionally mirrored to serial
+    ConsoleAndSerial,
+}
+
+/// Open handles of a process, indexed by descriptor
+#[derive(Debug, Clone)]
+pub struct HandleTable {
+    handles: BTreeMap<u64, Handle>,
+}
+
+impl HandleTable {
+    /// Table with the standard descriptors `STDIN`, `STDOUT` and `STDERR` open
+    pub fn new() -> Self {
+        let mut handles = BTreeMap::new();
+        handles.insert(abi::STDIN, Handle::Keyboard);
+        handles.insert(abi::STDOUT, Handle::Console);
+        handles.insert(abi::STDERR, Handle::ConsoleAndSerial);
+        HandleTable { handles }
+    }
+
+    pub fn get(&self, fd: u64) -> Result<Handle, Errno> {
+        self.handles.get(&fd).copied().ok_or(Errno::BadF)
+    }
+
+    /// Opens `handle` under the lowest free descriptor and returns it.
+    pub fn insert(&mut self, handle: Handle) -> u64 {
+        let fd = (0..)
+            .find(|fd| !self.handles.contains_key(fd))
+            .expect("descriptor space exhausted");
+        self.handles.insert(fd, handle);
+        fd
+    }
+
+    pub fn close(&mut self, fd: u64) -> Result<Handle, Errno> {
+        self.handles.remove(&fd).ok_or(Errno::BadF)
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+}
+
+impl Default for HandleTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
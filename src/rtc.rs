@@ -0,0 +1,295 @@
+use crate::{interrupts, time};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use x86_64::instructions::{interrupts as cpu_interrupts, port::Port};
+
+// CMOS = battery backed RAM of the motherboard, the RTC lives in its first registers.
+// A register is selected by writing its index to 0x70, then read/written through 0x71.
+// Bit 7 of the index disables NMIs, so it is left clear.
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+/// Not standardized, but 0x32 is what QEMU and most chipsets use
+const REG_CENTURY: u8 = 0x32;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+const REG_STATUS_C: u8 = 0x0C;
+
+/// Status A: an update cycle is in progress, the time registers may be inconsistent
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Status B: periodic interrupt enable
+const STATUS_B_PERIODIC_INTERRUPT: u8 = 1 << 6;
+/// Status B: values are binary instead of BCD
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// Status B: hours are 0-23 instead of 1-12 with a PM flag
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+/// Set in the hours register for PM times in 12 hour mode
+const HOUR_PM: u8 = 1 << 7;
+
+/// UNIX timestamp read from the RTC during `init`
+static BOOT_TIMESTAMP: AtomicU64 = AtomicU64::new(0);
+/// Value of `time::ticks()` when `BOOT_TIMESTAMP` was read
+static BOOT_TICKS: AtomicU64 = AtomicU64::new(0);
+/// Number of RTC periodic interrupts received
+static PERIODIC_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Calendar time as read from the RTC, always UTC on QEMU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01T00:00:00
+    pub fn to_unix_timestamp(&self) -> u64 {
+        let days = days_from_civil(
+            i64::from(self.year),
+            u32::from(self.month),
+            u32::from(self.day),
+        );
+        let seconds =
+            u64::from(self.hour) * 3600 + u64::from(self.minute) * 60 + u64::from(self.second);
+        days as u64 * 86_400 + seconds
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Days between 1970-01-01 and the given date of the proleptic Gregorian calendar.
+///
+/// https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // shift the year to start in March so the leap day is the last day of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400; // [0, 399]
+    let month = i64::from(month);
+    let month_from_march = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_from_march + 2) / 5 + i64::from(day) - 1; // [0, 365]
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value & 0x0f) + (value >> 4) * 10
+}
+
+/// # Safety
+/// Reads a CMOS register.
+///
+/// Unsafe because the caller must ensure interrupts are disabled, otherwise the
+/// RTC interrupt handler can change the selected register between both port accesses.
+unsafe fn read_register(register: u8) -> u8 {
+    let mut address: Port<u8> = Port::new(CMOS_ADDRESS);
+    let mut data: Port<u8> = Port::new(CMOS_DATA);
+    address.write(register);
+    data.read()
+}
+
+/// # Safety
+/// Writes a CMOS register, same requirements as `read_register`.
+unsafe fn write_register(register: u8, value: u8) {
+    let mut address: Port<u8> = Port::new(CMOS_ADDRESS);
+    let mut data: Port<u8> = Port::new(CMOS_DATA);
+    address.write(register);
+    data.write(value);
+}
+
+/// Raw register values of a single read, still in the format set by status B
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+/// # Safety
+/// Same requirements as `read_register`.
+unsafe fn read_raw_time() -> RawTime {
+    // an update cycle takes ~2ms and the registers are garbage while it runs
+    while read_register(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+    RawTime {
+        second: read_register(REG_SECONDS),
+        minute: read_register(REG_MINUTES),
+        hour: read_register(REG_HOURS),
+        day: read_register(REG_DAY),
+        month: read_register(REG_MONTH),
+        year: read_register(REG_YEAR),
+        century: read_register(REG_CENTURY),
+    }
+}
+
+/// Converts raw register values into a `DateTime` according to the format flags in status B
+fn decode(raw: RawTime, status_b: u8) -> DateTime {
+    let binary = status_b & STATUS_B_BINARY != 0;
+    let convert = |value: u8| if binary { value } else { bcd_to_binary(value) };
+
+    // the PM flag is set on top of the (possibly BCD encoded) hour value
+    let pm = raw.hour & HOUR_PM != 0;
+    let mut hour = convert(raw.hour & !HOUR_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 AM is midnight and 12 PM is noon
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+
+    let century = match convert(raw.century) {
+        century @ 19..=21 => u16::from(century),
+        // no century register, assume we are running in the 21st century
+        _ => 20,
+    };
+
+    DateTime {
+        year: century * 100 + u16::from(convert(raw.year)),
+        month: convert(raw.month),
+        day: convert(raw.day),
+        hour,
+        minute: convert(raw.minute),
+        second: convert(raw.second),
+    }
+}
+
+/// Reads the current date and time from the RTC.
+///
+/// The registers are read until two consecutive reads agree, so an update
+/// cycle starting in the middle of a read can't produce a torn value.
+pub fn read_datetime() -> DateTime {
+    cpu_interrupts::without_interrupts(|| unsafe {
+        let mut last = read_raw_time();
+        loop {
+            let current = read_raw_time();
+            if current == last {
+                break;
+            }
+            last = current;
+        }
+        decode(last, read_register(REG_STATUS_B))
+    })
+}
+
+/// Reads the RTC once and anchors wall-clock time to the monotonic timer.
+///
+/// Must be called after `time::init`.
+pub fn init() {
+    let timestamp = read_datetime().to_unix_timestamp();
+    BOOT_TICKS.store(time::ticks(), Ordering::Relaxed);
+    BOOT_TIMESTAMP.store(timestamp, Ordering::Relaxed);
+}
+
+/// Current wall-clock time as a UNIX timestamp in seconds.
+///
+/// Derived from the timestamp read during `init` plus the monotonic timer,
+/// so it is cheap and never goes backwards.
+pub fn now() -> u64 {
+    let elapsed = time::ticks() - BOOT_TICKS.load(Ordering::Relaxed);
+    BOOT_TIMESTAMP.load(Ordering::Relaxed) + time::ticks_to_duration(elapsed).as_secs()
+}
+
+/// Enables the RTC periodic interrupt on IRQ 8.
+///
+/// The interrupt fires at `32768 >> (rate - 1)` Hz, `rate` must be in `3..=15`
+/// (8192 Hz down to 2 Hz).
+pub fn enable_periodic_interrupt(rate: u8) {
+    assert!((3..=15).contains(&rate), "invalid RTC rate {}", rate);
+
+    cpu_interrupts::without_interrupts(|| unsafe {
+        let status_a = read_register(REG_STATUS_A);
+        write_register(REG_STATUS_A, (status_a & 0xf0) | rate);
+        let status_b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, status_b | STATUS_B_PERIODIC_INTERRUPT);
+        // discard a pending interrupt, otherwise IRQ 8 is never raised again
+        read_register(REG_STATUS_C);
+        interrupts::unmask_irq(interrupts::InterruptIndex::Rtc);
+    });
+}
+
+/// Number of periodic interrupts received since `enable_periodic_interrupt`
+pub fn periodic_ticks() -> u64 {
+    PERIODIC_TICKS.load(Ordering::Relaxed)
+}
+
+/// Called by the RTC interrupt handler.
+///
+/// Must not block or allocate.
+pub(crate) fn handle_interrupt() {
+    PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed);
+    // status C has to be read to acknowledge the interrupt
+    unsafe {
+        read_register(REG_STATUS_C);
+    }
+}
+
+#[test_case]
+fn test_unix_timestamp() {
+    let epoch = DateTime {
+        year: 1970,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+    };
+    assert_eq!(epoch.to_unix_timestamp(), 0);
+
+    let leap_day = DateTime {
+        year: 2024,
+        month: 2,
+        day: 29,
+        hour: 13,
+        minute: 37,
+        second: 42,
+    };
+    assert_eq!(leap_day.to_unix_timestamp(), 1_709_213_862);
+}
+
+#[test_case]
+fn test_decode_bcd_12_hour() {
+    let raw = RawTime {
+        second: 0x59,
+        minute: 0x30,
+        hour: HOUR_PM | 0x12, // 12 PM is noon
+        day: 0x31,
+        month: 0x12,
+        year: 0x25,
+        century: 0x20,
+    };
+    let datetime = decode(raw, 0);
+    assert_eq!(datetime.year, 2025);
+    assert_eq!(datetime.month, 12);
+    assert_eq!(datetime.day, 31);
+    assert_eq!(datetime.hour, 12);
+    assert_eq!(datetime.minute, 30);
+    assert_eq!(datetime.second, 59);
+
+    let midnight = RawTime { hour: 0x12, ..raw };
+    assert_eq!(decode(midnight, 0).hour, 0);
+}
@@ -1,18 +1,32 @@
 use core::fmt::Write;
 
+use crate::sync::IrqSpinLock;
 use lazy_static::lazy_static;
-use spin::Mutex;
 use uart_16550::SerialPort;
 use x86_64::instructions::interrupts;
 
+/// I/O port base of COM1
+const COM1: u16 = 0x3F8;
+
 lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+    pub static ref SERIAL1: IrqSpinLock<SerialPort> = {
+        let mut serial_port = unsafe { SerialPort::new(COM1) };
         serial_port.init();
-        Mutex::new(serial_port)
+        IrqSpinLock::new(serial_port)
     };
 }
 
+/// # Safety
+/// Returns a handle to COM1 that bypasses the `SERIAL1` lock.
+///
+/// Only meant for NMI and watchdog reports, where the interrupted code may
+/// hold the lock. Unsafe because output can interleave with concurrent prints.
+pub unsafe fn emergency_port() -> SerialPort {
+    // make sure the port has been initialized through `SERIAL1`
+    lazy_static::initialize(&SERIAL1);
+    SerialPort::new(COM1)
+}
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     interrupts::without_interrupts(|| {
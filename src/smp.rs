@@ -0,0 +1,280 @@
+use crate::{
+    acpi, apic, cpu, gdt, interrupts,
+    memory::{self, stack, tlb},
+    println, syscall,
+    task::executor::Executor,
+    time,
+};
+use core::{
+    arch::global_asm,
+    ptr,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+use x86_64::{
+    instructions::interrupts as cpu_interrupts,
+    structures::paging::{Mapper, Page, PageTableFlags as Flags, PhysFrame, Size4KiB},
+    VirtAddr,
+};
+
+// APPLICATION PROCESSOR STARTUP:
+//
+// Only the bootstrap processor (BSP) runs after power on, every other CPU waits
+// for an INIT IPI followed by two STARTUP IPIs (INIT-SIPI-SIPI). A STARTUP IPI
+// makes the AP execute in real mode at the page number given as its vector, so
+// the trampoline below is copied to a page below 1 MiB first.
+//
+// The trampoline walks the AP through the same modes the bootloader walked the BSP:
+// real mode → protected mode (temporary GDT) → long mode (kernel page table)
+// and then calls `ap_main` on a freshly allocated stack. APs are started one at
+// a time, so all of them share the arguments at the end of the trampoline.
+//
+// The thread scheduler and device interrupts still only run on the BSP. Each AP
+// runs an executor for shared tasks (see `task::executor`) and halts in between,
+// it only wakes up for IPIs.
+
+/// Size of the stack each AP starts on
+const AP_STACK_PAGES: u64 = 16;
+/// Ticks to wait after the INIT IPI, the specification asks for 10 ms
+const INIT_DELAY_TICKS: u64 = 2;
+/// Ticks to wait for an AP to come online before giving up on it
+const STARTUP_TIMEOUT_TICKS: u64 = 20;
+
+/// Number of CPUs that finished initialization, including the BSP
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+/// Ids of the online CPUs, one bit each, empty until `init`
+static ONLINE_MASK: AtomicU64 = AtomicU64::new(0);
+
+/// Arguments of the trampoline, the layout must match `ap_trampoline_args`
+#[repr(C)]
+struct TrampolineArgs {
+    /// Kernel level 4 table, must be below 4 GiB as it is loaded in 32-bit mode
+    cr3: u64,
+    stack: u64,
+    entry: u64,
+    cpu: u64,
+}
+
+global_asm!(
+    r#"
+    .global ap_trampoline_start
+    .global ap_trampoline_args
+    .global ap_trampoline_end
+
+    .code16
+ap_trampoline_start:
+    cli
+    cld
+    movw %cs, %ax
+    movw %ax, %ds
+    movw %ax, %ss
+    movw $0x1000, %sp
+    # ebx = physical address of the trampoline
+    xorl %ebx, %ebx
+    movw %cs, %bx
+    shll $4, %ebx
+    # the GDT pointer needs a linear address, only known at runtime
+    leal (ap_gdt - ap_trampoline_start)(%ebx), %eax
+    movl %eax, (ap_gdt_pointer - ap_trampoline_start + 2)
+    lgdtl (ap_gdt_pointer - ap_trampoline_start)
+    movl %cr0, %eax
+    orl $1, %eax
+    movl %eax, %cr0
+    pushl $0x08
+    leal (ap_protected_mode - ap_trampoline_start)(%ebx), %eax
+    pushl %eax
+    lretl
+
+    .code32
+ap_protected_mode:
+    movw $0x10, %ax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+    movw %ax, %fs
+    movw %ax, %gs
+    leal 0x1000(%ebx), %esp
+    # PAE
+    movl %cr4, %eax
+    orl $(1 << 5), %eax
+    movl %eax, %cr4
+    movl (ap_trampoline_args - ap_trampoline_start)(%ebx), %eax
+    movl %eax, %cr3
+    # EFER: long mode enable, no-execute enable
+    movl $0xc0000080, %ecx
+    rdmsr
+    orl $((1 << 8) | (1 << 11)), %eax
+    wrmsr
+    # paging and write protect
+    movl %cr0, %eax
+    orl $((1 << 31) | (1 << 16)), %eax
+    movl %eax, %cr0
+    pushl $0x18
+    leal (ap_long_mode - ap_trampoline_start)(%ebx), %eax
+    pushl %eax
+    lretl
+
+    .code64
+ap_long_mode:
+    movl %ebx, %ebx
+    movq (ap_trampoline_args - ap_trampoline_start + 8)(%rbx), %rsp
+    movq (ap_trampoline_args - ap_trampoline_start + 24)(%rbx), %rdi
+    xorl %ebp, %ebp
+    callq *(ap_trampoline_args - ap_trampoline_start + 16)(%rbx)
+    ud2
+
+    .balign 8
+ap_gdt:
+    .quad 0
+    # 32-bit code, 32-bit data, 64-bit code
+    .quad 0x00cf9a000000ffff
+    .quad 0x00cf92000000ffff
+    .quad 0x00af9a000000ffff
+ap_gdt_pointer:
+    .word 4 * 8 - 1
+    .long 0
+
+    .balign 8
+ap_trampoline_args:
+    .fill 4, 8, 0
+ap_trampoline_end:
+"#,
+    options(att_syntax)
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_args: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// Number of CPUs running the kernel
+pub fn cpu_count() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// Ids of the online CPUs, bit `n` is set if CPU `n` is online
+pub fn online_mask() -> u64 {
+    ONLINE_MASK.load(Ordering::Acquire)
+}
+
+/// Starts every enabled CPU listed in the ACPI MADT and returns the number of
+/// CPUs online afterwards.
+///
+/// Must be called once on the BSP after `memory::install`, with interrupts
+/// enabled so the startup delays can be timed.
+pub fn init() -> usize {
+    let madt = match acpi::madt() {
+        Ok(madt) => madt,
+        Err(err) => {
+            println!("SMP disabled: {:?}", err);
+            return cpu_count();
+        }
+    };
+    apic::init(madt.local_apic_address);
+    ONLINE_MASK.fetch_or(1 << cpu::current_id(), Ordering::AcqRel);
+
+    let frame = memory::with_kernel_memory(|memory| memory.frame_allocator.low_memory_frame())
+        .expect("no low memory for the AP trampoline");
+    install_trampoline(frame);
+
+    let bsp = apic::id();
+    for cpu in madt.cpus.iter().filter(|cpu| cpu.enabled) {
+        if cpu.apic_id == bsp {
+            continue;
+        }
+        if usize::from(cpu.apic_id) >= cpu::MAX_CPUS {
+            println!("SMP: ignoring CPU with APIC id {}", cpu.apic_id);
+            continue;
+        }
+        if !start_cpu(frame, cpu.apic_id) {
+            println!("SMP: CPU with APIC id {} did not start", cpu.apic_id);
+        }
+    }
+    cpu_count()
+}
+
+/// Copies the trampoline to `frame` and identity maps it, so it keeps running
+/// when the AP enables paging.
+fn install_trampoline(frame: PhysFrame) {
+    let start = &raw const ap_trampoline_start;
+    let len = unsafe { (&raw const ap_trampoline_end).offset_from(start) } as usize;
+    assert!(len <= 4096, "AP trampoline larger than a page");
+    let dest = memory::phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+    unsafe { ptr::copy_nonoverlapping(start, dest, len) };
+
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+    memory::with_kernel_memory(|memory| match memory.mapper.translate_page(page) {
+        Ok(mapped) => assert_eq!(mapped, frame, "trampoline page already in use"),
+        Err(_) => unsafe {
+            memory
+                .mapper
+                .map_to(
+                    page,
+                    frame,
+                    Flags::PRESENT | Flags::WRITABLE,
+                    &mut memory.frame_allocator,
+                )
+                .expect("mapping the AP trampoline failed")
+                .flush();
+        },
+    });
+}
+
+/// Sends INIT-SIPI-SIPI to the CPU `apic_id` and waits for it to come online.
+fn start_cpu(frame: PhysFrame, apic_id: u8) -> bool {
+    let stack = stack::alloc_stack(AP_STACK_PAGES).expect("AP stack allocation failed");
+    let cr3 = memory::with_kernel_memory(|memory| memory.kernel_level_4_frame.start_address());
+    assert!(cr3.as_u64() < 1 << 32, "kernel page table above 4 GiB");
+
+    let offset =
+        unsafe { (&raw const ap_trampoline_args).offset_from(&raw const ap_trampoline_start) };
+    let args = (memory::phys_to_virt(frame.start_address()) + offset as u64)
+        .as_mut_ptr::<TrampolineArgs>();
+    unsafe {
+        args.write_volatile(TrampolineArgs {
+            cr3: cr3.as_u64(),
+            stack: stack.end().as_u64(),
+            entry: ap_main as *const () as u64,
+            cpu: u64::from(apic_id),
+        });
+    }
+
+    let online = cpu_count();
+    apic::send_init(apic_id);
+    wait_ticks(INIT_DELAY_TICKS);
+    for _ in 0..2 {
+        apic::send_startup(apic_id, frame);
+        let deadline = time::ticks() + STARTUP_TIMEOUT_TICKS;
+        while time::ticks() < deadline {
+            if cpu_count() > online {
+                return true;
+            }
+            core::hint::spin_loop();
+        }
+    }
+    false
+}
+
+/// Waits at least `ticks` full timer ticks.
+fn wait_ticks(ticks: u64) {
+    let deadline = time::ticks() + ticks + 1;
+    while time::ticks() < deadline {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// First Rust code run by an AP, called by the trampoline with the AP's id.
+extern "C" fn ap_main(cpu: u64) -> ! {
+    cpu::init();
+    gdt::init_ap();
+    syscall::init();
+    interrupts::init_idt();
+    apic::enable();
+    tlb::init();
+    debug_assert_eq!(cpu, cpu::current_id() as u64);
+    ONLINE_MASK.fetch_or(1 << cpu, Ordering::AcqRel);
+    ONLINE.fetch_add(1, Ordering::AcqRel);
+
+    cpu_interrupts::enable();
+    Executor::new().run()
+}
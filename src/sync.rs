@@ -0,0 +1,19 @@
+pub mod irq_spin_lock;
+pub mod lockdep;
+pub mod queue_lock;
+pub mod ticket_lock;
+
+pub use irq_spin_lock::{IrqSpinLock, IrqSpinLockGuard};
+pub use queue_lock::QueueLock;
+pub use ticket_lock::{TicketLock, TicketLockGuard};
+
+// KERNEL LOCKS:
+//
+// TicketLock   FIFO spinlock, waiters are served in arrival order
+// QueueLock    MCS lock, each waiter spins on its own cache line
+// IrqSpinLock  ticket lock that also disables interrupts while held, for data
+//              shared with interrupt handlers
+//
+// `spin::Mutex` is neither fair nor interrupt-safe: if an interrupt handler
+// takes a lock the interrupted code holds, the CPU deadlocks. Every lock here
+// reports to `lockdep`, which is off unless enabled.
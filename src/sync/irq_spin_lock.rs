@@ -0,0 +1,117 @@
+use super::{lockdep, ticket_lock::RawTicketLock};
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    panic::Location,
+};
+use x86_64::instructions::interrupts;
+
+/// Ticket lock that keeps interrupts disabled on the owning CPU while held.
+///
+/// Use it for data an interrupt handler touches: the handler can never
+/// interrupt the owner and spin on the lock forever. The previous interrupt
+/// state is restored when the guard is dropped, so it nests.
+pub struct IrqSpinLock<T> {
+    raw: RawTicketLock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for IrqSpinLock<T> {}
+unsafe impl<T: Send> Sync for IrqSpinLock<T> {}
+
+impl<T> IrqSpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        IrqSpinLock {
+            raw: RawTicketLock::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[track_caller]
+    pub fn lock(&self) -> IrqSpinLockGuard<'_, T> {
+        let interrupts_were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        lockdep::acquire(self.id(), Location::caller());
+        self.raw.lock();
+        IrqSpinLockGuard {
+            lock: self,
+            interrupts_were_enabled,
+        }
+    }
+
+    /// Takes the lock if it is free, never spins
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<'_, T>> {
+        let interrupts_were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        if !self.raw.try_lock() {
+            if interrupts_were_enabled {
+                interrupts::enable();
+            }
+            return None;
+        }
+        lockdep::acquire(self.id(), Location::caller());
+        Some(IrqSpinLockGuard {
+            lock: self,
+            interrupts_were_enabled,
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.raw.is_locked()
+    }
+
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+impl<T> Drop for IrqSpinLock<T> {
+    fn drop(&mut self) {
+        lockdep::forget(self.id());
+    }
+}
+
+pub struct IrqSpinLockGuard<'a, T> {
+    lock: &'a IrqSpinLock<T>,
+    interrupts_were_enabled: bool,
+}
+
+impl<T> Deref for IrqSpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for IrqSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for IrqSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.raw.unlock();
+        lockdep::release(self.lock.id());
+        if self.interrupts_were_enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+#[test_case]
+fn test_irq_spin_lock_restores_interrupts() {
+    let lock = IrqSpinLock::new(0);
+    assert!(interrupts::are_enabled());
+    {
+        let _outer = lock.lock();
+        assert!(!interrupts::are_enabled());
+        let inner = IrqSpinLock::new(0);
+        drop(inner.lock());
+        // the inner guard found interrupts disabled, so it leaves them disabled
+        assert!(!interrupts::are_enabled());
+    }
+    assert!(interrupts::are_enabled());
+}
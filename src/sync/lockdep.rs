@@ -0,0 +1,295 @@
+use crate::{interrupts, percpu, serial};
+use core::{
+    fmt::{self, Write},
+    panic::Location,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use spin::Mutex;
+use x86_64::instructions::interrupts as cpu_interrupts;
+
+// LOCK DEPENDENCY CHECKER:
+//
+// While enabled, every acquisition of a `sync` lock is checked against what was
+// seen so far, so a deadlock is reported the first time the conflicting code
+// paths run, even if they never actually race:
+//
+// - taking B while holding A records the order A → B; if B → … → A was recorded
+//   before, two CPUs taking the locks in opposite order can deadlock
+// - a lock taken by an interrupt handler and elsewhere with interrupts enabled
+//   deadlocks once the interrupt arrives while the lock is held
+// - taking a lock the CPU already holds deadlocks right away
+//
+// Locks are identified by address. Dropping a lock forgets it, and only the
+// first `MAX_LOCKS` locks seen are tracked. The internal state is guarded by
+// `spin::Mutex`es, which are not checked themselves.
+
+/// Number of distinct locks tracked, one bit each in `Graph::after`
+const MAX_LOCKS: usize = 64;
+/// Maximum nesting depth tracked per CPU
+const MAX_HELD: usize = 16;
+
+/// A lock as reported by the checker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockInfo {
+    pub address: usize,
+    /// Where the lock was first taken
+    pub location: &'static Location<'static>,
+}
+
+impl fmt::Display for LockInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "lock {:#x} (taken at {})", self.address, self.location)
+    }
+}
+
+/// A potential deadlock found by the checker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Report {
+    /// `lock` was taken again by the CPU holding it
+    Recursive { lock: LockInfo },
+    /// `acquired` was taken while holding `held`, but `held` was taken while
+    /// holding `acquired` before
+    OrderInversion { held: LockInfo, acquired: LockInfo },
+    /// `lock` is taken in interrupt handlers and with interrupts enabled
+    IrqUnsafe { lock: LockInfo },
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Report::Recursive { lock } => write!(f, "recursive locking of {}", lock),
+            Report::OrderInversion { held, acquired } => write!(
+                f,
+                "lock order inversion: {} taken while holding {}, the reverse order was seen before",
+                acquired, held
+            ),
+            Report::IrqUnsafe { lock } => write!(
+                f,
+                "{} is taken in interrupt context and with interrupts enabled",
+                lock
+            ),
+        }
+    }
+}
+
+struct Class {
+    info: LockInfo,
+    used_in_interrupt: bool,
+    used_with_interrupts_enabled: bool,
+    irq_reported: bool,
+}
+
+struct Graph {
+    classes: [Option<Class>; MAX_LOCKS],
+    /// Bit `j` of `after[i]`: lock `j` was taken while holding lock `i`
+    after: [u64; MAX_LOCKS],
+    /// Bit `j` of `reported[i]`: the inversion of `i` → `j` was already reported
+    reported: [u64; MAX_LOCKS],
+}
+
+impl Graph {
+    fn index_of(&self, address: usize) -> Option<usize> {
+        self.classes
+            .iter()
+            .position(|class| class.as_ref().is_some_and(|c| c.info.address == address))
+    }
+
+    fn index_or_insert(
+        &mut self,
+        address: usize,
+        location: &'static Location<'static>,
+    ) -> Option<usize> {
+        if let Some(index) = self.index_of(address) {
+            return Some(index);
+        }
+        let index = self.classes.iter().position(Option::is_none)?;
+        self.classes[index] = Some(Class {
+            info: LockInfo { address, location },
+            used_in_interrupt: false,
+            used_with_interrupts_enabled: false,
+            irq_reported: false,
+        });
+        Some(index)
+    }
+
+    fn info(&self, index: usize) -> LockInfo {
+        self.classes[index].as_ref().expect("lock not tracked").info
+    }
+
+    /// Whether `to` was ever taken, directly or transitively, while holding `from`
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut visited = 1u64 << from;
+        let mut frontier = visited;
+        while frontier != 0 {
+            let mut next = 0;
+            for index in 0..MAX_LOCKS {
+                if frontier & (1 << index) != 0 {
+                    next |= self.after[index];
+                }
+            }
+            if next & (1 << to) != 0 {
+                return true;
+            }
+            frontier = next & !visited;
+            visited |= next;
+        }
+        false
+    }
+}
+
+struct HeldLocks {
+    addresses: [usize; MAX_HELD],
+    len: usize,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static REPORTS: AtomicU64 = AtomicU64::new(0);
+static LAST_REPORT: Mutex<Option<Report>> = Mutex::new(None);
+static GRAPH: Mutex<Graph> = Mutex::new(Graph {
+    classes: [const { None }; MAX_LOCKS],
+    after: [0; MAX_LOCKS],
+    reported: [0; MAX_LOCKS],
+});
+
+percpu! {
+    /// Locks held by each CPU, in acquisition order
+    static HELD: Mutex<HeldLocks> = Mutex::new(HeldLocks {
+        addresses: [0; MAX_HELD],
+        len: 0,
+    });
+}
+
+/// Starts checking lock acquisitions. Locks already held are not known to the
+/// checker, so enable it early or while no `sync` lock is held.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Number of potential deadlocks reported so far
+pub fn report_count() -> u64 {
+    REPORTS.load(Ordering::Relaxed)
+}
+
+pub fn last_report() -> Option<Report> {
+    *LAST_REPORT.lock()
+}
+
+/// Called by a lock before it spins for `address`.
+pub(super) fn acquire(address: usize, location: &'static Location<'static>) {
+    if !is_enabled() {
+        return;
+    }
+    let interrupts_enabled = cpu_interrupts::are_enabled();
+    let reports = cpu_interrupts::without_interrupts(|| {
+        let mut held = HELD.get().lock();
+        let reports = check(&held, address, location, interrupts_enabled);
+        if held.len < MAX_HELD {
+            let len = held.len;
+            held.addresses[len] = address;
+            held.len += 1;
+        }
+        reports
+    });
+    for report in reports.into_iter().flatten() {
+        REPORTS.fetch_add(1, Ordering::Relaxed);
+        cpu_interrupts::without_interrupts(|| *LAST_REPORT.lock() = Some(report));
+        // the lock being checked may be `SERIAL1` itself
+        let mut port = unsafe { serial::emergency_port() };
+        let _ = writeln!(port, "lockdep: {}", report);
+    }
+}
+
+fn check(
+    held: &HeldLocks,
+    address: usize,
+    location: &'static Location<'static>,
+    interrupts_enabled: bool,
+) -> [Option<Report>; 2] {
+    let mut graph = GRAPH.lock();
+    let Some(index) = graph.index_or_insert(address, location) else {
+        return [None, None];
+    };
+    // at most one context and one ordering problem per acquisition
+    let mut context_report = None;
+    let mut order_report = None;
+
+    let in_interrupt = interrupts::in_interrupt();
+    let class = graph.classes[index].as_mut().expect("lock not tracked");
+    class.used_in_interrupt |= in_interrupt;
+    class.used_with_interrupts_enabled |= interrupts_enabled && !in_interrupt;
+    if class.used_in_interrupt && class.used_with_interrupts_enabled && !class.irq_reported {
+        class.irq_reported = true;
+        context_report = Some(Report::IrqUnsafe { lock: class.info });
+    }
+
+    for &held_address in &held.addresses[..held.len] {
+        let Some(held_index) = graph.index_of(held_address) else {
+            continue;
+        };
+        if held_index == index {
+            order_report = Some(Report::Recursive {
+                lock: graph.info(index),
+            });
+            break;
+        }
+        if graph.after[held_index] & (1 << index) != 0 {
+            continue;
+        }
+        if order_report.is_none()
+            && graph.reaches(index, held_index)
+            && graph.reported[held_index] & (1 << index) == 0
+        {
+            graph.reported[held_index] |= 1 << index;
+            order_report = Some(Report::OrderInversion {
+                held: graph.info(held_index),
+                acquired: graph.info(index),
+            });
+        }
+        graph.after[held_index] |= 1 << index;
+    }
+    [context_report, order_report]
+}
+
+/// Called by a lock after releasing `address`.
+pub(super) fn release(address: usize) {
+    if !is_enabled() {
+        return;
+    }
+    cpu_interrupts::without_interrupts(|| {
+        let mut held = HELD.get().lock();
+        let len = held.len;
+        // guards are usually, but not necessarily, dropped in reverse order
+        if let Some(position) = held.addresses[..len].iter().rposition(|&a| a == address) {
+            held.addresses.copy_within(position + 1..len, position);
+            held.len -= 1;
+        }
+    });
+}
+
+/// Called when a lock is dropped, so another lock at the same address starts
+/// without history.
+pub(super) fn forget(address: usize) {
+    if !is_enabled() {
+        return;
+    }
+    cpu_interrupts::without_interrupts(|| {
+        let mut graph = GRAPH.lock();
+        if let Some(index) = graph.index_of(address) {
+            graph.classes[index] = None;
+            graph.after[index] = 0;
+            graph.reported[index] = 0;
+            for i in 0..MAX_LOCKS {
+                graph.after[i] &= !(1 << index);
+                graph.reported[i] &= !(1 << index);
+            }
+        }
+    });
+}
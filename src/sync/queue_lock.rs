@@ -0,0 +1,112 @@
+use super::lockdep;
+use core::{
+    cell::UnsafeCell,
+    hint,
+    panic::Location,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+};
+
+/// A waiter's entry in the queue, lives on the waiter's stack
+#[repr(align(64))]
+struct Node {
+    next: AtomicPtr<Node>,
+    locked: AtomicBool,
+}
+
+/// MCS queue lock: waiters form a linked list and each spins on a flag in its
+/// own node, so a release only touches the cache line of the next waiter.
+///
+/// Fair like `TicketLock`, but scales better when many CPUs wait. The node
+/// must stay in place while queued, so the lock is only available for the
+/// duration of a closure instead of through a guard.
+pub struct QueueLock<T> {
+    /// Last waiter in the queue, null if the lock is free
+    tail: AtomicPtr<Node>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for QueueLock<T> {}
+unsafe impl<T: Send> Sync for QueueLock<T> {}
+
+impl<T> QueueLock<T> {
+    pub const fn new(data: T) -> Self {
+        QueueLock {
+            tail: AtomicPtr::new(ptr::null_mut()),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Runs `f` while holding the lock
+    #[track_caller]
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        lockdep::acquire(self.id(), Location::caller());
+        let node = Node {
+            next: AtomicPtr::new(ptr::null_mut()),
+            locked: AtomicBool::new(true),
+        };
+        let node_ptr = &node as *const Node as *mut Node;
+
+        let previous = self.tail.swap(node_ptr, Ordering::AcqRel);
+        if !previous.is_null() {
+            // queue behind the previous waiter and wait for it to hand over
+            unsafe { (*previous).next.store(node_ptr, Ordering::Release) };
+            while node.locked.load(Ordering::Acquire) {
+                hint::spin_loop();
+            }
+        }
+
+        let result = f(unsafe { &mut *self.data.get() });
+
+        let mut next = node.next.load(Ordering::Acquire);
+        if next.is_null() {
+            // no known successor: free the lock, unless one is just enqueueing
+            if self
+                .tail
+                .compare_exchange(
+                    node_ptr,
+                    ptr::null_mut(),
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                lockdep::release(self.id());
+                return result;
+            }
+            while next.is_null() {
+                hint::spin_loop();
+                next = node.next.load(Ordering::Acquire);
+            }
+        }
+        unsafe { (*next).locked.store(false, Ordering::Release) };
+        lockdep::release(self.id());
+        result
+    }
+
+    pub fn is_locked(&self) -> bool {
+        !self.tail.load(Ordering::Relaxed).is_null()
+    }
+
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+impl<T> Drop for QueueLock<T> {
+    fn drop(&mut self) {
+        lockdep::forget(self.id());
+    }
+}
+
+#[test_case]
+fn test_queue_lock() {
+    let lock = QueueLock::new(41);
+    let value = lock.with(|value| {
+        *value += 1;
+        *value
+    });
+    assert_eq!(value, 42);
+    assert!(!lock.is_locked());
+    assert!(lock.with(|_| lock.is_locked()));
+}
@@ -0,0 +1,135 @@
+use super::lockdep;
+use core::{
+    cell::UnsafeCell,
+    hint,
+    ops::{Deref, DerefMut},
+    panic::Location,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The bare ticket lock, shared with `IrqSpinLock`
+pub(super) struct RawTicketLock {
+    /// Ticket handed to the next CPU that wants the lock
+    next_ticket: AtomicUsize,
+    /// Ticket of the current owner
+    now_serving: AtomicUsize,
+}
+
+impl RawTicketLock {
+    pub(super) const fn new() -> Self {
+        RawTicketLock {
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+        }
+    }
+
+    pub(super) fn lock(&self) {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            hint::spin_loop();
+        }
+    }
+
+    pub(super) fn try_lock(&self) -> bool {
+        let ticket = self.now_serving.load(Ordering::Relaxed);
+        self.next_ticket
+            .compare_exchange(ticket, ticket + 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    pub(super) fn unlock(&self) {
+        self.now_serving.fetch_add(1, Ordering::Release);
+    }
+
+    pub(super) fn is_locked(&self) -> bool {
+        self.next_ticket.load(Ordering::Relaxed) != self.now_serving.load(Ordering::Relaxed)
+    }
+}
+
+/// Spinlock granting the lock in the order it was requested, so no CPU can
+/// starve while others keep re-acquiring it.
+pub struct TicketLock<T> {
+    raw: RawTicketLock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for TicketLock<T> {}
+unsafe impl<T: Send> Sync for TicketLock<T> {}
+
+impl<T> TicketLock<T> {
+    pub const fn new(data: T) -> Self {
+        TicketLock {
+            raw: RawTicketLock::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[track_caller]
+    pub fn lock(&self) -> TicketLockGuard<'_, T> {
+        lockdep::acquire(self.id(), Location::caller());
+        self.raw.lock();
+        TicketLockGuard { lock: self }
+    }
+
+    /// Takes the lock if it is free, never spins
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<TicketLockGuard<'_, T>> {
+        if !self.raw.try_lock() {
+            return None;
+        }
+        lockdep::acquire(self.id(), Location::caller());
+        Some(TicketLockGuard { lock: self })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.raw.is_locked()
+    }
+
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+impl<T> Drop for TicketLock<T> {
+    fn drop(&mut self) {
+        lockdep::forget(self.id());
+    }
+}
+
+pub struct TicketLockGuard<'a, T> {
+    lock: &'a TicketLock<T>,
+}
+
+impl<T> Deref for TicketLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for TicketLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for TicketLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.raw.unlock();
+        lockdep::release(self.lock.id());
+    }
+}
+
+#[test_case]
+fn test_ticket_lock() {
+    let lock = TicketLock::new(1);
+    {
+        let mut guard = lock.lock();
+        *guard += 1;
+        assert!(lock.is_locked());
+        assert!(lock.try_lock().is_none());
+    }
+    assert!(!lock.is_locked());
+    assert_eq!(*lock.try_lock().expect("lock should be free"), 2);
+}
@@ -0,0 +1,159 @@
+mod handlers;
+pub mod user_ptr;
+
+use crate::{
+    abi::{Errno, Syscall},
+    cpu, gdt, process, userspace,
+};
+use core::{arch::naked_asm, sync::atomic::Ordering};
+use x86_64::{
+    instructions::interrupts,
+    registers::{
+        model_specific::{Efer, EferFlags, LStar, SFMask, Star},
+        rflags::RFlags,
+    },
+    VirtAddr,
+};
+
+// SYSCALL/SYSRET:
+//
+// `syscall` jumps to the address in LSTAR with CS/SS taken from STAR, saves the
+// user RIP in rcx and RFLAGS in r11, and clears the RFLAGS bits set in SFMASK.
+// Unlike an interrupt it does not switch stacks, so `syscall_entry` swaps to the
+// kernel stack itself before touching memory through rsp.
+//
+// Register convention (same as Linux):
+// rax        → syscall number, return value
+// rdi, rsi, rdx, r10, r8, r9 → arguments 0-5
+// rcx, r11   → clobbered
+
+/// User register state saved by `syscall_entry`, in stack order
+#[derive(Debug)]
+#[repr(C)]
+pub struct SyscallFrame {
+    pub r9: u64,
+    pub r8: u64,
+    pub r10: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rax: u64,
+    pub rflags: u64,
+    pub rip: u64,
+    pub rsp: u64,
+}
+
+impl SyscallFrame {
+    pub fn number(&self) -> u64 {
+        self.rax
+    }
+
+    /// Arguments in calling convention order
+    pub fn args(&self) -> [u64; 6] {
+        [self.rdi, self.rsi, self.rdx, self.r10, self.r8, self.r9]
+    }
+}
+
+/// Enables `syscall`/`sysret` on the calling CPU and points LSTAR at
+/// `syscall_entry`.
+///
+/// Must be called by every CPU after its `gdt::init` or `gdt::init_ap`.
+pub fn init() {
+    let selectors = gdt::selectors();
+    Star::write(
+        selectors.user_code_selector,
+        selectors.user_data_selector,
+        selectors.code_selector,
+        selectors.data_selector,
+    )
+    .expect("GDT layout incompatible with SYSRET");
+    LStar::write(VirtAddr::from_ptr(syscall_entry as *const ()));
+    // handlers start with interrupts disabled until they are on the kernel stack
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS));
+    }
+}
+
+/// Sets the stack `syscall_entry` switches to on the calling CPU, kept equal to
+/// RSP0 of its TSS, see `gdt::set_kernel_stack`.
+pub(crate) fn set_kernel_stack(stack_end: VirtAddr) {
+    cpu::area()
+        .syscall_stack
+        .store(stack_end.as_u64(), Ordering::Relaxed);
+}
+
+#[unsafe(naked)]
+unsafe extern "C" fn syscall_entry() {
+    naked_asm!(
+        // GS holds the user's base until here, see `cpu`
+        "swapgs",
+        // interrupts are masked by SFMASK, so the scratch slot can't be clobbered
+        "mov gs:[{user_stack}], rsp",
+        "mov rsp, gs:[{kernel_stack}]",
+        // build a `SyscallFrame`
+        "push qword ptr gs:[{user_stack}]",
+        "push rcx",
+        "push r11",
+        "push rax",
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push r10",
+        "push r8",
+        "push r9",
+        // 10 pushes keep the stack 16 byte aligned for the call
+        "mov rdi, rsp",
+        "call {handler}",
+        // rax holds the return value
+        "pop r9",
+        "pop r8",
+        "pop r10",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
+        "add rsp, 8",
+        "pop r11",
+        "pop rcx",
+        "pop rsp",
+        "swapgs",
+        "sysretq",
+        user_stack = const cpu::USER_STACK_OFFSET,
+        kernel_stack = const cpu::SYSCALL_STACK_OFFSET,
+        handler = sym syscall_handler,
+    );
+}
+
+type Handler = fn(&mut SyscallFrame) -> Result<u64, Errno>;
+
+/// Syscall handlers, indexed by `abi::Syscall` number
+static SYSCALL_TABLE: [Handler; Syscall::COUNT] = [
+    handlers::write,
+    handlers::read,
+    handlers::exit,
+    handlers::sleep,
+    handlers::time,
+    handlers::mmap,
+    handlers::munmap,
+];
+
+extern "C" fn syscall_handler(frame: &mut SyscallFrame) -> u64 {
+    // on the kernel stack now, so interrupts can be served during the syscall
+    interrupts::enable();
+    if process::kill_requested() {
+        // the exit code is ignored, `process::wait` reports `ExitStatus::Killed`
+        userspace::exit(0);
+    }
+    let result = match SYSCALL_TABLE.get(frame.number() as usize) {
+        Some(handler) => handler(frame),
+        None => Err(Errno::NoSys),
+    };
+    // `syscall_entry` switches back to the user stack before `sysretq`
+    // restores the user flags, no interrupt may arrive in between
+    interrupts::disable();
+
+    match result {
+        Ok(value) => value,
+        Err(errno) => errno.to_return_value(),
+    }
+}
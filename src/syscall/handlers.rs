@@ -0,0 +1,192 @@
+use super::{
+    user_ptr::{copy_from_user, copy_to_user},
+    SyscallFrame,
+};
+use crate::{
+    abi::{self, Errno},
+    deferred,
+    memory::{self, tlb::TlbBatch, user},
+    print,
+    process::{self, handle::Handle},
+    rtc, serial_print,
+    task::keyboard,
+    thread, time, userspace,
+};
+use core::time::Duration;
+use x86_64::{
+    instructions::hlt,
+    structures::paging::{mapper::TranslateResult, PageTableFlags, Translate},
+};
+
+/// Size of the on-stack buffer user data is copied through
+const CHUNK_SIZE: usize = 256;
+
+/// Lets interrupts and deferred work run until the next interrupt arrives.
+///
+/// Interrupts are enabled during syscalls, so `hlt` returns on the next one.
+fn wait_for_interrupt() {
+    deferred::run_pending();
+    if thread::others_ready() {
+        thread::yield_now();
+    } else {
+        hlt();
+    }
+}
+
+pub(super) fn write(frame: &mut SyscallFrame) -> Result<u64, Errno> {
+    let [fd, buf, len, ..] = frame.args();
+    let mirror_to_serial = match process::handle(fd)? {
+        Handle::Console => false,
+        Handle::ConsoleAndSerial => true,
+        Handle::Keyboard => return Err(Errno::BadF),
+    };
+
+    let mut chunk = [0u8; CHUNK_SIZE];
+    let mut written = 0;
+    while written < len {
+        let size = (len - written).min(CHUNK_SIZE as u64) as usize;
+        copy_from_user(&mut chunk[..size], buf + written)?;
+        for &byte in &chunk[..size] {
+            // the VGA buffer only knows ASCII anyway
+            print!("{}", byte as char);
+            if mirror_to_serial {
+                serial_print!("{}", byte as char);
+            }
+        }
+        written += size as u64;
+    }
+    Ok(written)
+}
+
+pub(super) fn read(frame: &mut SyscallFrame) -> Result<u64, Errno> {
+    let [fd, buf, len, ..] = frame.args();
+    if process::handle(fd)? != Handle::Keyboard {
+        return Err(Errno::BadF);
+    }
+    if len == 0 {
+        return Ok(0);
+    }
+
+    // block for the first byte, then take what is already buffered
+    let mut chunk = [0u8; CHUNK_SIZE];
+    let mut read = 0;
+    while read < len {
+        let size = (len - read).min(CHUNK_SIZE as u64) as usize;
+        let count = keyboard::read_bytes(&mut chunk[..size]);
+        if count == 0 {
+            if read > 0 {
+                break;
+            }
+            wait_for_interrupt();
+            continue;
+        }
+        copy_to_user(buf + read, &chunk[..count])?;
+        read += count as u64;
+    }
+    Ok(read)
+}
+
+pub(super) fn exit(frame: &mut SyscallFrame) -> Result<u64, Errno> {
+    let [code, ..] = frame.args();
+    userspace::exit(code as i32)
+}
+
+pub(super) fn sleep(frame: &mut SyscallFrame) -> Result<u64, Errno> {
+    let [millis, ..] = frame.args();
+    thread::sleep(Duration::from_millis(millis));
+    Ok(0)
+}
+
+pub(super) fn time(frame: &mut SyscallFrame) -> Result<u64, Errno> {
+    let [clock, ..] = frame.args();
+    match clock {
+        abi::CLOCK_REALTIME => Ok(rtc::now()),
+        abi::CLOCK_MONOTONIC => Ok(time::uptime().as_millis() as u64),
+        _ => Err(Errno::Inval),
+    }
+}
+
+/// Converts `mmap` protection bits into page table flags.
+///
+/// Rejects `prot` 0: present user pages are always readable.
+fn page_flags(prot: u64) -> Result<PageTableFlags, Errno> {
+    if prot == 0 || prot & !(abi::PROT_READ | abi::PROT_WRITE | abi::PROT_EXEC) != 0 {
+        return Err(Errno::Inval);
+    }
+    let mut flags = PageTableFlags::empty();
+    if prot & abi::PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if prot & abi::PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    Ok(flags)
+}
+
+pub(super) fn mmap(frame: &mut SyscallFrame) -> Result<u64, Errno> {
+    let [addr, len, prot, ..] = frame.args();
+    let flags = page_flags(prot)?;
+    if len == 0 || addr % abi::PAGE_SIZE != 0 {
+        return Err(Errno::Inval);
+    }
+    let len = len
+        .checked_next_multiple_of(abi::PAGE_SIZE)
+        .ok_or(Errno::Inval)?;
+
+    let reserved = addr == 0;
+    let addr = if reserved {
+        // programs run without a process have to pick their addresses themselves
+        process::with_current(|process| process.reserve_mmap(len))
+            .flatten()
+            .ok_or(Errno::NoMem)?
+    } else if user::is_user_range(addr, len) {
+        addr
+    } else {
+        return Err(Errno::Inval);
+    };
+
+    let result = memory::with_active_mapper(|mapper, frame_allocator| {
+        let pages = user::page_range(addr, len);
+        // never replace an existing mapping
+        let occupied = pages.into_iter().any(|page| {
+            !matches!(
+                mapper.translate(page.start_address()),
+                TranslateResult::NotMapped
+            )
+        });
+        if occupied {
+            return Err(Errno::Inval);
+        }
+        // unmaps the pages it mapped before failing
+        user::map_zeroed(mapper, frame_allocator, pages, flags).map_err(|_| Errno::NoMem)
+    });
+    if let Err(errno) = result {
+        if reserved {
+            process::with_current(|process| process.release_mmap(addr, len));
+        }
+        return Err(errno);
+    }
+    Ok(addr)
+}
+
+pub(super) fn munmap(frame: &mut SyscallFrame) -> Result<u64, Errno> {
+    let [addr, len, ..] = frame.args();
+    if len == 0 || addr % abi::PAGE_SIZE != 0 || !user::is_user_range(addr, len) {
+        return Err(Errno::Inval);
+    }
+    let mut tlb = TlbBatch::active();
+    memory::with_active_mapper(|mapper, frame_allocator| {
+        user::unmap(
+            mapper,
+            frame_allocator,
+            user::page_range(addr, len),
+            &mut tlb,
+        )
+    });
+    // only after the kernel memory lock is released, see `TlbBatch`
+    drop(tlb);
+    if let Some(len) = len.checked_next_multiple_of(abi::PAGE_SIZE) {
+        process::with_current(|process| process.release_mmap(addr, len));
+    }
+    Ok(0)
+}
@@ -0,0 +1,33 @@
+use crate::{
+    abi::Errno,
+    memory::{self, user},
+};
+use core::slice;
+
+/// Checks that the user buffer `addr..addr + len` is mapped accessible to ring 3
+/// in the active address space, and writable if `write` is set.
+pub fn validate(addr: u64, len: u64, write: bool) -> Result<(), Errno> {
+    let accessible =
+        memory::with_active_mapper(|mapper, _| user::is_accessible(mapper, addr, len, write));
+    if accessible {
+        Ok(())
+    } else {
+        Err(Errno::Fault)
+    }
+}
+
+/// Copies `dst.len()` bytes from the user buffer at `addr` into `dst`.
+pub fn copy_from_user(dst: &mut [u8], addr: u64) -> Result<(), Errno> {
+    validate(addr, dst.len() as u64, false)?;
+    let src = unsafe { slice::from_raw_parts(addr as *const u8, dst.len()) };
+    dst.copy_from_slice(src);
+    Ok(())
+}
+
+/// Copies `src` into the user buffer at `addr`.
+pub fn copy_to_user(addr: u64, src: &[u8]) -> Result<(), Errno> {
+    validate(addr, src.len() as u64, true)?;
+    let dst = unsafe { slice::from_raw_parts_mut(addr as *mut u8, src.len()) };
+    dst.copy_from_slice(src);
+    Ok(())
+}
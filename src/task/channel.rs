@@ -0,0 +1,45 @@
+use alloc::vec::Vec;
+use core::task::Waker;
+
+pub mod broadcast;
+pub mod mpsc;
+pub mod oneshot;
+pub mod watch;
+
+// TASK CHANNELS:
+//
+// mpsc       bounded queue, many senders and one receiver; `send` waits for
+//            space, `try_send` fails instead
+// oneshot    a single value from one sender to one receiver
+// broadcast  every receiver sees every value, slow receivers lose the oldest
+//            ones instead of blocking senders
+// watch      only the latest value, receivers learn that it changed
+//
+// Sending without waiting (`try_send`, `oneshot::Sender::send`, `broadcast`
+// and `watch` sends) never allocates or blocks, so it can be used from
+// interrupt handlers. Receiving and waiting for space are for tasks only.
+
+/// Wakers of tasks parked on a channel, kept under the channel's lock so a
+/// task can't miss a wakeup between checking the channel and registering.
+struct WakerList {
+    wakers: Vec<Waker>,
+}
+
+impl WakerList {
+    const fn new() -> Self {
+        WakerList { wakers: Vec::new() }
+    }
+
+    fn register(&mut self, waker: &Waker) {
+        if !self.wakers.iter().any(|w| w.will_wake(waker)) {
+            self.wakers.push(waker.clone());
+        }
+    }
+
+    /// Wakes every registered task, keeps the allocation for later use
+    fn wake_all(&mut self) {
+        for waker in self.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use super::WakerList;
+use crate::sync::IrqSpinLock;
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    future::poll_fn,
+    task::{Context, Poll},
+};
+
+/// No receiver exists, the value is handed back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// Every sender is gone and all values were received
+    Closed,
+    /// The receiver fell behind and this many values were dropped for it,
+    /// receiving continues with the oldest value still buffered
+    Lagged(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+    Lagged(u64),
+}
+
+struct State<T> {
+    /// The last `capacity` values sent
+    buffer: VecDeque<T>,
+    /// Sequence number of `buffer[0]`
+    head: u64,
+    senders: usize,
+    receivers: usize,
+    waiting: WakerList,
+}
+
+struct Shared<T> {
+    capacity: usize,
+    state: IrqSpinLock<State<T>>,
+}
+
+/// Creates a channel buffering the last `capacity` values for slow receivers.
+///
+/// The buffer is allocated here, sending never allocates.
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "broadcast channel without capacity");
+    let shared = Arc::new(Shared {
+        capacity,
+        state: IrqSpinLock::new(State {
+            buffer: VecDeque::with_capacity(capacity),
+            head: 0,
+            senders: 1,
+            receivers: 1,
+            waiting: WakerList::new(),
+        }),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared, next: 0 },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Clone> Sender<T> {
+    /// Sends `value` to every receiver and returns how many there are.
+    ///
+    /// Never waits: when the buffer is full the oldest value is dropped, and
+    /// receivers that didn't get it yet see `Lagged`. Usable from interrupt
+    /// handlers.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let mut state = self.shared.state.lock();
+        if state.receivers == 0 {
+            return Err(SendError(value));
+        }
+        if state.buffer.len() == self.shared.capacity {
+            state.buffer.pop_front();
+            state.head += 1;
+        }
+        state.buffer.push_back(value);
+        state.waiting.wake_all();
+        Ok(state.receivers)
+    }
+
+    /// New receiver getting the values sent from now on
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.shared.state.lock();
+        state.receivers += 1;
+        Receiver {
+            shared: self.shared.clone(),
+            next: state.head + state.buffer.len() as u64,
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.state.lock().receivers
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().senders += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            state.waiting.wake_all();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// Sequence number of the next value to receive
+    next: u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let state = self.shared.state.lock();
+        Self::take(&mut self.next, &state)
+    }
+
+    /// Waits for the next value
+    pub async fn recv(&mut self) -> Result<T, RecvError> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context) -> Poll<Result<T, RecvError>> {
+        let mut state = self.shared.state.lock();
+        match Self::take(&mut self.next, &state) {
+            Ok(value) => Poll::Ready(Ok(value)),
+            Err(TryRecvError::Closed) => Poll::Ready(Err(RecvError::Closed)),
+            Err(TryRecvError::Lagged(count)) => Poll::Ready(Err(RecvError::Lagged(count))),
+            Err(TryRecvError::Empty) => {
+                state.waiting.register(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn take(next: &mut u64, state: &State<T>) -> Result<T, TryRecvError> {
+        if *next < state.head {
+            let lagged = state.head - *next;
+            *next = state.head;
+            return Err(TryRecvError::Lagged(lagged));
+        }
+        match state.buffer.get((*next - state.head) as usize) {
+            Some(value) => {
+                *next += 1;
+                Ok(value.clone())
+            }
+            None if state.senders == 0 => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    /// Receiver continuing at the same position
+    fn clone(&self) -> Self {
+        self.shared.state.lock().receivers += 1;
+        Receiver {
+            shared: self.shared.clone(),
+            next: self.next,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().receivers -= 1;
+    }
+}
@@ -0,0 +1,182 @@
+use super::WakerList;
+use crate::sync::IrqSpinLock;
+use alloc::sync::Arc;
+use core::{
+    future::poll_fn,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{task::AtomicWaker, Stream};
+
+/// The receiver is gone, the value is handed back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel is at capacity
+    Full(T),
+    /// The receiver is gone
+    Closed(T),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    /// Every sender is gone and the channel is empty
+    Disconnected,
+}
+
+struct Shared<T> {
+    queue: ArrayQueue<T>,
+    senders: AtomicUsize,
+    receiver_closed: AtomicBool,
+    receiver_waker: AtomicWaker,
+    /// Senders waiting for space, woken when a value is received or the
+    /// receiver is dropped
+    senders_waiting: IrqSpinLock<WakerList>,
+}
+
+/// Creates a channel holding up to `capacity` values.
+///
+/// The buffer is allocated here, sending never allocates. Panics if `capacity`
+/// is 0.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "mpsc channel without capacity");
+    let shared = Arc::new(Shared {
+        queue: ArrayQueue::new(capacity),
+        senders: AtomicUsize::new(1),
+        receiver_closed: AtomicBool::new(false),
+        receiver_waker: AtomicWaker::new(),
+        senders_waiting: IrqSpinLock::new(WakerList::new()),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value` if there is space, without waiting. Usable from interrupt
+    /// handlers.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        if self.is_closed() {
+            return Err(TrySendError::Closed(value));
+        }
+        self.shared.queue.push(value).map_err(TrySendError::Full)?;
+        self.shared.receiver_waker.wake();
+        Ok(())
+    }
+
+    /// Sends `value`, waiting for space while the channel is full
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut value = Some(value);
+        poll_fn(|cx| {
+            // tried under the lock, so space freed meanwhile isn't missed
+            let mut waiting = self.shared.senders_waiting.lock();
+            match self.try_send(value.take().expect("send polled after completion")) {
+                Ok(()) => Poll::Ready(Ok(())),
+                Err(TrySendError::Closed(v)) => Poll::Ready(Err(SendError(v))),
+                Err(TrySendError::Full(v)) => {
+                    value = Some(v);
+                    waiting.register(cx.waker());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// Whether the receiver is gone
+    pub fn is_closed(&self) -> bool {
+        self.shared.receiver_closed.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.receiver_waker.wake();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        if let Some(value) = self.shared.queue.pop() {
+            self.shared.senders_waiting.lock().wake_all();
+            return Ok(value);
+        }
+        if self.shared.senders.load(Ordering::Acquire) == 0 {
+            // a value sent right before the last sender was dropped
+            return self.shared.queue.pop().ok_or(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    /// Waits for the next value, `None` once every sender is gone and the
+    /// channel is empty
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<T>> {
+        match self.try_recv() {
+            Ok(value) => return Poll::Ready(Some(value)),
+            Err(TryRecvError::Disconnected) => return Poll::Ready(None),
+            Err(TryRecvError::Empty) => {}
+        }
+        self.shared.receiver_waker.register(cx.waker());
+        // checked again, a value may have arrived before the registration
+        match self.try_recv() {
+            Ok(value) => Poll::Ready(Some(value)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+
+    /// Number of values waiting to be received
+    pub fn len(&self) -> usize {
+        self.shared.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.queue.is_empty()
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.get_mut().poll_recv(cx)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_closed.store(true, Ordering::Release);
+        self.shared.senders_waiting.lock().wake_all();
+    }
+}
@@ -0,0 +1,99 @@
+use crate::sync::IrqSpinLock;
+use alloc::sync::Arc;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+
+/// The sender was dropped without sending
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+struct Shared<T> {
+    value: IrqSpinLock<Option<T>>,
+    /// The sender sent or was dropped
+    complete: AtomicBool,
+    receiver_closed: AtomicBool,
+    receiver_waker: AtomicWaker,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        value: IrqSpinLock::new(None),
+        complete: AtomicBool::new(false),
+        receiver_closed: AtomicBool::new(false),
+        receiver_waker: AtomicWaker::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Hands `value` to the receiver, or back if the receiver is gone.
+    /// Usable from interrupt handlers.
+    pub fn send(self, value: T) -> Result<(), T> {
+        if self.is_closed() {
+            return Err(value);
+        }
+        *self.shared.value.lock() = Some(value);
+        // the receiver is woken when `self` is dropped
+        Ok(())
+    }
+
+    /// Whether the receiver is gone
+    pub fn is_closed(&self) -> bool {
+        self.shared.receiver_closed.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.complete.store(true, Ordering::Release);
+        self.shared.receiver_waker.wake();
+    }
+}
+
+/// Future resolving to the value sent
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Takes the value if it was sent already
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.shared.value.lock().take()
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, RecvError>> {
+        let shared = &self.shared;
+        if !shared.complete.load(Ordering::Acquire) {
+            shared.receiver_waker.register(cx.waker());
+            // checked again, the sender may have finished before the registration
+            if !shared.complete.load(Ordering::Acquire) {
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(shared.value.lock().take().ok_or(RecvError))
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_closed.store(true, Ordering::Release);
+    }
+}
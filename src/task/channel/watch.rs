@@ -0,0 +1,165 @@
+use super::WakerList;
+use crate::sync::{IrqSpinLock, IrqSpinLockGuard};
+use alloc::sync::Arc;
+use core::{future::poll_fn, ops::Deref, task::Poll};
+
+/// The sender is gone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+struct State<T> {
+    value: T,
+    /// Incremented by every send
+    version: u64,
+    sender_alive: bool,
+    receivers: usize,
+    waiting: WakerList,
+}
+
+/// Creates a channel holding `initial`, which receivers consider seen.
+pub fn channel<T>(initial: T) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(IrqSpinLock::new(State {
+        value: initial,
+        version: 0,
+        sender_alive: true,
+        receivers: 1,
+        waiting: WakerList::new(),
+    }));
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared, seen: 0 },
+    )
+}
+
+/// Shared access to the current value, keeps the channel locked with
+/// interrupts disabled, so drop it soon
+pub struct Ref<'a, T> {
+    guard: IrqSpinLockGuard<'a, State<T>>,
+}
+
+impl<T> Deref for Ref<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard.value
+    }
+}
+
+pub struct Sender<T> {
+    shared: Arc<IrqSpinLock<State<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Replaces the value and notifies the receivers. Usable from interrupt
+    /// handlers, as long as dropping the old value doesn't free memory.
+    pub fn send(&self, value: T) {
+        self.send_replace(value);
+    }
+
+    /// Like `send`, returns the previous value
+    pub fn send_replace(&self, value: T) -> T {
+        let mut state = self.shared.lock();
+        let old = core::mem::replace(&mut state.value, value);
+        state.version += 1;
+        state.waiting.wake_all();
+        old
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        Ref {
+            guard: self.shared.lock(),
+        }
+    }
+
+    /// New receiver that considers the current value seen
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.shared.lock();
+        state.receivers += 1;
+        Receiver {
+            shared: self.shared.clone(),
+            seen: state.version,
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.lock().receivers
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.sender_alive = false;
+        state.waiting.wake_all();
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<IrqSpinLock<State<T>>>,
+    /// Version of the value last seen
+    seen: u64,
+}
+
+impl<T> Receiver<T> {
+    /// The current value, without marking it seen
+    pub fn borrow(&self) -> Ref<'_, T> {
+        Ref {
+            guard: self.shared.lock(),
+        }
+    }
+
+    /// The current value, marked seen
+    pub fn borrow_and_update(&mut self) -> Ref<'_, T> {
+        let guard = self.shared.lock();
+        self.seen = guard.version;
+        Ref { guard }
+    }
+
+    /// Whether a value was sent since the last one seen
+    pub fn has_changed(&self) -> Result<bool, RecvError> {
+        let state = self.shared.lock();
+        if state.version != self.seen {
+            Ok(true)
+        } else if state.sender_alive {
+            Ok(false)
+        } else {
+            Err(RecvError)
+        }
+    }
+
+    /// Waits until a value is sent that wasn't seen yet and marks it seen.
+    /// Values sent in between are skipped.
+    pub async fn changed(&mut self) -> Result<(), RecvError> {
+        poll_fn(|cx| {
+            let mut state = self.shared.lock();
+            if state.version != self.seen {
+                self.seen = state.version;
+                Poll::Ready(Ok(()))
+            } else if !state.sender_alive {
+                Poll::Ready(Err(RecvError))
+            } else {
+                state.waiting.register(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().receivers += 1;
+        Receiver {
+            shared: self.shared.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.lock().receivers -= 1;
+    }
+}
@@ -0,0 +1,170 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+// FUTURE COMBINATORS:
+//
+// join(a, b)        both outputs, once both futures completed
+// join_all(futures) all outputs in order, once every future completed
+// select(a, b)      the output of whichever future completes first
+// select_all(..)    the first output and the index of its future
+//
+// The futures are boxed, so they don't have to be `Unpin`. Combined futures are
+// polled in order each time the task is woken, which biases `select` towards
+// its first future. Futures that lost a `select` are dropped with it.
+
+/// Output of `select`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+/// A future that may have completed, keeping its output until taken
+enum MaybeDone<F: Future> {
+    Pending(Pin<Box<F>>),
+    Done(F::Output),
+    Taken,
+}
+
+// the output is never pinned
+impl<F: Future> Unpin for MaybeDone<F> {}
+
+impl<F: Future> MaybeDone<F> {
+    fn new(future: F) -> Self {
+        MaybeDone::Pending(Box::pin(future))
+    }
+
+    /// Polls the future if it is still pending, returns whether it is done
+    fn poll(&mut self, cx: &mut Context) -> bool {
+        if let MaybeDone::Pending(future) = self {
+            match future.as_mut().poll(cx) {
+                Poll::Ready(output) => *self = MaybeDone::Done(output),
+                Poll::Pending => return false,
+            }
+        }
+        true
+    }
+
+    fn take(&mut self) -> F::Output {
+        match core::mem::replace(self, MaybeDone::Taken) {
+            MaybeDone::Done(output) => output,
+            _ => panic!("output of an unfinished future taken"),
+        }
+    }
+}
+
+pub fn join<A: Future, B: Future>(a: A, b: B) -> Join<A, B> {
+    Join {
+        a: MaybeDone::new(a),
+        b: MaybeDone::new(b),
+    }
+}
+
+pub struct Join<A: Future, B: Future> {
+    a: MaybeDone<A>,
+    b: MaybeDone<B>,
+}
+
+impl<A: Future, B: Future> Future for Join<A, B> {
+    type Output = (A::Output, B::Output);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let a_done = this.a.poll(cx);
+        let b_done = this.b.poll(cx);
+        if a_done && b_done {
+            Poll::Ready((this.a.take(), this.b.take()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+pub fn join_all<I>(futures: I) -> JoinAll<I::Item>
+where
+    I: IntoIterator,
+    I::Item: Future,
+{
+    JoinAll {
+        futures: futures.into_iter().map(MaybeDone::new).collect(),
+    }
+}
+
+pub struct JoinAll<F: Future> {
+    futures: Vec<MaybeDone<F>>,
+}
+
+impl<F: Future> Future for JoinAll<F> {
+    type Output = Vec<F::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Vec<F::Output>> {
+        let this = self.get_mut();
+        let mut all_done = true;
+        for future in &mut this.futures {
+            all_done &= future.poll(cx);
+        }
+        if all_done {
+            Poll::Ready(this.futures.iter_mut().map(MaybeDone::take).collect())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+pub fn select<A: Future, B: Future>(a: A, b: B) -> Select<A, B> {
+    Select {
+        a: Box::pin(a),
+        b: Box::pin(b),
+    }
+}
+
+pub struct Select<A, B> {
+    a: Pin<Box<A>>,
+    b: Pin<Box<B>>,
+}
+
+impl<A: Future, B: Future> Future for Select<A, B> {
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if let Poll::Ready(output) = self.a.as_mut().poll(cx) {
+            return Poll::Ready(Either::Left(output));
+        }
+        self.b.as_mut().poll(cx).map(Either::Right)
+    }
+}
+
+/// Completes with the first future to complete and its index.
+///
+/// Panics when polled without futures, which would never complete.
+pub fn select_all<I>(futures: I) -> SelectAll<I::Item>
+where
+    I: IntoIterator,
+    I::Item: Future,
+{
+    SelectAll {
+        futures: futures.into_iter().map(Box::pin).collect(),
+    }
+}
+
+pub struct SelectAll<F> {
+    futures: Vec<Pin<Box<F>>>,
+}
+
+impl<F: Future> Future for SelectAll<F> {
+    type Output = (F::Output, usize);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        assert!(!self.futures.is_empty(), "select_all without futures");
+        for (index, future) in self.futures.iter_mut().enumerate() {
+            if let Poll::Ready(output) = future.as_mut().poll(cx) {
+                return Poll::Ready((output, index));
+            }
+        }
+        Poll::Pending
+    }
+}
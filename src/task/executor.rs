@@ -1,12 +1,222 @@
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
-use core::task::{Context, Poll, Waker};
+mod shared;
+
+use super::{
+    local,
+    metrics::{self, TaskMetrics},
+    JoinHandle, Priority, Task, TaskId,
+};
+use crate::{
+    cpu, deferred, percpu,
+    sync::IrqSpinLock,
+    thread::{self, ThreadId},
+    time, watchdog,
+};
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    rc::Rc,
+    sync::Arc,
+    task::Wake,
+    vec::Vec,
+};
+use core::{
+    any::type_name,
+    future::Future,
+    sync::atomic::{self, AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
 use crossbeam_queue::ArrayQueue;
+use spin::Mutex;
 use x86_64::instructions::interrupts::{self, enable_and_hlt};
 
+// EXECUTORS:
+//
+// Each CPU runs its own `Executor`: the BSP in `kernel_main`, APs in `ap_main`.
+// Tasks spawned on an executor are not `Send` and stay on its CPU. Tasks spawned
+// with `spawn_shared` or `spawn_on` are `Send` and polled by whichever executor
+// they are queued on, see `shared`.
+
+/// Vector of the IPI that wakes a halted executor to run shared tasks
+pub const WAKEUP_VECTOR: u8 = 0xfc;
+
+percpu! {
+    /// Wake queue of the executor running on each CPU, registered by `Executor::run`
+    static RUN_QUEUES: Mutex<Option<Arc<WakeQueue>>> = Mutex::new(None);
+    /// Spawner of the executor running on each CPU, used by `spawn`
+    static SPAWNERS: LocalSpawner = LocalSpawner(IrqSpinLock::new(None));
+}
+
+/// Capacity of each ready queue of the wake queue, more tasks can be ready at
+/// once (see `WakeQueue`)
+const WAKE_QUEUE_CAPACITY: usize = 100;
+
+/// Slot of `SPAWNERS`.
+///
+/// Tasks are not `Send`, so neither is `Spawner`. Only the owning CPU accesses
+/// its slot (through `PerCpu::get`), and `local_spawner` turns away interrupt
+/// handlers and other kernel threads, so the tasks never leave the thread that
+/// runs the executor.
+struct LocalSpawner(IrqSpinLock<Option<Registered>>);
+
+unsafe impl Sync for LocalSpawner {}
+
+/// Executor registered on a CPU
+struct Registered {
+    spawner: Spawner,
+    /// Thread running the executor, `None` where no threads are scheduled
+    thread: Option<ThreadId>,
+}
+
+/// Spawner of the executor running on the calling CPU.
+///
+/// Panics if there is none, or when called from an interrupt handler or another
+/// thread than the executor's.
+fn local_spawner() -> Spawner {
+    assert!(
+        !crate::interrupts::in_interrupt(),
+        "spawn called from an interrupt handler"
+    );
+    let (spawner, owner) = match &*SPAWNERS.get().0.lock() {
+        Some(registered) => (registered.spawner.clone(), registered.thread),
+        None => panic!("no executor running on this CPU"),
+    };
+    if let Some(owner) = owner {
+        assert_eq!(
+            thread::current(),
+            owner,
+            "spawn called from another thread than the executor's"
+        );
+    }
+    spawner
+}
+
+/// Spawns `future` on the executor running on the calling CPU.
+///
+/// Usable from inside tasks, panics if no executor runs on this CPU or when
+/// called from an interrupt handler or another kernel thread.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    spawn_named(type_name::<F>(), future)
+}
+
+/// Like `spawn`, with the name shown in the task list
+pub fn spawn_named<F>(name: &'static str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    local_spawner().spawn_named(name, future)
+}
+
+/// Spawns `task` on the executor running on the calling CPU, e.g. one built with
+/// `Task::with_priority`.
+///
+/// Panics like `spawn`.
+pub fn spawn_task(task: Task) {
+    local_spawner().spawn_task(task);
+}
+
+/// Spawns a `Send` task, which the executor of any CPU may run.
+///
+/// Usable before executors run, the task is queued on the calling CPU until
+/// one picks it up.
+pub fn spawn_shared<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    shared::spawn(type_name::<F>(), None, future)
+}
+
+/// Spawns a `Send` task that only ever runs on `cpu`, e.g. because it drives a
+/// device of that CPU. It waits until the executor of `cpu` runs.
+///
+/// Panics if `cpu` is not below `cpu::MAX_CPUS`.
+pub fn spawn_on<F>(cpu: usize, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    shared::spawn(type_name::<F>(), Some(cpu), future)
+}
+
+/// Like `spawn_shared` or, with an `affinity`, `spawn_on`, with the name shown
+/// in the task list
+pub fn spawn_shared_named<F>(
+    name: &'static str,
+    affinity: Option<usize>,
+    future: F,
+) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    shared::spawn(name, affinity, future)
+}
+
+/// Number of tasks waiting to be polled by the executor running on `cpu`,
+/// including shared tasks queued there
+pub fn queued_tasks(cpu: usize) -> usize {
+    let local = RUN_QUEUES
+        .get_on(cpu)
+        .and_then(|queue| queue.lock().as_ref().map(|queue| queue.len()))
+        .unwrap_or(0);
+    local + shared::queued(cpu)
+}
+
+/// Ids of the tasks to poll next, one queue per `Priority`.
+///
+/// A task is queued at most once (see `TaskWaker::scheduled`), so the queue only
+/// fills up with more than `WAKE_QUEUE_CAPACITY` ready tasks of a priority. Wakeups that don't
+/// fit set `overflowed` instead and the executor looks for scheduled tasks
+/// itself, so waking never allocates, blocks or fails, even in interrupt handlers.
+struct WakeQueue {
+    ids: [ArrayQueue<TaskId>; Priority::COUNT],
+    overflowed: AtomicBool,
+    /// CPU of the executor, wakers on other CPUs wake it from `hlt`
+    cpu: usize,
+}
+
+impl WakeQueue {
+    fn new() -> Self {
+        WakeQueue {
+            ids: core::array::from_fn(|_| ArrayQueue::new(WAKE_QUEUE_CAPACITY)),
+            overflowed: AtomicBool::new(false),
+            cpu: cpu::current_id(),
+        }
+    }
+
+    fn push(&self, task_id: TaskId, priority: Priority) {
+        if self.ids[priority.index()].push(task_id).is_err() {
+            self.overflowed.store(true, Ordering::Release);
+        }
+        atomic::fence(Ordering::SeqCst);
+        shared::wake_executor(self.cpu);
+    }
+
+    /// Next task of the highest priority that has one
+    fn pop(&self) -> Option<TaskId> {
+        self.ids.iter().find_map(ArrayQueue::pop)
+    }
+
+    fn len(&self) -> usize {
+        self.ids.iter().map(ArrayQueue::len).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ids.iter().all(ArrayQueue::is_empty) && !self.overflowed.load(Ordering::Acquire)
+    }
+}
+
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    priority: Priority,
+    wake_queue: Arc<WakeQueue>,
+    /// Set while the task waits to be polled, so repeated wakeups queue it once
+    scheduled: AtomicBool,
+    metrics: Arc<TaskMetrics>,
 }
 
 impl Wake for TaskWaker {
@@ -20,76 +230,299 @@ impl Wake for TaskWaker {
 }
 
 impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(TaskWaker {
-            task_id,
-            task_queue,
-        }))
+    fn new(task: &Task, wake_queue: Arc<WakeQueue>) -> Arc<TaskWaker> {
+        Arc::new(TaskWaker {
+            task_id: task.id,
+            priority: task.priority,
+            wake_queue,
+            scheduled: AtomicBool::new(false),
+            metrics: metrics::register(task.id, task.name),
+        })
     }
 
     fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full")
+        self.metrics.woken();
+        if !self.scheduled.swap(true, Ordering::AcqRel) {
+            self.wake_queue.push(self.task_id, self.priority);
+        }
+    }
+}
+
+/// Tasks spawned through a `Spawner`, with their wakers
+type SpawnQueue = Rc<IrqSpinLock<VecDeque<(Task, Arc<TaskWaker>)>>>;
+
+/// Cloneable handle that spawns tasks onto an `Executor`, also while it runs.
+///
+/// New tasks are handed over through a queue the executor drains before
+/// polling, since only the executor itself may touch its task map.
+#[derive(Clone)]
+pub struct Spawner {
+    wake_queue: Arc<WakeQueue>,
+    spawned: SpawnQueue,
+}
+
+impl Spawner {
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        self.spawn_named(type_name::<F>(), future)
+    }
+
+    /// Like `spawn`, with the name shown in the task list
+    pub fn spawn_named<F>(&self, name: &'static str, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let (task, handle) = Task::with_join_handle(future);
+        self.spawn_task(task.named(name));
+        handle
+    }
+
+    pub fn spawn_task(&self, task: Task) {
+        let waker = TaskWaker::new(&task, self.wake_queue.clone());
+        self.spawned.lock().push_back((task, waker.clone()));
+        waker.wake_task();
     }
 }
 
 pub struct Executor {
     tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>,
-    waker_cache: BTreeMap<TaskId, Waker>,
+    wake_queue: Arc<WakeQueue>,
+    waker_cache: BTreeMap<TaskId, Arc<TaskWaker>>,
+    /// Tasks handed over by a `Spawner`, not yet in `tasks`
+    spawned: SpawnQueue,
 }
 
 impl Executor {
     pub fn new() -> Self {
         Executor {
             tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
+            wake_queue: Arc::new(WakeQueue::new()),
             waker_cache: BTreeMap::new(),
+            spawned: Rc::new(IrqSpinLock::new(VecDeque::new())),
         }
     }
 
     pub fn spawn(&mut self, task: Task) {
-        let task_id = task.id;
-        if self.tasks.insert(task.id, task).is_some() {
+        let waker = TaskWaker::new(&task, self.wake_queue.clone());
+        self.insert(task, waker.clone());
+        waker.wake_task();
+    }
+
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            wake_queue: self.wake_queue.clone(),
+            spawned: self.spawned.clone(),
+        }
+    }
+
+    /// Number of tasks owned by the executor, finished ones are removed
+    pub fn task_count(&self) -> usize {
+        self.tasks.len() + self.spawned.lock().len()
+    }
+
+    /// Drops the task `id` without polling it again, its `JoinHandle` resolves to
+    /// `JoinError::Cancelled`. Returns false if the task doesn't exist (anymore).
+    ///
+    /// Wakers of the task stay valid, waking them after this does nothing.
+    pub fn cancel(&mut self, id: TaskId) -> bool {
+        self.insert_spawned();
+        self.remove(id)
+    }
+
+    /// Drops the task `id` and its cached waker
+    fn remove(&mut self, id: TaskId) -> bool {
+        self.waker_cache.remove(&id);
+        metrics::unregister(id);
+        // dropping the task may wake its joiners, which only queues them
+        self.tasks.remove(&id).is_some()
+    }
+
+    fn insert(&mut self, task: Task, waker: Arc<TaskWaker>) {
+        if self.tasks.contains_key(&task.id) {
             panic!("task with same ID already in tasks");
         }
-        self.task_queue.push(task_id).expect("queue full");
+        self.waker_cache.insert(task.id, waker);
+        self.tasks.insert(task.id, task);
     }
 
+    /// Moves the tasks handed over by spawners into `tasks`
+    fn insert_spawned(&mut self) {
+        // taken in one go, polling must not happen with the lock held
+        let spawned = core::mem::take(&mut *self.spawned.lock());
+        for (task, waker) in spawned {
+            self.insert(task, waker);
+        }
+    }
+
+    /// Polls the ready tasks of the executor, then the shared tasks queued on
+    /// its CPU
     fn run_ready_tasks(&mut self) {
-        while let Some(task_id) = self.task_queue.pop() {
-            let task = match self.tasks.get_mut(&task_id) {
-                Some(task) => task,
-                None => continue, // task no longer exists
-            };
-            let waker = self
+        self.run_local_tasks();
+        shared::run_ready();
+    }
+
+    fn run_local_tasks(&mut self) {
+        self.insert_spawned();
+        loop {
+            // popped one by one, so tasks woken meanwhile are polled in order
+            // of priority as well
+            while let Some(task_id) = self.wake_queue.pop() {
+                self.run_task(task_id);
+            }
+            if !self.wake_queue.overflowed.swap(false, Ordering::AcqRel) {
+                break;
+            }
+            // the wakeups that didn't fit only left their scheduled flag
+            self.insert_spawned();
+            let mut scheduled: Vec<(Priority, TaskId)> = self
                 .waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, self.task_queue.clone()));
-            let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
-                Poll::Ready(()) => {
-                    // task done -> remove it and its cached waker
-                    self.tasks.remove(&task_id);
-                    self.waker_cache.remove(&task_id);
-                }
-                Poll::Pending => {}
+                .values()
+                .filter(|waker| waker.scheduled.load(Ordering::Acquire))
+                .map(|waker| (waker.priority, waker.task_id))
+                .collect();
+            scheduled.sort_by_key(|(priority, _)| priority.index());
+            for (_, task_id) in scheduled {
+                self.run_task(task_id);
+            }
+        }
+    }
+
+    fn run_task(&mut self, task_id: TaskId) {
+        if !self.tasks.contains_key(&task_id) {
+            // spawned by the task polled last
+            self.insert_spawned();
+        }
+        let (Some(task), Some(waker)) =
+            (self.tasks.get_mut(&task_id), self.waker_cache.get(&task_id))
+        else {
+            return; // task finished or was cancelled
+        };
+        // cleared before polling, so wakeups from now on poll it again
+        if !waker.scheduled.swap(false, Ordering::AcqRel) {
+            return; // already polled since it was queued
+        }
+        let metrics = waker.metrics.clone();
+        let waker = Waker::from(waker.clone());
+        let mut context = Context::from_waker(&waker);
+        watchdog::set_current_task(Some(task_id.0));
+        let current = local::enter(task_id, task.name);
+        let start = time::tsc();
+        let result = task.poll(&mut context);
+        let cycles = time::tsc() - start;
+        drop(current);
+        watchdog::set_current_task(None);
+        watchdog::feed();
+        metrics.polled(cycles);
+        match result {
+            Poll::Ready(()) => {
+                // task done -> remove it and its cached waker
+                self.remove(task_id);
             }
+            Poll::Pending => {}
         }
     }
 
+    /// Makes this the executor `spawn` and `queued_tasks` refer to on the calling CPU
+    fn register(&self) {
+        *RUN_QUEUES.get().lock() = Some(self.wake_queue.clone());
+        *SPAWNERS.get().0.lock() = Some(Registered {
+            spawner: self.spawner(),
+            thread: thread::schedules_here().then(thread::current),
+        });
+    }
+
+    fn unregister(&self) {
+        *RUN_QUEUES.get().lock() = None;
+        *SPAWNERS.get().0.lock() = None;
+    }
+
+    /// Polls tasks until none is ready anymore, without waiting for interrupts.
+    ///
+    /// The executor is registered for the calling CPU meanwhile, so tasks can use
+    /// `spawn`. Mostly useful for tests, which can't use the diverging `run`.
+    pub fn run_until_stalled(&mut self) {
+        self.register();
+        loop {
+            deferred::run_pending();
+            self.run_ready_tasks();
+            if !self.has_work() {
+                break;
+            }
+        }
+        self.unregister();
+    }
+
+    /// Runs `future` as a task next to the executor's other tasks until it
+    /// completes and returns its output.
+    ///
+    /// Waits for interrupts while no task is ready, so timers work. Requires
+    /// interrupts to be enabled.
+    pub fn block_on<F>(&mut self, future: F) -> F::Output
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let (task, mut handle) = Task::with_join_handle(future);
+        self.spawn(task);
+        self.register();
+        let result = loop {
+            deferred::run_pending();
+            self.run_ready_tasks();
+            if let Some(result) = handle.take_result() {
+                break result;
+            }
+            self.sleep_if_idle();
+        };
+        self.unregister();
+        result.expect("block_on task did not complete")
+    }
+
+    /// Runs the executor on the calling CPU, which becomes its run queue's owner.
     pub fn run(&mut self) -> ! {
+        self.register();
         loop {
+            watchdog::feed();
+            // interrupt handlers only queue work, run it before polling tasks
+            deferred::run_pending();
             self.run_ready_tasks();
             self.sleep_if_idle();
         }
     }
 
+    fn has_work(&self) -> bool {
+        !self.wake_queue.is_empty() || deferred::has_pending() || shared::has_work()
+    }
+
     fn sleep_if_idle(&self) {
+        if !self.has_work() && shared::steal() {
+            return;
+        }
         interrupts::disable();
-        if self.task_queue.is_empty() {
-            enable_and_hlt();
+        shared::set_idle(true);
+        if !self.has_work() {
+            if thread::others_ready() {
+                // a waking task is picked up once this thread runs again
+                interrupts::enable();
+                thread::yield_now();
+            } else {
+                enable_and_hlt();
+            }
         } else {
             interrupts::enable();
         }
+        shared::set_idle(false);
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        self.insert_spawned();
+        for id in self.tasks.keys() {
+            metrics::unregister(*id);
+        }
     }
 }
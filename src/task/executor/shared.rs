@@ -0,0 +1,300 @@
+use super::WAKEUP_VECTOR;
+use crate::{
+    apic, cpu, percpu,
+    sync::IrqSpinLock,
+    task::{
+        join, local,
+        metrics::{self, TaskMetrics},
+        JoinHandle, TaskId,
+    },
+    time, watchdog,
+};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, task::Wake, vec::Vec};
+use conquer_once::spin::OnceCell;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{self, AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+use crossbeam_queue::ArrayQueue;
+use spin::Mutex;
+
+// SHARED TASKS:
+//
+// `Send` tasks belong to no single executor. A woken task is queued on the CPU
+// that polled it last, or on the CPU of its affinity, which it never leaves.
+// Every executor polls the tasks queued on its CPU after its own tasks, and an
+// executor that ran out of work steals half of another CPU's stealable queue
+// before it halts.
+//
+// Executors halted in `hlt` are woken with a `WAKEUP_VECTOR` IPI when a task is
+// queued on their CPU. Queueing a stealable task also wakes one other idle
+// executor, which steals it if the CPU it was queued on is busy.
+
+/// Capacity of each queue, see `CpuQueues::overflowed`
+const QUEUE_CAPACITY: usize = 256;
+
+static QUEUES: OnceCell<Vec<CpuQueues>> = OnceCell::uninit();
+/// Every unfinished shared task, searched when a queue overflowed
+static REGISTRY: IrqSpinLock<BTreeMap<TaskId, Arc<SharedTask>>> = IrqSpinLock::new(BTreeMap::new());
+
+percpu! {
+    /// Set while the executor of a CPU halts, cleared by whoever wakes it
+    static IDLE: AtomicBool = AtomicBool::new(false);
+}
+
+/// Run queues of one CPU
+struct CpuQueues {
+    /// Tasks with an affinity for this CPU
+    pinned: ArrayQueue<Arc<SharedTask>>,
+    /// Tasks any CPU may run
+    stealable: ArrayQueue<Arc<SharedTask>>,
+    /// Set when a task didn't fit into a queue. It is still scheduled, so the
+    /// executor finds it in `REGISTRY`, like `WakeQueue` does for local tasks.
+    overflowed: AtomicBool,
+}
+
+impl CpuQueues {
+    fn new() -> Self {
+        CpuQueues {
+            pinned: ArrayQueue::new(QUEUE_CAPACITY),
+            stealable: ArrayQueue::new(QUEUE_CAPACITY),
+            overflowed: AtomicBool::new(false),
+        }
+    }
+
+    fn has_work(&self) -> bool {
+        !self.pinned.is_empty()
+            || !self.stealable.is_empty()
+            || self.overflowed.load(Ordering::Acquire)
+    }
+}
+
+/// Queues of every CPU, `None` until the first shared task is spawned
+fn queues() -> Option<&'static [CpuQueues]> {
+    QUEUES.get().map(Vec::as_slice)
+}
+
+/// A `Send` task, which is its own waker
+struct SharedTask {
+    id: TaskId,
+    /// CPU the task must run on
+    affinity: Option<usize>,
+    /// CPU that polled the task last, it is queued there when woken
+    cpu: AtomicUsize,
+    /// Set while the task waits in a queue, so repeated wakeups queue it once
+    scheduled: AtomicBool,
+    /// Locked while polled, `None` once the task completed. A task woken during
+    /// its poll may be stolen meanwhile, the lock keeps the thief waiting.
+    future: Mutex<Option<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+    metrics: Arc<TaskMetrics>,
+}
+
+impl Wake for SharedTask {
+    fn wake(self: Arc<Self>) {
+        self.schedule();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.schedule();
+    }
+}
+
+impl SharedTask {
+    /// Queues the task on its CPU, never allocates or blocks
+    fn schedule(self: &Arc<Self>) {
+        self.metrics.woken();
+        if self.scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let queues = queues().expect("shared task spawned without queues");
+        let (cpu, stealable) = match self.affinity {
+            Some(cpu) => (cpu, false),
+            None => (self.cpu.load(Ordering::Relaxed), true),
+        };
+        let target = &queues[cpu];
+        let queue = if stealable {
+            &target.stealable
+        } else {
+            &target.pinned
+        };
+        if queue.push(self.clone()).is_err() {
+            target.overflowed.store(true, Ordering::Release);
+        }
+        // pairs with the fence in `set_idle`, either the executor sees the
+        // task before halting or it is seen halting here
+        atomic::fence(Ordering::SeqCst);
+        wake_executor(cpu);
+        if stealable {
+            wake_thief(cpu);
+        }
+    }
+
+    fn run(self: Arc<Self>) {
+        // cleared before polling, so wakeups from now on poll it again
+        if !self.scheduled.swap(false, Ordering::AcqRel) {
+            return; // already polled since it was queued
+        }
+        let cpu = cpu::current_id();
+        self.cpu.store(cpu, Ordering::Relaxed);
+        self.metrics.ran_on(cpu);
+
+        let mut future = self.future.lock();
+        let Some(task) = future.as_mut() else {
+            return; // completed
+        };
+        let waker = Waker::from(self.clone());
+        let mut context = Context::from_waker(&waker);
+        watchdog::set_current_task(Some(self.id.0));
+        let current = local::enter(self.id, self.metrics.name());
+        let start = time::tsc();
+        let result = task.as_mut().poll(&mut context);
+        let cycles = time::tsc() - start;
+        drop(current);
+        watchdog::set_current_task(None);
+        watchdog::feed();
+        self.metrics.polled(cycles);
+        if let Poll::Ready(()) = result {
+            *future = None;
+            drop(future);
+            REGISTRY.lock().remove(&self.id);
+            metrics::unregister(self.id);
+        }
+    }
+}
+
+/// Wakes the executor of `cpu` if it halts, see `set_idle`
+pub(super) fn wake_executor(cpu: usize) {
+    let Some(idle) = IDLE.get_on(cpu) else {
+        return;
+    };
+    // cleared here, so a burst of wakeups sends one IPI
+    if idle.swap(false, Ordering::SeqCst) && cpu != cpu::current_id() {
+        apic::send_interrupt(cpu as u8, WAKEUP_VECTOR);
+    }
+}
+
+/// Wakes one idle executor other than the one of `cpu` and the calling CPU's,
+/// which steals a task queued on `cpu` if it is still queued by then
+fn wake_thief(cpu: usize) {
+    let current = cpu::current_id();
+    let thief = IDLE
+        .iter()
+        .find(|&(other, idle)| other != cpu && other != current && idle.load(Ordering::SeqCst));
+    if let Some((thief, _)) = thief {
+        wake_executor(thief);
+    }
+}
+
+/// Spawns a shared task, see `executor::spawn_shared`
+pub(super) fn spawn<F>(
+    name: &'static str,
+    affinity: Option<usize>,
+    future: F,
+) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    if let Some(cpu) = affinity {
+        assert!(cpu < cpu::MAX_CPUS, "no CPU {}", cpu);
+    }
+    QUEUES.get_or_init(|| (0..cpu::MAX_CPUS).map(|_| CpuQueues::new()).collect());
+    let id = TaskId::new();
+    let (future, handle) = join::joined(id, future);
+    let task = Arc::new(SharedTask {
+        id,
+        affinity,
+        cpu: AtomicUsize::new(cpu::current_id()),
+        scheduled: AtomicBool::new(false),
+        future: Mutex::new(Some(Box::pin(future))),
+        metrics: metrics::register(id, name),
+    });
+    REGISTRY.lock().insert(id, task.clone());
+    task.schedule();
+    handle
+}
+
+/// Polls the tasks queued on the calling CPU.
+///
+/// Only the tasks queued when it is called, so tasks that keep waking themselves
+/// can't starve the executor's own tasks.
+pub(super) fn run_ready() {
+    let Some(queues) = queues() else {
+        return;
+    };
+    let cpu = cpu::current_id();
+    let local = &queues[cpu];
+    for queue in [&local.pinned, &local.stealable] {
+        for _ in 0..queue.len() {
+            let Some(task) = queue.pop() else {
+                break;
+            };
+            task.run();
+        }
+    }
+    if local.overflowed.swap(false, Ordering::AcqRel) {
+        // the wakeups that didn't fit only left their scheduled flag
+        let scheduled: Vec<Arc<SharedTask>> = REGISTRY
+            .lock()
+            .values()
+            .filter(|task| task.scheduled.load(Ordering::Acquire))
+            .filter(|task| task.affinity.is_none_or(|affinity| affinity == cpu))
+            .cloned()
+            .collect();
+        for task in scheduled {
+            task.run();
+        }
+    }
+}
+
+/// Whether tasks are queued on the calling CPU
+pub(super) fn has_work() -> bool {
+    queues().is_some_and(|queues| queues[cpu::current_id()].has_work())
+}
+
+/// Moves half of the stealable tasks of the first CPU that has some into the
+/// calling CPU's queue. Returns false if there was nothing to steal.
+pub(super) fn steal() -> bool {
+    let Some(queues) = queues() else {
+        return false;
+    };
+    let cpu = cpu::current_id();
+    let local = &queues[cpu];
+    // starting at the next CPU, so thieves spread over their victims
+    for offset in 1..cpu::MAX_CPUS {
+        let victim = &queues[(cpu + offset) % cpu::MAX_CPUS].stealable;
+        let count = victim.len().div_ceil(2);
+        let mut stolen = false;
+        for _ in 0..count {
+            let Some(task) = victim.pop() else {
+                break;
+            };
+            stolen = true;
+            if let Err(task) = local.stealable.push(task) {
+                task.run();
+            }
+        }
+        if stolen {
+            return true;
+        }
+    }
+    false
+}
+
+/// Marks the calling CPU's executor as halting, or as running again.
+///
+/// Once marked, the executor must check `has_work` before halting, queueing a
+/// task after that check sends it an IPI.
+pub(super) fn set_idle(idle: bool) {
+    IDLE.get().store(idle, Ordering::SeqCst);
+    atomic::fence(Ordering::SeqCst);
+}
+
+/// Number of tasks queued on `cpu`
+pub(super) fn queued(cpu: usize) -> usize {
+    queues()
+        .and_then(|queues| queues.get(cpu))
+        .map_or(0, |queues| queues.pinned.len() + queues.stealable.len())
+}
@@ -0,0 +1,233 @@
+use super::{Priority, Task, TaskId};
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    any::type_name,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+use spin::Mutex;
+
+/// Why a task did not produce its output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task was aborted or dropped before completing, e.g. with its executor
+    Cancelled,
+    /// The task panicked while being polled.
+    ///
+    /// The kernel is built with `panic = "abort"`, so a panicking task currently
+    /// halts the kernel and this is only reported if unwinding gets enabled.
+    Panicked,
+}
+
+enum State<T> {
+    Running {
+        waker: Option<Waker>,
+    },
+    Finished(Result<T, JoinError>),
+    /// The result was handed to the `JoinHandle`
+    Taken,
+}
+
+/// State shared by a task and its `JoinHandle`
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    /// Set by `JoinHandle::abort`, the task is dropped on its next poll
+    aborted: AtomicBool,
+    /// Waker of the task itself, to get it polled after an abort
+    task_waker: Mutex<Option<Waker>>,
+}
+
+impl<T> Shared<T> {
+    fn finish(&self, result: Result<T, JoinError>) {
+        let waker = match core::mem::replace(&mut *self.state.lock(), State::Finished(result)) {
+            State::Running { waker } => waker,
+            _ => unreachable!("task finished twice"),
+        };
+        // woken without the lock held, the waiter may be polled right away
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Future resolving to the output of a spawned task.
+///
+/// Dropping the handle, or calling `detach`, lets the task run to completion
+/// and discards its output.
+pub struct JoinHandle<T> {
+    id: TaskId,
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// Whether the task completed, was cancelled or panicked
+    pub fn is_finished(&self) -> bool {
+        !matches!(*self.shared.state.lock(), State::Running { .. })
+    }
+
+    /// Takes the result if the task finished, without waiting
+    pub(super) fn take_result(&mut self) -> Option<Result<T, JoinError>> {
+        let mut state = self.shared.state.lock();
+        match core::mem::replace(&mut *state, State::Taken) {
+            State::Finished(result) => Some(result),
+            other => {
+                *state = other;
+                None
+            }
+        }
+    }
+
+    /// Lets the task run on its own, its output is discarded
+    pub fn detach(self) {}
+
+    /// Cancels the task: its future is dropped the next time the executor
+    /// polls it and the handle resolves to `JoinError::Cancelled`.
+    ///
+    /// Does nothing if the task already finished.
+    pub fn abort(&self) {
+        self.shared.aborted.store(true, Ordering::Release);
+        let waker = self.shared.task_waker.lock().take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.shared.state.lock();
+        match &mut *state {
+            State::Running { waker } => {
+                if !waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+                    *waker = Some(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+            State::Finished(_) => match core::mem::replace(&mut *state, State::Taken) {
+                State::Finished(result) => Poll::Ready(result),
+                _ => unreachable!(),
+            },
+            State::Taken => panic!("JoinHandle polled after completion"),
+        }
+    }
+}
+
+/// Reports the result of a task to its `JoinHandle`, also if the task is
+/// dropped before completing
+struct Completion<T> {
+    shared: Arc<Shared<T>>,
+    /// Set while the task is polled, a drop meanwhile means it panicked
+    polling: bool,
+    done: bool,
+}
+
+impl<T> Completion<T> {
+    fn finish(&mut self, result: Result<T, JoinError>) {
+        self.done = true;
+        self.shared.finish(result);
+    }
+}
+
+impl<T> Drop for Completion<T> {
+    fn drop(&mut self) {
+        if !self.done {
+            let error = if self.polling {
+                JoinError::Panicked
+            } else {
+                JoinError::Cancelled
+            };
+            self.finish(Err(error));
+        }
+    }
+}
+
+/// Future of a task with a `JoinHandle`, stores the output of `future`
+pub(super) struct Joined<F: Future> {
+    // declared first so it is dropped before the result is reported,
+    // `None` once aborted
+    future: Option<F>,
+    completion: Completion<F::Output>,
+}
+
+impl<F: Future> Future for Joined<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        // SAFETY: `future` is never moved out of `self`, `completion` isn't pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.completion.done {
+            return Poll::Ready(());
+        }
+        let shared = &this.completion.shared;
+        if shared.aborted.load(Ordering::Acquire) {
+            // dropped in place, before joiners learn about the cancellation
+            unsafe { Pin::new_unchecked(&mut this.future) }.set(None);
+            this.completion.finish(Err(JoinError::Cancelled));
+            return Poll::Ready(());
+        }
+        {
+            let mut task_waker = shared.task_waker.lock();
+            if !task_waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+                *task_waker = Some(cx.waker().clone());
+            }
+        }
+
+        this.completion.polling = true;
+        let future = this.future.as_mut().expect("task polled after abort");
+        let poll = unsafe { Pin::new_unchecked(future) }.poll(cx);
+        this.completion.polling = false;
+        match poll {
+            Poll::Ready(output) => {
+                this.completion.finish(Ok(output));
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Wraps `future` of the task `id`, the returned handle resolves to its output
+pub(super) fn joined<F: Future>(id: TaskId, future: F) -> (Joined<F>, JoinHandle<F::Output>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State::Running { waker: None }),
+        aborted: AtomicBool::new(false),
+        task_waker: Mutex::new(None),
+    });
+    let joined = Joined {
+        future: Some(future),
+        completion: Completion {
+            shared: shared.clone(),
+            polling: false,
+            done: false,
+        },
+    };
+    (joined, JoinHandle { id, shared })
+}
+
+impl Task {
+    /// Like `Task::new`, but for futures with any output, which the returned
+    /// `JoinHandle` resolves to.
+    pub fn with_join_handle<F>(future: F) -> (Task, JoinHandle<F::Output>)
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let id = TaskId::new();
+        let (joined, handle) = joined(id, future);
+        let task = Task {
+            id,
+            name: type_name::<F>(),
+            priority: Priority::Normal,
+            future: Box::pin(joined),
+        };
+        (task, handle)
+    }
+}
@@ -1,4 +1,9 @@
-use crate::{print, println};
+use crate::{
+    deferred::{self, Work},
+    interrupts::stats,
+    print, println,
+    task::metrics,
+};
 use conquer_once::spin::OnceCell;
 use core::{
     pin::Pin,
@@ -6,7 +11,9 @@ use core::{
 };
 use crossbeam_queue::ArrayQueue;
 use futures_util::{task::AtomicWaker, Stream, StreamExt};
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use lazy_static::lazy_static;
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, Keyboard, ScancodeSet1};
+use spin::Mutex;
 
 /// Use `OnceCell` because it ensures the initialization does not happend in
 /// the interrupt handler, preventing the handler from performing a heap
@@ -19,17 +26,102 @@ static WAKER: AtomicWaker = AtomicWaker::new();
 
 /// Called by the keyboard interrupt handler.
 ///
-/// Must not block or allocate.
+/// Must not block or allocate, so warnings are printed as deferred work.
 pub(crate) fn add_scancode(scancode: u8) {
     if let Ok(queue) = SCANCODE_QUEUE.try_get() {
-        if let Err(_) = queue.push(scancode) {
-            println!("WARNING: scancode queue full; dropping keyboard input")
+        if queue.push(scancode).is_err() {
+            let _ = deferred::schedule(Work::new(
+                |_| println!("WARNING: scancode queue full; dropping keyboard input"),
+                0,
+            ));
         } else {
             WAKER.wake();
         }
     } else {
-        println!("WARNING: scancode queue uninitialized")
+        let _ = deferred::schedule(Work::new(
+            |_| println!("WARNING: scancode queue uninitialized"),
+            0,
+        ));
+    }
+}
+
+lazy_static! {
+    /// Decoder state for `try_read_char`, separate from the async consumers
+    static ref DECODER: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = Mutex::new(Keyboard::new(
+        ScancodeSet1::new(),
+        layouts::Us104Key,
+        HandleControl::Ignore,
+    ));
+}
+
+/// Decodes queued scancodes until one completes a character.
+///
+/// Returns `None` once the queue runs empty. Used by `read_bytes` for the `read`
+/// syscall, which consumes the same queue as `ScancodeStream`, so input goes to
+/// whoever asks first.
+pub fn try_read_char() -> Option<char> {
+    let queue = SCANCODE_QUEUE.try_get().ok()?;
+    let mut keyboard = DECODER.lock();
+    while let Some(scancode) = queue.pop() {
+        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+            if let Some(DecodedKey::Unicode(character)) = keyboard.process_keyevent(key_event) {
+                return Some(character);
+            }
+        }
     }
+    None
+}
+
+/// Rest of a character `read_bytes` couldn't hand out completely
+static UNREAD: Mutex<Unread> = Mutex::new(Unread {
+    bytes: [0; 4],
+    start: 0,
+    end: 0,
+});
+
+/// UTF-8 encoding of one character, of which `start..end` is still unread
+struct Unread {
+    bytes: [u8; 4],
+    start: usize,
+    end: usize,
+}
+
+impl Unread {
+    fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    fn set(&mut self, character: char) {
+        self.start = 0;
+        self.end = character.encode_utf8(&mut self.bytes).len();
+    }
+
+    /// Moves as many unread bytes into `buf` as fit, returns their number
+    fn take(&mut self, buf: &mut [u8]) -> usize {
+        let count = (self.end - self.start).min(buf.len());
+        buf[..count].copy_from_slice(&self.bytes[self.start..self.start + count]);
+        self.start += count;
+        count
+    }
+}
+
+/// Fills `buf` with UTF-8 encoded input, returns the number of bytes written.
+///
+/// Never blocks. The bytes of a character that doesn't fit into `buf` are kept
+/// for the next call, so short buffers lose no input.
+pub fn read_bytes(buf: &mut [u8]) -> usize {
+    let mut unread = UNREAD.lock();
+    let mut read = 0;
+    while read < buf.len() {
+        if unread.is_empty() {
+            let Some(character) = try_read_char() else {
+                break;
+            };
+            unread.set(character);
+        }
+        read += unread.take(&mut buf[read..]);
+    }
+    read
 }
 
 pub struct ScancodeStream {
@@ -68,6 +160,9 @@ impl ScancodeStream {
     }
 }
 
+/// Echoes key presses to the screen.
+///
+/// F1 prints the task list and F2 the interrupt counters over serial.
 pub async fn print_keypresses() {
     let mut scancodes = ScancodeStream::new();
     let mut keyboard = Keyboard::new(
@@ -81,9 +176,28 @@ pub async fn print_keypresses() {
             if let Some(key) = keyboard.process_keyevent(key_event) {
                 match key {
                     DecodedKey::Unicode(character) => print!("{}", character),
+                    DecodedKey::RawKey(KeyCode::F1) => metrics::dump(),
+                    DecodedKey::RawKey(KeyCode::F2) => stats::dump(),
                     DecodedKey::RawKey(key) => print!("{:?}", key),
                 }
             }
         }
     }
 }
+
+#[test_case]
+fn test_unread_keeps_the_rest() {
+    let mut unread = Unread {
+        bytes: [0; 4],
+        start: 0,
+        end: 0,
+    };
+    let mut buf = [0u8; 1];
+    unread.set('é');
+    assert_eq!(unread.take(&mut buf), 1);
+    assert_eq!(buf, [0xc3]);
+    assert!(!unread.is_empty());
+    assert_eq!(unread.take(&mut buf), 1);
+    assert_eq!(buf, [0xa9]);
+    assert!(unread.is_empty());
+}
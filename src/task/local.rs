@@ -0,0 +1,141 @@
+use super::TaskId;
+use crate::{cpu, percpu, percpu::PerCpu, sync::IrqSpinLock};
+use alloc::sync::Arc;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+// TASK-LOCAL STORAGE:
+//
+// Executors record the task they poll, so code deep inside a future can find
+// out which task it runs in through `current`, e.g. to tag log messages.
+//
+// A `LocalKey` carries a value through a future without passing it along:
+//
+//     static REQUEST: LocalKey<u64> = LocalKey::new();
+//
+//     REQUEST.scope(42, handle_request()).await;  // REQUEST.get() == 42 inside
+//
+// Each key has one slot per CPU. While a `Scope` is polled, its value is moved
+// into the slot of the polling CPU and moved back out afterwards, so it follows
+// the future to whichever CPU polls it. Like `executor::spawn`, this assumes a
+// CPU doesn't switch to another executor's thread in the middle of a poll.
+
+percpu! {
+    /// Task polled by the executor of each CPU
+    static CURRENT: IrqSpinLock<Option<CurrentTask>> = IrqSpinLock::new(None);
+}
+
+/// The task being polled, see `current`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CurrentTask {
+    pub id: TaskId,
+    /// Name shown in the task list, see `Task::named`
+    pub name: &'static str,
+}
+
+/// Task the calling CPU is polling, `None` outside of tasks
+pub fn current() -> Option<CurrentTask> {
+    *CURRENT.get().lock()
+}
+
+/// Marks the task `id` as current until the returned guard is dropped
+pub(super) fn enter(id: TaskId, name: &'static str) -> Entered {
+    let previous = CURRENT.get().lock().replace(CurrentTask { id, name });
+    Entered { previous }
+}
+
+/// Restores the task that was current before, `block_on` can nest polls
+pub(super) struct Entered {
+    previous: Option<CurrentTask>,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        *CURRENT.get().lock() = self.previous;
+    }
+}
+
+/// The key was accessed outside of a `scope` setting it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessError;
+
+/// Key of a task-local value, declared as a `static` and set with `scope`
+pub struct LocalKey<T: 'static> {
+    slots: PerCpu<IrqSpinLock<Option<Arc<T>>>>,
+}
+
+impl<T: Send + Sync + 'static> LocalKey<T> {
+    pub const fn new() -> Self {
+        LocalKey {
+            slots: PerCpu::new([const { IrqSpinLock::new(None) }; cpu::MAX_CPUS]),
+        }
+    }
+
+    /// Runs `future` with the key set to `value`, nested scopes of the same key
+    /// shadow it.
+    ///
+    /// Only `future` itself sees the value, not tasks it spawns.
+    pub fn scope<F: Future>(&'static self, value: T, future: F) -> Scope<T, F> {
+        Scope {
+            key: self,
+            value: Some(Arc::new(value)),
+            future,
+        }
+    }
+
+    /// Calls `f` with the value of the enclosing `scope`
+    pub fn try_with<R>(&'static self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        // cloned, so `f` runs without the slot locked and may use the key again
+        let value = self.slots.get().lock().clone();
+        value.map(|value| f(&value)).ok_or(AccessError)
+    }
+
+    /// Like `try_with`, panics outside of a `scope`
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        self.try_with(f)
+            .expect("task-local value accessed outside of its scope")
+    }
+
+    /// Copy of the value, panics outside of a `scope`
+    pub fn get(&'static self) -> T
+    where
+        T: Clone,
+    {
+        self.with(T::clone)
+    }
+
+    /// Puts `value` into the slot of the calling CPU, returns what was there
+    fn swap(&'static self, value: Option<Arc<T>>) -> Option<Arc<T>> {
+        core::mem::replace(&mut *self.slots.get().lock(), value)
+    }
+}
+
+impl<T: Send + Sync + 'static> Default for LocalKey<T> {
+    fn default() -> Self {
+        LocalKey::new()
+    }
+}
+
+/// Future returned by `LocalKey::scope`
+pub struct Scope<T: 'static, F> {
+    key: &'static LocalKey<T>,
+    /// Moved into the key's slot while `future` is polled
+    value: Option<Arc<T>>,
+    future: F,
+}
+
+impl<T: Send + Sync + 'static, F: Future> Future for Scope<T, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        // SAFETY: `future` is never moved out of `self`, `value` isn't pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let outer = this.key.swap(this.value.take());
+        let poll = unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx);
+        this.value = this.key.swap(outer);
+        poll
+    }
+}
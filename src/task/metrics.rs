@@ -0,0 +1,201 @@
+use super::TaskId;
+use crate::{cpu, serial_print, serial_println, sync::IrqSpinLock, time};
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::{
+    cmp::Reverse,
+    fmt,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+// TASK METRICS:
+//
+// Every task spawned on an `Executor` is listed in `REGISTRY` until it finishes
+// or is cancelled. The executor counts polls and their TSC cycles, wakers
+// record the tick of the last wakeup. Polls longer than the budget set with
+// `set_poll_budget` are reported over serial: a task that doesn't yield stalls
+// every other task on its executor.
+
+/// Marker for "never woken"
+const NEVER: u64 = u64::MAX;
+
+/// Counters of one task, shared by its executor and wakers
+pub(super) struct TaskMetrics {
+    id: TaskId,
+    name: &'static str,
+    cpu: AtomicUsize,
+    polls: AtomicU64,
+    poll_cycles: AtomicU64,
+    max_poll_cycles: AtomicU64,
+    last_woken: AtomicU64,
+}
+
+impl TaskMetrics {
+    pub(super) fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub(super) fn woken(&self) {
+        self.last_woken.store(time::ticks(), Ordering::Relaxed);
+    }
+
+    /// Records the CPU polling the task, shared tasks move between CPUs
+    pub(super) fn ran_on(&self, cpu: usize) {
+        self.cpu.store(cpu, Ordering::Relaxed);
+    }
+
+    /// Records a poll that took `cycles` and warns if it exceeded the budget
+    pub(super) fn polled(&self, cycles: u64) {
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        self.poll_cycles.fetch_add(cycles, Ordering::Relaxed);
+        self.max_poll_cycles.fetch_max(cycles, Ordering::Relaxed);
+
+        let budget = POLL_BUDGET_MICROS.load(Ordering::Relaxed);
+        if budget == 0 {
+            return;
+        }
+        let budget = Duration::from_micros(budget);
+        if time::duration_to_cycles(budget).is_some_and(|limit| cycles > limit) {
+            OVER_BUDGET.fetch_add(1, Ordering::Relaxed);
+            serial_println!(
+                "WARNING: task {} ({}) polled for {:?}, budget {:?}",
+                self.id.0,
+                self.name,
+                time::cycles_to_duration(cycles).unwrap_or_default(),
+                budget
+            );
+        }
+    }
+}
+
+static REGISTRY: IrqSpinLock<BTreeMap<TaskId, Arc<TaskMetrics>>> =
+    IrqSpinLock::new(BTreeMap::new());
+/// Poll budget in microseconds, 0 if disabled
+static POLL_BUDGET_MICROS: AtomicU64 = AtomicU64::new(0);
+/// Polls that exceeded the budget
+static OVER_BUDGET: AtomicU64 = AtomicU64::new(0);
+
+/// Lists a task spawned on the calling CPU
+pub(super) fn register(id: TaskId, name: &'static str) -> Arc<TaskMetrics> {
+    let metrics = Arc::new(TaskMetrics {
+        id,
+        name,
+        cpu: AtomicUsize::new(cpu::current_id()),
+        polls: AtomicU64::new(0),
+        poll_cycles: AtomicU64::new(0),
+        max_poll_cycles: AtomicU64::new(0),
+        last_woken: AtomicU64::new(NEVER),
+    });
+    REGISTRY.lock().insert(id, metrics.clone());
+    metrics
+}
+
+pub(super) fn unregister(id: TaskId) {
+    REGISTRY.lock().remove(&id);
+}
+
+/// Warns over serial whenever a single poll takes longer than `budget`,
+/// `None` disables the warning.
+///
+/// Only checked once the TSC is calibrated, a second after boot.
+pub fn set_poll_budget(budget: Option<Duration>) {
+    let micros = budget.map_or(0, |budget| budget.as_micros().max(1) as u64);
+    POLL_BUDGET_MICROS.store(micros, Ordering::Relaxed);
+}
+
+/// Number of polls that exceeded the poll budget
+pub fn over_budget_polls() -> u64 {
+    OVER_BUDGET.load(Ordering::Relaxed)
+}
+
+/// Snapshot of the metrics of a task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: &'static str,
+    /// CPU of the executor that polled the task last
+    pub cpu: usize,
+    pub polls: u64,
+    /// TSC cycles spent in `poll`
+    pub poll_cycles: u64,
+    /// Longest single poll in TSC cycles
+    pub max_poll_cycles: u64,
+    /// Tick of the last wakeup, `None` if never woken
+    pub last_woken: Option<u64>,
+}
+
+impl TaskInfo {
+    /// Time spent in `poll`, `None` while the TSC is calibrated
+    pub fn poll_time(&self) -> Option<Duration> {
+        time::cycles_to_duration(self.poll_cycles)
+    }
+}
+
+impl fmt::Display for TaskInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>5} {:>3} {:>8} ", self.id.0, self.cpu, self.polls)?;
+        match self.poll_time() {
+            Some(time) => write!(f, "{:>10} ", time.as_micros())?,
+            None => write!(f, "{:>10} ", "-")?,
+        }
+        match time::cycles_to_duration(self.max_poll_cycles) {
+            Some(time) => write!(f, "{:>9} ", time.as_micros())?,
+            None => write!(f, "{:>9} ", "-")?,
+        }
+        match self.last_woken {
+            Some(tick) => {
+                let ago = time::ticks_to_duration(time::ticks().saturating_sub(tick));
+                write!(f, "{:>10} ", ago.as_millis())?
+            }
+            None => write!(f, "{:>10} ", "-")?,
+        }
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Metrics of every live task, busiest first
+pub fn list() -> Vec<TaskInfo> {
+    let mut tasks: Vec<TaskInfo> = REGISTRY
+        .lock()
+        .values()
+        .map(|metrics| TaskInfo {
+            id: metrics.id,
+            name: metrics.name,
+            cpu: metrics.cpu.load(Ordering::Relaxed),
+            polls: metrics.polls.load(Ordering::Relaxed),
+            poll_cycles: metrics.poll_cycles.load(Ordering::Relaxed),
+            max_poll_cycles: metrics.max_poll_cycles.load(Ordering::Relaxed),
+            last_woken: match metrics.last_woken.load(Ordering::Relaxed) {
+                NEVER => None,
+                tick => Some(tick),
+            },
+        })
+        .collect();
+    tasks.sort_by_key(|task| Reverse(task.poll_cycles));
+    tasks
+}
+
+/// Writes the task list to `out`, like `top`
+pub fn write_to(out: &mut impl fmt::Write) -> fmt::Result {
+    // WOKEN is the time since the last wakeup
+    writeln!(
+        out,
+        "   ID CPU    POLLS  POLL (us)  MAX (us) WOKEN (ms) NAME"
+    )?;
+    for info in list() {
+        writeln!(out, "{}", info)?;
+    }
+    writeln!(
+        out,
+        "{} polls over the budget of {} us",
+        over_budget_polls(),
+        POLL_BUDGET_MICROS.load(Ordering::Relaxed)
+    )
+}
+
+/// Prints the task list over serial
+pub fn dump() {
+    let mut table = String::new();
+    write_to(&mut table).unwrap();
+    serial_print!("{}", table);
+}
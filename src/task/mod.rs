@@ -1,14 +1,25 @@
 use alloc::boxed::Box;
 use core::{
+    any::type_name,
     future::Future,
     pin::Pin,
     sync::atomic::{AtomicU64, Ordering},
     task::{Context, Poll},
 };
 
+pub mod channel;
+pub mod combinator;
 pub mod executor;
+pub mod join;
 pub mod keyboard;
+pub mod local;
+pub mod metrics;
 pub mod simple_executor;
+pub mod sync;
+pub mod timer;
+
+pub use join::{JoinError, JoinHandle};
+pub use local::LocalKey;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TaskId(u64);
@@ -20,8 +31,36 @@ impl TaskId {
     }
 }
 
+/// Order in which an executor polls its ready tasks.
+///
+/// Every ready task of a higher priority is polled before the next one of a
+/// lower priority, so tasks that are always ready starve the levels below.
+/// Shared tasks (see `executor::spawn_shared`) are polled after all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    /// For I/O tasks that have to keep up with their device
+    High,
+    #[default]
+    Normal,
+    /// For background work
+    Low,
+}
+
+impl Priority {
+    /// Number of levels
+    const COUNT: usize = 3;
+
+    /// Index of the level, 0 is polled first
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
 pub struct Task {
     id: TaskId,
+    /// Shown in the task list, see `metrics`
+    name: &'static str,
+    priority: Priority,
     future: Pin<Box<dyn Future<Output = ()>>>,
 }
 
@@ -33,13 +72,39 @@ impl Task {
     /// Static lifetime required because the returned `Task`
     /// can live for an arbitrary amount of time, so future must be valid for that
     /// amount of time as well
-    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+    pub fn new<F: Future<Output = ()> + 'static>(future: F) -> Task {
         Task {
             id: TaskId::new(),
+            name: type_name::<F>(),
+            priority: Priority::Normal,
             future: Box::pin(future),
         }
     }
 
+    /// Replaces the default name, the type of the future
+    pub fn named(mut self, name: &'static str) -> Task {
+        self.name = name;
+        self
+    }
+
+    /// Replaces the default `Priority::Normal`
+    pub fn with_priority(mut self, priority: Priority) -> Task {
+        self.priority = priority;
+        self
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
         self.future.as_mut().poll(context)
     }
@@ -0,0 +1,23 @@
+pub mod mutex;
+pub mod notify;
+pub mod once_cell;
+pub mod rwlock;
+pub mod semaphore;
+
+pub use mutex::{Mutex, MutexGuard};
+pub use notify::Notify;
+pub use once_cell::OnceCell;
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{Semaphore, SemaphorePermit};
+
+// TASK SYNCHRONIZATION:
+//
+// Mutex      exclusive access, held across `.await` points
+// RwLock     shared readers or one writer
+// Semaphore  counting permits, the base of Mutex, RwLock and OnceCell
+// Notify     wakes waiting tasks without passing data
+// OnceCell   value initialized once by the first task asking for it
+//
+// A task that can't proceed is parked and woken through its waker, unlike with
+// the spinning locks in `crate::sync`, which would stall the whole executor
+// while another task on it holds the lock. Waiters are served in FIFO order.
@@ -0,0 +1,68 @@
+use super::semaphore::{Semaphore, SemaphorePermit};
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+};
+
+/// Mutex for tasks: a task waiting for the lock is parked instead of spinning,
+/// so the executor keeps running the other tasks, including the lock owner.
+///
+/// Waiters get the lock in arrival order. Holding the guard across an `.await`
+/// is fine, unlike with `spin::Mutex`.
+pub struct Mutex<T> {
+    semaphore: Semaphore,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            semaphore: Semaphore::new(1),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        MutexGuard {
+            lock: self,
+            _permit: self.semaphore.acquire().await,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        Some(MutexGuard {
+            lock: self,
+            _permit: self.semaphore.try_acquire()?,
+        })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    lock: &'a Mutex<T>,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
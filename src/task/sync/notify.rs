@@ -0,0 +1,178 @@
+use crate::sync::IrqSpinLock;
+use alloc::collections::VecDeque;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+/// Wakes waiting tasks without passing data, like a condition variable.
+///
+/// `notify_one` stores a permit if no task waits, so a notification sent
+/// before the waiter got to `notified().await` isn't lost. Both notify calls
+/// never allocate and can be used from interrupt handlers.
+pub struct Notify {
+    state: IrqSpinLock<State>,
+}
+
+struct State {
+    /// Set by `notify_one` without waiters, consumed by the next waiter
+    permit: bool,
+    waiters: VecDeque<Waiter>,
+    next_id: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Notification {
+    Pending,
+    One,
+    All,
+}
+
+struct Waiter {
+    id: u64,
+    waker: Option<Waker>,
+    notification: Notification,
+}
+
+impl State {
+    fn position(&self, id: u64) -> Option<usize> {
+        self.waiters.iter().position(|w| w.id == id)
+    }
+
+    /// Notifies the longest waiting task, or stores the permit
+    fn notify_one(&mut self) -> Option<Waker> {
+        match self
+            .waiters
+            .iter_mut()
+            .find(|w| w.notification == Notification::Pending)
+        {
+            Some(waiter) => {
+                waiter.notification = Notification::One;
+                waiter.waker.take()
+            }
+            None => {
+                self.permit = true;
+                None
+            }
+        }
+    }
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Notify {
+            state: IrqSpinLock::new(State {
+                permit: false,
+                waiters: VecDeque::new(),
+                next_id: 0,
+            }),
+        }
+    }
+
+    /// Waits for a notification, starting from the first poll
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            id: None,
+        }
+    }
+
+    /// Wakes the longest waiting task, or the next one to wait if none does
+    pub fn notify_one(&self) {
+        let waker = self.state.lock().notify_one();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Wakes every task waiting right now, stores no permit
+    pub fn notify_waiters(&self) {
+        let mut state = self.state.lock();
+        // woken with the lock held, collecting the wakers would allocate
+        for waiter in state.waiters.iter_mut() {
+            if waiter.notification == Notification::Pending {
+                waiter.notification = Notification::All;
+                if let Some(waker) = waiter.waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future returned by `Notify::notified`
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    /// Id of the waiter entry once queued
+    id: Option<u64>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let mut state = self.notify.state.lock();
+        let Some(id) = self.id else {
+            if state.permit {
+                state.permit = false;
+                return Poll::Ready(());
+            }
+            let id = state.next_id;
+            state.next_id += 1;
+            state.waiters.push_back(Waiter {
+                id,
+                waker: Some(cx.waker().clone()),
+                notification: Notification::Pending,
+            });
+            drop(state);
+            self.id = Some(id);
+            return Poll::Pending;
+        };
+
+        let position = state.position(id).expect("notify waiter lost");
+        let waiter = &mut state.waiters[position];
+        if waiter.notification != Notification::Pending {
+            state.waiters.remove(position);
+            drop(state);
+            self.id = None;
+            return Poll::Ready(());
+        }
+        if !waiter
+            .waker
+            .as_ref()
+            .is_some_and(|w| w.will_wake(cx.waker()))
+        {
+            waiter.waker = Some(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let Some(id) = self.id else {
+            return;
+        };
+        let waker = {
+            let mut state = self.notify.state.lock();
+            let position = state.position(id).expect("notify waiter lost");
+            let waiter = state.waiters.remove(position).expect("notify waiter lost");
+            // a `notify_one` meant for a waiter that gave up goes to the next one
+            if waiter.notification == Notification::One {
+                state.notify_one()
+            } else {
+                None
+            }
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use super::semaphore::Semaphore;
+use core::future::Future;
+use spin::Once;
+
+/// Cell written once, by the first task to initialize it.
+///
+/// Tasks calling `get_or_init` meanwhile are parked until the value is ready.
+/// If the initializing task is cancelled, the next waiter runs its initializer.
+pub struct OnceCell<T> {
+    value: Once<T>,
+    /// Held by the task running an initializer
+    init: Semaphore,
+}
+
+impl<T> OnceCell<T> {
+    pub const fn new() -> Self {
+        OnceCell {
+            value: Once::new(),
+            init: Semaphore::new(1),
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.value.r#try()
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.get().is_some()
+    }
+
+    /// Stores `value` unless the cell is set or being initialized, in which case
+    /// it is handed back
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let Some(_permit) = self.init.try_acquire() else {
+            return Err(value);
+        };
+        if self.is_initialized() {
+            return Err(value);
+        }
+        self.value.call_once(|| value);
+        Ok(())
+    }
+
+    /// Returns the value, running `init` first if the cell is empty
+    pub async fn get_or_init<F, Fut>(&self, init: F) -> &T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        if let Some(value) = self.get() {
+            return value;
+        }
+        let _permit = self.init.acquire().await;
+        if let Some(value) = self.get() {
+            return value;
+        }
+        let value = init().await;
+        self.value.call_once(|| value)
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,194 @@
+use crate::sync::IrqSpinLock;
+use alloc::collections::VecDeque;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+/// Counting semaphore whose waiters are served in arrival order.
+///
+/// A large request at the front of the queue blocks smaller ones behind it,
+/// so writers of `RwLock` can't be starved by a stream of readers.
+pub struct Semaphore {
+    state: IrqSpinLock<State>,
+}
+
+struct State {
+    permits: usize,
+    waiters: VecDeque<Waiter>,
+    next_id: u64,
+}
+
+struct Waiter {
+    id: u64,
+    needed: usize,
+    waker: Option<Waker>,
+    /// The permits were handed over, the waiter only has to pick them up
+    granted: bool,
+}
+
+impl State {
+    /// Hands out permits to waiters from the front of the queue and wakes the
+    /// waiters served, with the lock held: collecting the wakers would allocate
+    fn grant(&mut self) {
+        for waiter in self.waiters.iter_mut().filter(|w| !w.granted) {
+            if waiter.needed > self.permits {
+                break;
+            }
+            self.permits -= waiter.needed;
+            waiter.granted = true;
+            if let Some(waker) = waiter.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn position(&self, id: u64) -> Option<usize> {
+        self.waiters.iter().position(|w| w.id == id)
+    }
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore {
+            state: IrqSpinLock::new(State {
+                permits,
+                waiters: VecDeque::new(),
+                next_id: 0,
+            }),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().permits
+    }
+
+    pub fn acquire(&self) -> Acquire<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Waits until `count` permits are available at once
+    pub fn acquire_many(&self, count: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            count,
+            id: None,
+        }
+    }
+
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    /// Takes `count` permits if they are available and nobody waits for permits
+    pub fn try_acquire_many(&self, count: usize) -> Option<SemaphorePermit<'_>> {
+        let mut state = self.state.lock();
+        if !state.waiters.is_empty() || state.permits < count {
+            return None;
+        }
+        state.permits -= count;
+        Some(SemaphorePermit {
+            semaphore: self,
+            count,
+        })
+    }
+
+    /// Returns `count` permits and wakes the waiters that can proceed now
+    pub fn add_permits(&self, count: usize) {
+        let mut state = self.state.lock();
+        state.permits += count;
+        state.grant();
+    }
+}
+
+/// Permits taken from a `Semaphore`, returned when dropped
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    count: usize,
+}
+
+impl SemaphorePermit<'_> {
+    /// Keeps the permits taken for good
+    pub fn forget(mut self) {
+        self.count = 0;
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.count > 0 {
+            self.semaphore.add_permits(self.count);
+        }
+    }
+}
+
+/// Future returned by `Semaphore::acquire`, queued on its first poll
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    count: usize,
+    /// Id of the waiter entry once queued
+    id: Option<u64>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<SemaphorePermit<'a>> {
+        let semaphore = self.semaphore;
+        let count = self.count;
+        let mut state = semaphore.state.lock();
+
+        let Some(id) = self.id else {
+            if state.waiters.is_empty() && state.permits >= count {
+                state.permits -= count;
+                return Poll::Ready(SemaphorePermit { semaphore, count });
+            }
+            let id = state.next_id;
+            state.next_id += 1;
+            state.waiters.push_back(Waiter {
+                id,
+                needed: count,
+                waker: Some(cx.waker().clone()),
+                granted: false,
+            });
+            self.id = Some(id);
+            return Poll::Pending;
+        };
+
+        let position = state.position(id).expect("semaphore waiter lost");
+        let waiter = &mut state.waiters[position];
+        if waiter.granted {
+            state.waiters.remove(position);
+            self.id = None;
+            return Poll::Ready(SemaphorePermit { semaphore, count });
+        }
+        if !waiter
+            .waker
+            .as_ref()
+            .is_some_and(|w| w.will_wake(cx.waker()))
+        {
+            waiter.waker = Some(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let Some(id) = self.id else {
+            return;
+        };
+        let mut state = self.semaphore.state.lock();
+        let position = state.position(id).expect("semaphore waiter lost");
+        let waiter = state
+            .waiters
+            .remove(position)
+            .expect("semaphore waiter lost");
+        if waiter.granted {
+            state.permits += waiter.needed;
+        }
+        // a cancelled waiter at the front may have blocked others
+        state.grant();
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{
+    deferred::{self, Work},
+    sync::IrqSpinLock,
+    time,
+};
+use alloc::collections::BTreeMap;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+// TASK TIMERS:
+//
+// A sleeping task registers its waker under its deadline tick. The timer
+// interrupt only compares the current tick with the earliest deadline and, once
+// it is due, schedules `fire_expired` as deferred work, which wakes the tasks
+// from the executor loop. Timers have the resolution of the timer tick.
+
+/// Registered timers by deadline tick and a unique id
+static TIMERS: IrqSpinLock<BTreeMap<(u64, u64), Waker>> = IrqSpinLock::new(BTreeMap::new());
+/// Earliest deadline in `TIMERS`, `u64::MAX` if there is none
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+/// Set while `fire_expired` is queued, so it is queued once
+static FIRE_QUEUED: AtomicBool = AtomicBool::new(false);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Called by `time::tick` in the timer interrupt.
+///
+/// Must not block or allocate.
+pub(crate) fn on_tick(now: u64) {
+    let due = now >= NEXT_DEADLINE.load(Ordering::Acquire);
+    if due
+        && !FIRE_QUEUED.swap(true, Ordering::AcqRel)
+        && deferred::schedule(Work::new(fire_expired, 0)).is_err()
+    {
+        // the queue is full, retried on the next tick. `Sleep` makes sure it
+        // exists at all, so this can't go on forever.
+        FIRE_QUEUED.store(false, Ordering::Release);
+    }
+}
+
+/// Wakes the tasks whose deadline passed
+fn fire_expired(_: usize) {
+    FIRE_QUEUED.store(false, Ordering::Release);
+    let now = time::ticks();
+    let expired = {
+        let mut timers = TIMERS.lock();
+        let pending = timers.split_off(&(now + 1, 0));
+        let expired = core::mem::replace(&mut *timers, pending);
+        NEXT_DEADLINE.store(first_deadline(&timers), Ordering::Release);
+        expired
+    };
+    for waker in expired.into_values() {
+        waker.wake();
+    }
+}
+
+fn first_deadline(timers: &BTreeMap<(u64, u64), Waker>) -> u64 {
+    timers
+        .first_key_value()
+        .map_or(u64::MAX, |(&(deadline, _), _)| deadline)
+}
+
+/// Number of tasks waiting for a timer
+pub fn pending_timers() -> usize {
+    TIMERS.lock().len()
+}
+
+/// Waits at least `duration`, rounded up to whole timer ticks
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(time::ticks().saturating_add(time::duration_to_ticks(duration)))
+}
+
+/// Waits until the tick count reaches `deadline`
+pub fn sleep_until(deadline: u64) -> Sleep {
+    Sleep {
+        deadline,
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        registered: false,
+    }
+}
+
+/// Future returned by `sleep`, registers its timer on the first poll
+pub struct Sleep {
+    deadline: u64,
+    id: u64,
+    registered: bool,
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+
+    pub fn is_elapsed(&self) -> bool {
+        time::ticks() >= self.deadline
+    }
+
+    fn deregister(&mut self) {
+        if self.registered {
+            self.registered = false;
+            let mut timers = TIMERS.lock();
+            timers.remove(&(self.deadline, self.id));
+            NEXT_DEADLINE.store(first_deadline(&timers), Ordering::Release);
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.is_elapsed() {
+            self.deregister();
+            return Poll::Ready(());
+        }
+        assert!(
+            deferred::is_initialized(),
+            "timers fire through deferred work, call deferred::init first"
+        );
+        let mut timers = TIMERS.lock();
+        let waker = timers
+            .entry((self.deadline, self.id))
+            .or_insert_with(|| cx.waker().clone());
+        if !waker.will_wake(cx.waker()) {
+            *waker = cx.waker().clone();
+        }
+        NEXT_DEADLINE.fetch_min(self.deadline, Ordering::AcqRel);
+        drop(timers);
+        self.registered = true;
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.deregister();
+    }
+}
+
+/// The deadline of `timeout` passed first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Runs `future` until it completes or `duration` passed, whichever is first.
+/// On timeout the future is dropped.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+    }
+}
+
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // SAFETY: `future` is never moved out of `self`, `sleep` is `Unpin`
+        let this = unsafe { self.get_unchecked_mut() };
+        // polled first, so a future that is ready in time always wins
+        if let Poll::Ready(output) = unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut this.sleep).poll(cx).map(|()| Err(Elapsed))
+    }
+}
@@ -0,0 +1,357 @@
+mod context;
+pub mod policy;
+pub mod scheduler;
+
+use crate::{
+    cpu,
+    memory::stack::{self, StackBounds},
+    time,
+    userspace::{self, UserState},
+};
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+use policy::{Accounting, Policy};
+use scheduler::SCHEDULER;
+use x86_64::instructions::interrupts;
+
+// KERNEL THREADS:
+//
+// Every thread has its own kernel stack and is preempted by the timer interrupt
+// when its `Policy` says so, so a thread that never yields can't freeze the
+// machine (unless it is a real-time `Fifo` thread). The thread `kernel_main`
+// runs on becomes thread 0 in `init`, the async executor keeps running there
+// (or on any other thread that calls `Executor::run`).
+//
+// Ready ──scheduled──> Running ──yield/preempted──> Ready
+//                        │
+//                        ├──sleep/join/park──> Sleeping/Blocked/Parked ──woken──> Ready
+//                        └──exit──> Exited (stack freed by the next thread)
+
+/// Size of a thread's stack, excluding the guard page
+const STACK_PAGES: u64 = 16;
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+/// CPU that called `init`, threads only run there
+static SCHEDULER_CPU: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        // 0 is the boot thread
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    Ready,
+    Running,
+    /// Waiting for a timer tick, see `sleep`
+    Sleeping,
+    /// Waiting for another thread to exit, see `join`
+    Blocked,
+    /// Waiting for `unpark`, see `park`
+    Parked,
+    Exited,
+}
+
+struct Thread {
+    name: String,
+    state: ThreadState,
+    /// `None` for the boot thread, which runs on the bootloader's stack
+    stack: Option<StackBounds>,
+    /// Saved stack pointer while the thread is not running
+    stack_pointer: u64,
+    user_state: UserState,
+    /// Threads blocked in `join` on this one
+    joiners: Vec<ThreadId>,
+    /// Set by `unpark` while the thread wasn't parked, the next `park` returns
+    unparked: bool,
+    policy: Policy,
+    accounting: Accounting,
+}
+
+impl Drop for Thread {
+    fn drop(&mut self) {
+        if let Some(stack) = self.stack {
+            stack::free_stack(stack);
+        }
+    }
+}
+
+/// Snapshot of a thread
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: String,
+    pub state: ThreadState,
+    pub policy: Policy,
+    pub accounting: Accounting,
+}
+
+/// Turns the running code into thread 0 and starts the idle thread.
+///
+/// Must be called after `memory::install`. Preemption starts with the next
+/// timer interrupt.
+pub fn init() {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        scheduler.threads.insert(
+            ThreadId(0),
+            Box::new(Thread {
+                name: String::from("main"),
+                state: ThreadState::Running,
+                stack: None,
+                stack_pointer: 0,
+                user_state: userspace::save_state(),
+                joiners: Vec::new(),
+                unparked: false,
+                policy: Policy::default(),
+                accounting: Accounting::default(),
+            }),
+        );
+        scheduler.current = ThreadId(0);
+    });
+    SCHEDULER_CPU.store(cpu::current_id(), Ordering::Relaxed);
+    let idle = spawn("idle", || loop {
+        interrupts::enable_and_hlt();
+    });
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        scheduler.ready.remove(idle);
+        scheduler.idle = Some(idle);
+    });
+    INITIALIZED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_initialized() -> bool {
+    INITIALIZED.load(Ordering::Relaxed)
+}
+
+/// Whether the calling CPU runs the thread scheduler, other CPUs can't switch threads
+pub(crate) fn schedules_here() -> bool {
+    is_initialized() && SCHEDULER_CPU.load(Ordering::Relaxed) == cpu::current_id()
+}
+
+/// Starts a new thread running `f` with the default `Fair` policy, it exits
+/// when `f` returns.
+///
+/// Panics if no stack can be allocated.
+pub fn spawn(name: &str, f: impl FnOnce() + Send + 'static) -> ThreadId {
+    spawn_with_policy(name, Policy::default(), f)
+}
+
+/// Starts a new thread running `f` with scheduling `policy`, see `spawn`.
+pub fn spawn_with_policy(
+    name: &str,
+    policy: Policy,
+    f: impl FnOnce() + Send + 'static,
+) -> ThreadId {
+    let stack = stack::alloc_stack(STACK_PAGES).expect("thread stack allocation failed");
+    // double boxed to pass a thin pointer through a register
+    let closure: Box<Box<dyn FnOnce() + Send>> = Box::new(Box::new(f));
+    let arg = Box::into_raw(closure) as u64;
+    let stack_pointer = unsafe { context::prepare_stack(stack.end().as_u64(), thread_start, arg) };
+
+    let id = ThreadId::new();
+    let thread = Box::new(Thread {
+        name: String::from(name),
+        state: ThreadState::Ready,
+        stack: Some(stack),
+        stack_pointer,
+        // starts in the kernel address space without a user program
+        user_state: UserState::kernel(),
+        joiners: Vec::new(),
+        unparked: false,
+        policy,
+        accounting: Accounting::default(),
+    });
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        scheduler.threads.insert(id, thread);
+        scheduler.make_ready(id);
+    });
+    id
+}
+
+/// Entered through `context::thread_entry` the first time a thread runs.
+extern "C" fn thread_start(arg: u64) -> ! {
+    scheduler::finish_switch();
+    interrupts::enable();
+    let closure = unsafe { Box::from_raw(arg as *mut Box<dyn FnOnce() + Send>) };
+    closure();
+    exit()
+}
+
+/// Id of the running thread
+pub fn current() -> ThreadId {
+    interrupts::without_interrupts(|| SCHEDULER.lock().current)
+}
+
+/// Gives up the CPU to the next ready thread, returns right away if there is none
+/// or when called on another CPU than the one that called `init`.
+pub fn yield_now() {
+    if !schedules_here() {
+        return;
+    }
+    interrupts::without_interrupts(|| unsafe {
+        scheduler::reschedule(SCHEDULER.lock(), ThreadState::Ready);
+    });
+}
+
+/// Checks whether another thread is waiting for the CPU.
+pub fn others_ready() -> bool {
+    schedules_here() && interrupts::without_interrupts(|| !SCHEDULER.lock().ready.is_empty())
+}
+
+/// Blocks the running thread for at least `duration`.
+pub fn sleep(duration: Duration) {
+    let deadline = time::ticks().saturating_add(time::duration_to_ticks(duration));
+    if !is_initialized() {
+        while time::ticks() < deadline {
+            interrupts::enable_and_hlt();
+        }
+        return;
+    }
+    interrupts::without_interrupts(|| unsafe {
+        let mut scheduler = SCHEDULER.lock();
+        let current = scheduler.current;
+        scheduler.sleeping.push((deadline, current));
+        scheduler::reschedule(scheduler, ThreadState::Sleeping);
+    });
+}
+
+/// Ends the running thread and wakes up threads joining it.
+pub fn exit() -> ! {
+    interrupts::disable();
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current;
+    assert_ne!(current, ThreadId(0), "the boot thread can't exit");
+    let joiners = core::mem::take(
+        &mut scheduler
+            .threads
+            .get_mut(&current)
+            .expect("current thread missing")
+            .joiners,
+    );
+    for joiner in joiners {
+        scheduler.make_ready(joiner);
+    }
+    unsafe { scheduler::reschedule(scheduler, ThreadState::Exited) };
+    unreachable!("exited thread was scheduled again");
+}
+
+/// Blocks until the thread `id` exited, returns right away if it doesn't exist.
+pub fn join(id: ThreadId) {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let current = scheduler.current;
+        assert_ne!(current, id, "thread joining itself");
+        match scheduler.threads.get_mut(&id) {
+            Some(thread) => thread.joiners.push(current),
+            None => return,
+        }
+        unsafe { scheduler::reschedule(scheduler, ThreadState::Blocked) };
+    });
+}
+
+/// Blocks the running thread until `unpark` is called for it.
+///
+/// Returns right away if `unpark` was called since the last `park`, and on CPUs
+/// that don't schedule threads, so callers recheck what they wait for.
+pub fn park() {
+    if !schedules_here() {
+        core::hint::spin_loop();
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let current = scheduler.current;
+        let thread = scheduler
+            .threads
+            .get_mut(&current)
+            .expect("current thread missing");
+        if core::mem::take(&mut thread.unparked) {
+            return;
+        }
+        unsafe { scheduler::reschedule(scheduler, ThreadState::Parked) };
+    });
+}
+
+/// Wakes the thread `id` from `park`, or makes its next `park` return right away.
+pub fn unpark(id: ThreadId) {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let Some(thread) = scheduler.threads.get_mut(&id) else {
+            return;
+        };
+        if thread.state == ThreadState::Parked {
+            scheduler.make_ready(id);
+        } else {
+            thread.unparked = true;
+        }
+    });
+}
+
+/// Changes the scheduling policy of thread `id`, takes effect at its next
+/// scheduling decision. Returns `false` if the thread doesn't exist.
+pub fn set_policy(id: ThreadId, policy: Policy) -> bool {
+    interrupts::without_interrupts(|| match SCHEDULER.lock().threads.get_mut(&id) {
+        Some(thread) => {
+            thread.policy = policy;
+            true
+        }
+        None => false,
+    })
+}
+
+/// CPU accounting of thread `id`, `None` if it doesn't exist (anymore)
+pub fn accounting(id: ThreadId) -> Option<Accounting> {
+    interrupts::without_interrupts(|| {
+        SCHEDULER
+            .lock()
+            .threads
+            .get(&id)
+            .map(|thread| thread.accounting)
+    })
+}
+
+/// Runtime of thread `id`, `None` if it doesn't exist (anymore)
+pub fn runtime(id: ThreadId) -> Option<Duration> {
+    accounting(id).map(|accounting| time::ticks_to_duration(accounting.runtime_ticks))
+}
+
+/// Snapshot of all threads, ordered by id
+pub fn list() -> Vec<ThreadInfo> {
+    interrupts::without_interrupts(|| {
+        SCHEDULER
+            .lock()
+            .threads
+            .iter()
+            .map(|(&id, thread)| ThreadInfo {
+                id,
+                name: thread.name.clone(),
+                state: thread.state,
+                policy: thread.policy,
+                accounting: thread.accounting,
+            })
+            .collect()
+    })
+}
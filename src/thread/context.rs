@@ -0,0 +1,84 @@
+use core::arch::naked_asm;
+
+// SAVED CONTEXT:
+//
+// A suspended thread is just a stack pointer. Everything else `switch` has to
+// preserve is pushed onto the thread's own stack before switching away:
+//
+// higher addresses
+// ├── return address  (back into the caller of `switch`, or `thread_entry`)
+// ├── rflags          (restores the interrupt flag of the switched-to thread)
+// ├── rbp
+// ├── rbx
+// ├── r12
+// ├── r13
+// ├── r14
+// └── r15             <- saved stack pointer
+//
+// Caller-saved registers are already spilled by the compiler around the call.
+
+/// Number of words `switch` keeps on a suspended thread's stack
+const CONTEXT_WORDS: usize = 8;
+
+/// # Safety
+/// Saves the callee-saved registers on the current stack, stores the stack
+/// pointer in `*old_stack_pointer` and resumes the thread suspended at
+/// `new_stack_pointer`.
+///
+/// Unsafe because `new_stack_pointer` must come from a previous `switch` or
+/// `prepare_stack`, and interrupts must be disabled.
+#[unsafe(naked)]
+pub(super) unsafe extern "C" fn switch(old_stack_pointer: *mut u64, new_stack_pointer: u64) {
+    naked_asm!(
+        "pushfq",
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+        "mov rsp, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "popfq",
+        "ret",
+    );
+}
+
+/// # Safety
+/// Writes an initial context to the top of the stack ending at `stack_end`, so
+/// that switching to the returned stack pointer calls `start(arg)`.
+///
+/// Unsafe because `stack_end` must be the 16-byte aligned end of an unused stack.
+pub(super) unsafe fn prepare_stack(
+    stack_end: u64,
+    start: extern "C" fn(u64) -> !,
+    arg: u64,
+) -> u64 {
+    // `thread_entry` is entered by `ret`, leave one word of padding so rsp is
+    // 16-byte aligned at its `call` as the ABI demands
+    let stack_pointer = stack_end - 8 * (CONTEXT_WORDS as u64 + 2);
+    let context: [u64; CONTEXT_WORDS] = [
+        0,                                // r15
+        0,                                // r14
+        arg,                              // r13
+        start as *const () as u64,        // r12
+        0,                                // rbx
+        0,                                // rbp
+        0x2, // rflags: interrupts disabled, bit 1 is reserved and always set
+        thread_entry as *const () as u64, // return address
+    ];
+    (stack_pointer as *mut [u64; CONTEXT_WORDS]).write(context);
+    stack_pointer
+}
+
+/// First code a new thread runs, with the values `prepare_stack` put into r12 and r13.
+#[unsafe(naked)]
+unsafe extern "C" fn thread_entry() -> ! {
+    naked_asm!("mov rdi, r13", "call r12", "ud2");
+}
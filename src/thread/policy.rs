@@ -0,0 +1,197 @@
+use super::{Thread, ThreadId};
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use core::cmp::Reverse;
+
+// SCHEDULING CLASSES:
+//
+// Every thread has a `Policy`, and the ready thread of the highest class runs:
+//
+// Fifo      real-time, runs until it blocks or yields, only preempted by a
+//           higher priority Fifo thread
+// Priority  fixed priority, time sliced; waiting threads gain one level per
+//           `AGING_TICKS` so low priorities can't starve within the class
+// Fair      CFS-like, the thread with the smallest virtual runtime runs next;
+//           virtual runtime grows slower for threads with a lower nice value
+//
+// Ties within a class are broken in FIFO order of becoming ready.
+
+/// Ticks a `Priority` thread has to wait to gain one priority level
+pub const AGING_TICKS: u64 = 10;
+/// Virtual runtime a nice 0 thread accumulates per tick
+const VRUNTIME_PER_TICK: u64 = 1000;
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// Weight of nice values -20..=19, each step is ~25% more or less CPU (from Linux)
+const NICE_WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Real-time first-in first-out, higher `priority` runs first
+    Fifo { priority: u8 },
+    /// Fixed priority with aging, higher `priority` runs first
+    Priority { priority: u8 },
+    /// Fair share of the CPU weighted by `nice`, -20 (most) to 19 (least)
+    Fair { nice: i8 },
+}
+
+impl Policy {
+    fn class(self) -> u8 {
+        match self {
+            Policy::Fifo { .. } => 2,
+            Policy::Priority { .. } => 1,
+            Policy::Fair { .. } => 0,
+        }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::Fair { nice: 0 }
+    }
+}
+
+/// Per-thread CPU accounting
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Accounting {
+    /// Timer ticks spent running
+    pub runtime_ticks: u64,
+    /// Weighted runtime the `Fair` class orders by
+    pub vruntime: u64,
+    /// Number of times the thread was switched to
+    pub switches: u64,
+    /// Tick the thread last became ready, for aging and FIFO order
+    pub ready_since: u64,
+}
+
+impl Accounting {
+    /// Charges one tick of runtime to a thread running with `policy`.
+    pub(super) fn charge_tick(&mut self, policy: Policy) {
+        self.runtime_ticks += 1;
+        let weight = match policy {
+            Policy::Fair { nice } => NICE_WEIGHTS[(nice.clamp(-20, 19) + 20) as usize],
+            _ => NICE_0_WEIGHT,
+        };
+        self.vruntime += VRUNTIME_PER_TICK * NICE_0_WEIGHT / weight;
+    }
+}
+
+/// Sort key of a ready thread, the largest one runs next
+fn rank(thread: &Thread, now: u64) -> (u8, u64, Reverse<u64>) {
+    let accounting = &thread.accounting;
+    let level = match thread.policy {
+        Policy::Fifo { priority } => u64::from(priority),
+        Policy::Priority { priority } => {
+            let aged = (now - accounting.ready_since) / AGING_TICKS;
+            (u64::from(priority) + aged).min(u64::from(u8::MAX))
+        }
+        Policy::Fair { .. } => u64::MAX - accounting.vruntime,
+    };
+    (
+        thread.policy.class(),
+        level,
+        Reverse(accounting.ready_since),
+    )
+}
+
+/// Ready threads of all classes
+pub(super) struct RunQueue {
+    threads: Vec<ThreadId>,
+    /// Smallest virtual runtime handed the CPU so far, newly ready `Fair`
+    /// threads start here so sleeping doesn't bank CPU time
+    min_vruntime: u64,
+}
+
+impl RunQueue {
+    pub(super) const fn new() -> Self {
+        RunQueue {
+            threads: Vec::new(),
+            min_vruntime: 0,
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.threads.is_empty()
+    }
+
+    pub(super) fn push(&mut self, id: ThreadId, thread: &mut Thread, now: u64) {
+        thread.accounting.ready_since = now;
+        if let Policy::Fair { .. } = thread.policy {
+            thread.accounting.vruntime = thread.accounting.vruntime.max(self.min_vruntime);
+        }
+        self.threads.push(id);
+    }
+
+    pub(super) fn remove(&mut self, id: ThreadId) {
+        self.threads.retain(|&thread| thread != id);
+    }
+
+    fn best(
+        &self,
+        threads: &BTreeMap<ThreadId, Box<Thread>>,
+        now: u64,
+    ) -> Option<(usize, ThreadId)> {
+        self.threads
+            .iter()
+            .enumerate()
+            .max_by_key(|&(_, id)| rank(&threads[id], now))
+            .map(|(index, &id)| (index, id))
+    }
+
+    /// Removes and returns the thread that should run next.
+    pub(super) fn pop(
+        &mut self,
+        threads: &BTreeMap<ThreadId, Box<Thread>>,
+        now: u64,
+    ) -> Option<ThreadId> {
+        let (index, id) = self.best(threads, now)?;
+        self.threads.remove(index);
+        if let Policy::Fair { .. } = threads[&id].policy {
+            self.min_vruntime = self.min_vruntime.max(threads[&id].accounting.vruntime);
+        }
+        Some(id)
+    }
+
+    /// Checks whether the running thread `current` should give up the CPU.
+    pub(super) fn should_preempt(
+        &self,
+        threads: &BTreeMap<ThreadId, Box<Thread>>,
+        current: &Thread,
+        slice_expired: bool,
+        now: u64,
+    ) -> bool {
+        let best = match self.best(threads, now) {
+            Some((_, id)) => &threads[&id],
+            None => return false,
+        };
+        let (best_class, best_level, _) = rank(best, now);
+        if best_class != current.policy.class() {
+            return best_class > current.policy.class();
+        }
+        match current.policy {
+            Policy::Fifo { priority } => best_level > u64::from(priority),
+            Policy::Priority { priority } => {
+                best_level > u64::from(priority)
+                    || (slice_expired && best_level == u64::from(priority))
+            }
+            Policy::Fair { .. } => slice_expired,
+        }
+    }
+}
+
+#[test_case]
+fn test_nice_weights() {
+    let mut normal = Accounting::default();
+    let mut nice = Accounting::default();
+    let mut favored = Accounting::default();
+    normal.charge_tick(Policy::Fair { nice: 0 });
+    nice.charge_tick(Policy::Fair { nice: 5 });
+    favored.charge_tick(Policy::Fair { nice: -5 });
+    assert_eq!(normal.vruntime, VRUNTIME_PER_TICK);
+    assert!(nice.vruntime > normal.vruntime);
+    assert!(favored.vruntime < normal.vruntime);
+    assert_eq!(normal.runtime_ticks, 1);
+}
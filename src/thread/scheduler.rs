@@ -0,0 +1,170 @@
+use super::{context, policy::RunQueue, Thread, ThreadId, ThreadState};
+use crate::{time, userspace};
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use spin::{Mutex, MutexGuard};
+
+/// Timer ticks a time-sliced thread may run before it is preempted (20ms)
+pub const TIME_SLICE_TICKS: u64 = 2;
+
+/// Scheduler state, only locked with interrupts disabled
+pub(super) struct Scheduler {
+    pub(super) threads: BTreeMap<ThreadId, Box<Thread>>,
+    /// Threads waiting for the CPU, ordered by their `Policy`
+    pub(super) ready: RunQueue,
+    /// Sleeping threads and the tick they wake up at
+    pub(super) sleeping: Vec<(u64, ThreadId)>,
+    pub(super) current: ThreadId,
+    /// Runs when no other thread is ready, never queued in `ready`
+    pub(super) idle: Option<ThreadId>,
+    /// Exited threads whose stack can only be freed once we switched away from it.
+    /// Still boxed, `reschedule` holds a pointer into the thread while switching.
+    #[allow(clippy::vec_box)]
+    pub(super) dead: Vec<Box<Thread>>,
+    /// Ticks left in the time slice of the current thread
+    pub(super) slice_left: u64,
+}
+
+pub(super) static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler {
+    threads: BTreeMap::new(),
+    ready: RunQueue::new(),
+    sleeping: Vec::new(),
+    current: ThreadId(0),
+    idle: None,
+    dead: Vec::new(),
+    slice_left: TIME_SLICE_TICKS,
+});
+
+impl Scheduler {
+    /// Moves `thread` to the ready queue.
+    pub(super) fn make_ready(&mut self, thread: ThreadId) {
+        if let Some(entry) = self.threads.get_mut(&thread) {
+            entry.state = ThreadState::Ready;
+            if Some(thread) != self.idle {
+                self.ready.push(thread, entry, time::ticks());
+            }
+        }
+    }
+
+    /// Moves sleepers whose deadline passed to the ready queue.
+    fn wake_sleepers(&mut self, now: u64) {
+        let mut woken = Vec::new();
+        self.sleeping.retain(|&(deadline, thread)| {
+            let due = deadline <= now;
+            if due {
+                woken.push(thread);
+            }
+            !due
+        });
+        for thread in woken {
+            self.make_ready(thread);
+        }
+    }
+}
+
+/// # Safety
+/// Puts the current thread into `state` and switches to the next ready thread.
+///
+/// Returns once the current thread is scheduled again (never for `Exited`).
+/// Unsafe because interrupts must be disabled.
+pub(super) unsafe fn reschedule(mut scheduler_guard: MutexGuard<Scheduler>, state: ThreadState) {
+    let scheduler = &mut *scheduler_guard;
+    let old = scheduler.current;
+    let idle = scheduler.idle;
+    if state == ThreadState::Ready {
+        scheduler.make_ready(old);
+    } else if let Some(thread) = scheduler.threads.get_mut(&old) {
+        thread.state = state;
+    }
+
+    let next = match scheduler.ready.pop(&scheduler.threads, time::ticks()) {
+        Some(next) => next,
+        None if state == ThreadState::Ready => old,
+        None => idle.expect("no thread left to run"),
+    };
+    scheduler.slice_left = TIME_SLICE_TICKS;
+    if next == old {
+        if let Some(thread) = scheduler.threads.get_mut(&old) {
+            thread.state = ThreadState::Running;
+        }
+        return;
+    }
+
+    // the outgoing thread's box stays alive (in `threads` or `dead`) until the
+    // incoming thread runs `finish_switch`, so the pointer stays valid
+    let user_state = userspace::save_state();
+    let old_stack_pointer: *mut u64 = if state == ThreadState::Exited {
+        let mut thread = scheduler
+            .threads
+            .remove(&old)
+            .expect("current thread missing");
+        let pointer = &mut thread.stack_pointer as *mut u64;
+        scheduler.dead.push(thread);
+        pointer
+    } else {
+        let thread = scheduler
+            .threads
+            .get_mut(&old)
+            .expect("current thread missing");
+        thread.user_state = user_state;
+        &mut thread.stack_pointer as *mut u64
+    };
+    let thread = scheduler
+        .threads
+        .get_mut(&next)
+        .expect("next thread missing");
+    thread.state = ThreadState::Running;
+    thread.accounting.switches += 1;
+    let new_stack_pointer = thread.stack_pointer;
+    userspace::restore_state(&thread.user_state);
+    scheduler.current = next;
+    drop(scheduler_guard);
+
+    context::switch(old_stack_pointer, new_stack_pointer);
+    finish_switch();
+}
+
+/// Cleans up after a context switch, run by the thread that was switched to.
+///
+/// Must be called with interrupts disabled.
+pub(super) fn finish_switch() {
+    let dead = core::mem::take(&mut SCHEDULER.lock().dead);
+    // frees the stacks, takes the memory lock so not done under the scheduler lock
+    drop(dead);
+}
+
+/// Called by the timer interrupt handler after the EOI was sent.
+///
+/// Charges the tick to the current thread, wakes sleeping threads and
+/// preempts the current thread if its policy says so, see
+/// `RunQueue::should_preempt`. The idle thread is preempted as soon as any
+/// thread is ready.
+pub(crate) fn tick() {
+    if !super::is_initialized() {
+        return;
+    }
+    let mut scheduler = SCHEDULER.lock();
+    let now = time::ticks();
+    scheduler.wake_sleepers(now);
+    scheduler.slice_left = scheduler.slice_left.saturating_sub(1);
+
+    let current = scheduler.current;
+    let preempt = if Some(current) == scheduler.idle {
+        !scheduler.ready.is_empty()
+    } else {
+        let thread = scheduler
+            .threads
+            .get_mut(&current)
+            .expect("current thread missing");
+        thread.accounting.charge_tick(thread.policy);
+        scheduler.ready.should_preempt(
+            &scheduler.threads,
+            &scheduler.threads[&current],
+            scheduler.slice_left == 0,
+            now,
+        )
+    };
+    if preempt {
+        // interrupts are disabled in interrupt handlers
+        unsafe { reschedule(scheduler, ThreadState::Ready) };
+    }
+}
@@ -0,0 +1,112 @@
+use crate::task;
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use x86_64::instructions::{interrupts, port::Port};
+
+// PIT = programmable interval timer (Intel 8253/8254)
+/// Input clock of the PIT in Hz
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+/// Rate at which the timer interrupt fires; one tick every 10ms
+pub const TIMER_HZ: u32 = 100;
+
+const PIT_CHANNEL_0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+
+/// Number of timer interrupts since `init`
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// Time stamp counter at the first tick, the start of the calibration
+static TSC_AT_FIRST_TICK: AtomicU64 = AtomicU64::new(0);
+/// Time stamp counter increments per second, 0 until calibrated
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// Programs PIT channel 0 to fire IRQ 0 at `TIMER_HZ`.
+///
+/// Must be called before interrupts are enabled, otherwise the first ticks
+/// arrive at the BIOS default rate of ~18.2 Hz.
+pub fn init() {
+    let divisor = (PIT_FREQUENCY / TIMER_HZ) as u16;
+    let mut command: Port<u8> = Port::new(PIT_COMMAND);
+    let mut channel_0: Port<u8> = Port::new(PIT_CHANNEL_0);
+
+    interrupts::without_interrupts(|| unsafe {
+        // channel 0, access mode lobyte/hibyte, mode 3 (square wave), binary
+        command.write(0b0011_0110);
+        channel_0.write((divisor & 0xff) as u8);
+        channel_0.write((divisor >> 8) as u8);
+    });
+}
+
+/// Called by the timer interrupt handler.
+///
+/// Must not block or allocate.
+pub(crate) fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    // the TSC is calibrated against the first second of ticks
+    if now == 1 {
+        TSC_AT_FIRST_TICK.store(tsc(), Ordering::Relaxed);
+    } else if now == 1 + u64::from(TIMER_HZ) {
+        let start = TSC_AT_FIRST_TICK.load(Ordering::Relaxed);
+        TSC_FREQUENCY.store(tsc() - start, Ordering::Relaxed);
+    }
+    task::timer::on_tick(now);
+}
+
+/// Monotonic tick count since boot, incremented `TIMER_HZ` times per second
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Converts a tick count into a `Duration`
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_millis(ticks * 1000 / u64::from(TIMER_HZ))
+}
+
+/// Converts a `Duration` into a tick count, rounding up so that waiting for
+/// the result never returns early. Saturates, since user programs pass
+/// arbitrary durations.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let millis = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+    millis.saturating_mul(u64::from(TIMER_HZ)).div_ceil(1000)
+}
+
+/// Time elapsed since the timer was started
+pub fn uptime() -> Duration {
+    ticks_to_duration(ticks())
+}
+
+/// Reads the time stamp counter, for measuring short intervals in cycles
+pub fn tsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Converts TSC cycles into a `Duration`, `None` during the first second after
+/// `init`, while the TSC is calibrated
+pub fn cycles_to_duration(cycles: u64) -> Option<Duration> {
+    let frequency = TSC_FREQUENCY.load(Ordering::Relaxed);
+    if frequency == 0 {
+        return None;
+    }
+    let nanos = u128::from(cycles) * 1_000_000_000 / u128::from(frequency);
+    Some(Duration::from_nanos(nanos as u64))
+}
+
+/// Converts a `Duration` into TSC cycles, `None` while the TSC is calibrated
+pub fn duration_to_cycles(duration: Duration) -> Option<u64> {
+    let frequency = TSC_FREQUENCY.load(Ordering::Relaxed);
+    if frequency == 0 {
+        return None;
+    }
+    Some((duration.as_nanos() * u128::from(frequency) / 1_000_000_000) as u64)
+}
+
+#[test_case]
+fn test_duration_to_ticks_saturates() {
+    assert_eq!(duration_to_ticks(Duration::from_millis(10)), 1);
+    assert_eq!(
+        duration_to_ticks(Duration::from_millis(u64::MAX)),
+        u64::MAX / 1000 + 1
+    );
+    assert_eq!(duration_to_ticks(Duration::MAX), u64::MAX / 1000 + 1);
+}
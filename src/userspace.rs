@@ -0,0 +1,234 @@
+pub mod loader;
+
+use crate::{
+    cpu, gdt, hlt_loop,
+    memory::{self, tlb},
+    percpu,
+    process::{self, ExitStatus, Pid},
+    serial_println, syscall,
+};
+use core::{
+    arch::naked_asm,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use x86_64::{
+    registers::{control::Cr3, model_specific::KernelGsBase, rflags::RFlags},
+    structures::paging::PhysFrame,
+    VirtAddr,
+};
+
+/// `run_user` result of a program stopped by a fault, the low byte holds the
+/// exception vector. Sign-extended `i32` exit codes never have these bits.
+const FAULTED: i64 = 1 << 32;
+
+percpu! {
+    /// Stack interrupts and syscalls from ring 3 start on
+    static KERNEL_STACK: AtomicU64 = AtomicU64::new(0);
+}
+
+/// Ring transition state of a thread, swapped by the scheduler on every
+/// context switch so each thread can run its own user program.
+#[derive(Debug, Clone, Copy)]
+pub struct UserState {
+    return_stack: u64,
+    kernel_stack: u64,
+    level_4_frame: PhysFrame,
+    process: Option<Pid>,
+    /// GS base of the user program, in KERNEL_GS_BASE while the kernel runs
+    user_gs_base: VirtAddr,
+}
+
+impl UserState {
+    /// State of a thread that never ran user code
+    pub(crate) fn kernel() -> UserState {
+        UserState {
+            return_stack: 0,
+            kernel_stack: 0,
+            level_4_frame: memory::with_kernel_memory(|memory| memory.kernel_level_4_frame),
+            process: None,
+            user_gs_base: VirtAddr::zero(),
+        }
+    }
+}
+
+/// Enables `syscall`.
+pub fn init() {
+    syscall::init();
+}
+
+/// Sets the kernel stack for interrupts (TSS RSP0) and syscalls from ring 3.
+pub fn set_kernel_stack(stack_end: VirtAddr) {
+    KERNEL_STACK
+        .get()
+        .store(stack_end.as_u64(), Ordering::Relaxed);
+    gdt::set_kernel_stack(stack_end);
+    syscall::set_kernel_stack(stack_end);
+}
+
+/// State of the running thread, to be restored with `restore_state`.
+pub(crate) fn save_state() -> UserState {
+    UserState {
+        return_stack: cpu::area().return_stack.load(Ordering::Relaxed),
+        kernel_stack: KERNEL_STACK.get().load(Ordering::Relaxed),
+        level_4_frame: Cr3::read().0,
+        process: process::current(),
+        user_gs_base: KernelGsBase::read(),
+    }
+}
+
+/// # Safety
+/// Loads the ring transition state and address space of the thread being switched to.
+///
+/// Unsafe because it must only be called with interrupts disabled, right before
+/// switching to the thread `state` was saved from.
+pub(crate) unsafe fn restore_state(state: &UserState) {
+    cpu::area()
+        .return_stack
+        .store(state.return_stack, Ordering::Relaxed);
+    if state.kernel_stack != 0 {
+        set_kernel_stack(VirtAddr::new(state.kernel_stack));
+    }
+    if Cr3::read().0 != state.level_4_frame {
+        tlb::switch_address_space(state.level_4_frame);
+    }
+    process::set_current(state.process);
+    KernelGsBase::write(state.user_gs_base);
+}
+
+/// # Safety
+/// Drops to ring 3 and runs the code at `entry` with `user_stack` as stack
+/// pointer, until it calls `exit` or faults.
+///
+/// Interrupts and syscalls from ring 3 run on the caller's stack, right below
+/// the context saved by `run_user`, which sets the kernel stack accordingly.
+///
+/// Unsafe because the caller must guarantee that `entry` and `user_stack` point
+/// into memory mapped `USER_ACCESSIBLE` in the active page table, and that
+/// `init` was called. Only one user program can run per thread.
+pub unsafe fn run(entry: VirtAddr, user_stack: VirtAddr) -> ExitStatus {
+    // a new program starts without the GS base of the previous one
+    KernelGsBase::write(VirtAddr::zero());
+    let selectors = gdt::selectors();
+    let user_code = u64::from(selectors.user_code_selector.0);
+    let user_data = u64::from(selectors.user_data_selector.0);
+    // user code always runs with interrupts enabled
+    let rflags = RFlags::INTERRUPT_FLAG.bits();
+
+    let result = run_user(
+        entry.as_u64(),
+        user_stack.as_u64(),
+        user_code,
+        user_data,
+        rflags,
+    );
+    if result >> 32 == FAULTED >> 32 {
+        ExitStatus::Faulted(result as u8)
+    } else {
+        ExitStatus::Exited(result as i32)
+    }
+}
+
+/// Ends the running user program, `run` returns `exit_code`.
+///
+/// Called by the `exit` syscall on the syscall kernel stack, which is abandoned.
+pub(crate) fn exit(exit_code: i32) -> ! {
+    if cpu::area().return_stack.load(Ordering::Relaxed) == 0 {
+        serial_println!("exit({}) without a running user program", exit_code);
+        hlt_loop();
+    }
+    unsafe { return_from_user(i64::from(exit_code)) }
+}
+
+/// Ends the running user program after it raised the exception `vector`, `run`
+/// returns `ExitStatus::Faulted`.
+///
+/// Called by exception handlers for faults in ring 3. Their stack is abandoned
+/// like the syscall stack in `exit`.
+pub(crate) fn fault(vector: u8) -> ! {
+    if cpu::area().return_stack.load(Ordering::Relaxed) == 0 {
+        panic!("fault in ring 3 without a running user program");
+    }
+    unsafe { return_from_user(FAULTED | i64::from(vector)) }
+}
+
+/// Called by `run_user` with the stack pointer after saving the kernel context
+extern "C" fn set_kernel_stack_below(stack_pointer: u64) {
+    set_kernel_stack(VirtAddr::new(stack_pointer));
+}
+
+/// Saves the kernel context on the current stack, makes the stack below it the
+/// kernel stack and `iretq`s to ring 3.
+///
+/// Arguments in rdi, rsi, rdx, rcx, r8 as per the C calling convention.
+#[unsafe(naked)]
+unsafe extern "C" fn run_user(
+    entry: u64,
+    user_stack: u64,
+    code_selector: u64,
+    data_selector: u64,
+    rflags: u64,
+) -> i64 {
+    naked_asm!(
+        // callee-saved registers and flags, restored by `return_from_user`
+        "pushfq",
+        "push rbx",
+        "push rbp",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov gs:[{return_stack}], rsp",
+        // 16 byte aligned after the 7 pushes, so usable as kernel stack as is.
+        // The arguments are saved around the call, 6 slots keep the alignment.
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push rcx",
+        "push r8",
+        "sub rsp, 8",
+        "mov rdi, gs:[{return_stack}]",
+        "call {set_kernel_stack}",
+        "add rsp, 8",
+        "pop r8",
+        "pop rcx",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
+        "mov ds, cx",
+        "mov es, cx",
+        // interrupt frame popped by iretq: rip, cs, rflags, rsp, ss
+        "push rcx",
+        "push rsi",
+        "push r8",
+        "push rdx",
+        "push rdi",
+        // no interrupt may see the user's GS base before iretq enables them again
+        "cli",
+        "swapgs",
+        "iretq",
+        return_stack = const cpu::RETURN_STACK_OFFSET,
+        set_kernel_stack = sym set_kernel_stack_below,
+    );
+}
+
+/// Switches back to the stack saved by `run_user` and returns from it.
+#[unsafe(naked)]
+unsafe extern "C" fn return_from_user(exit_code: i64) -> ! {
+    naked_asm!(
+        "mov rsp, gs:[{return_stack}]",
+        "mov qword ptr gs:[{return_stack}], 0",
+        "xor eax, eax",
+        "mov ds, ax",
+        "mov es, ax",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbp",
+        "pop rbx",
+        "popfq",
+        "mov rax, rdi",
+        "ret",
+        return_stack = const cpu::RETURN_STACK_OFFSET,
+    );
+}
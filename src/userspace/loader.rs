@@ -0,0 +1,231 @@
+use crate::{
+    abi,
+    elf::{ElfError, ElfFile, ProgramHeader, PF_W, PF_X, PT_LOAD},
+    memory::{self, address_space::AddressSpace, user, USER_SPACE_END},
+    process::ExitStatus,
+};
+use alloc::vec::Vec;
+use x86_64::{
+    structures::paging::{mapper::TranslateResult, Mapper, Page, PageTableFlags, Translate},
+    VirtAddr,
+};
+
+// INITIAL USER STACK:
+//
+// Laid out like the System V ABI expects it, so `_start` finds its arguments
+// at the stack pointer:
+//
+// USER_SPACE_END
+// ├── argv and envp strings, NUL-terminated
+// ├── padding to 16 bytes
+// ├── auxv: (key, value) pairs ending with AT_NULL
+// ├── envp: pointers to the strings, ending with 0
+// ├── argv: pointers to the strings, ending with 0
+// └── argc                                          <- rsp, 16 byte aligned
+
+/// Size of the user stack
+const USER_STACK_PAGES: u64 = 16;
+/// Lowest address of the user stack
+const USER_STACK_START: u64 = USER_SPACE_END - USER_STACK_PAGES * abi::PAGE_SIZE;
+/// Upper bound for the argv/envp/auxv area, the rest is left to the program
+const MAX_ARGUMENTS_SIZE: u64 = 4 * abi::PAGE_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    Elf(ElfError),
+    /// A segment or the entry point lies outside of user space or overlaps the stack
+    BadAddress,
+    /// argv and envp don't fit the space reserved for them on the stack
+    ArgumentsTooLarge,
+    OutOfMemory,
+}
+
+impl From<ElfError> for LoadError {
+    fn from(err: ElfError) -> Self {
+        LoadError::Elf(err)
+    }
+}
+
+/// A program loaded into its own address space, ready to run
+pub struct Program {
+    pub address_space: AddressSpace,
+    pub entry: VirtAddr,
+    /// Initial stack pointer, pointing at argc
+    pub stack_pointer: VirtAddr,
+}
+
+/// Page flags for a segment with ELF permission `flags`
+fn segment_flags(flags: u32) -> PageTableFlags {
+    let mut page_flags = PageTableFlags::empty();
+    if flags & PF_W != 0 {
+        page_flags |= PageTableFlags::WRITABLE;
+    }
+    if flags & PF_X == 0 {
+        page_flags |= PageTableFlags::NO_EXECUTE;
+    }
+    page_flags
+}
+
+/// Maps a PT_LOAD segment zeroed and copies its file data.
+///
+/// Segments that share a page get the union of their permissions on it.
+fn load_segment(
+    address_space: &mut AddressSpace,
+    elf: &ElfFile,
+    header: &ProgramHeader,
+) -> Result<(), LoadError> {
+    if header.mem_size == 0 {
+        return Ok(());
+    }
+    if !user::is_user_range(header.vaddr, header.mem_size)
+        || header.vaddr + header.mem_size > USER_STACK_START
+    {
+        return Err(LoadError::BadAddress);
+    }
+
+    let flags = segment_flags(header.flags);
+    let mut mapper = address_space.mapper();
+    memory::with_kernel_memory(|memory| -> Result<(), LoadError> {
+        for page in user::page_range(header.vaddr, header.mem_size) {
+            match mapper.translate(page.start_address()) {
+                TranslateResult::Mapped {
+                    flags: existing, ..
+                } => {
+                    let mut merged = existing | flags;
+                    if !existing.contains(PageTableFlags::NO_EXECUTE)
+                        || !flags.contains(PageTableFlags::NO_EXECUTE)
+                    {
+                        merged.remove(PageTableFlags::NO_EXECUTE);
+                    }
+                    unsafe {
+                        mapper
+                            .update_flags(page, merged)
+                            .map_err(|_| LoadError::BadAddress)?
+                            .flush();
+                    }
+                }
+                _ => user::map_zeroed(
+                    &mut mapper,
+                    &mut memory.frame_allocator,
+                    Page::range_inclusive(page, page),
+                    flags,
+                )
+                .map_err(|_| LoadError::OutOfMemory)?,
+            }
+        }
+        Ok(())
+    })?;
+
+    if !address_space.write(VirtAddr::new(header.vaddr), elf.segment_data(header)) {
+        return Err(LoadError::BadAddress);
+    }
+    Ok(())
+}
+
+/// Maps the user stack and writes argc, argv, envp and auxv to its top.
+///
+/// Returns the initial stack pointer.
+fn setup_stack(
+    address_space: &mut AddressSpace,
+    argv: &[&str],
+    envp: &[&str],
+    auxv: &[(u64, u64)],
+) -> Result<VirtAddr, LoadError> {
+    let strings_size: u64 = argv
+        .iter()
+        .chain(envp)
+        .map(|string| string.len() as u64 + 1)
+        .sum();
+    let words = 1 + (argv.len() + 1) + (envp.len() + 1) + 2 * (auxv.len() + 1);
+    let strings_start = USER_SPACE_END - strings_size;
+    let stack_pointer = (strings_start - words as u64 * 8) & !0xf;
+    if USER_SPACE_END - stack_pointer > MAX_ARGUMENTS_SIZE {
+        return Err(LoadError::ArgumentsTooLarge);
+    }
+
+    let mut mapper = address_space.mapper();
+    memory::with_kernel_memory(|memory| {
+        user::map_zeroed(
+            &mut mapper,
+            &mut memory.frame_allocator,
+            user::page_range(USER_STACK_START, USER_STACK_PAGES * abi::PAGE_SIZE),
+            PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        )
+    })
+    .map_err(|_| LoadError::OutOfMemory)?;
+
+    // built in a kernel buffer and copied in one go
+    let mut contents = Vec::with_capacity((USER_SPACE_END - stack_pointer) as usize);
+    let mut string_addr = strings_start;
+    let mut push = |value: u64| contents.extend_from_slice(&value.to_le_bytes());
+    push(argv.len() as u64);
+    for list in [argv, envp] {
+        for string in list {
+            push(string_addr);
+            string_addr += string.len() as u64 + 1;
+        }
+        push(0);
+    }
+    for &(key, value) in auxv.iter().chain(&[(abi::AT_NULL, 0)]) {
+        push(key);
+        push(value);
+    }
+    contents.resize((strings_start - stack_pointer) as usize, 0);
+    for string in argv.iter().chain(envp) {
+        contents.extend_from_slice(string.as_bytes());
+        contents.push(0);
+    }
+
+    if !address_space.write(VirtAddr::new(stack_pointer), &contents) {
+        return Err(LoadError::OutOfMemory);
+    }
+    Ok(VirtAddr::new(stack_pointer))
+}
+
+/// Loads the ELF executable `image` into a fresh address space and prepares
+/// its stack with `argv` and `envp`.
+pub fn load(image: &[u8], argv: &[&str], envp: &[&str]) -> Result<Program, LoadError> {
+    let elf = ElfFile::parse(image)?;
+    if !user::is_user_range(elf.entry(), 1) {
+        return Err(LoadError::BadAddress);
+    }
+
+    let mut address_space = AddressSpace::new().ok_or(LoadError::OutOfMemory)?;
+    for header in elf
+        .program_headers()
+        .filter(|header| header.kind == PT_LOAD)
+    {
+        load_segment(&mut address_space, &elf, &header)?;
+    }
+
+    let mut auxv = Vec::new();
+    if let Some(phdr) = elf.program_header_address() {
+        auxv.push((abi::AT_PHDR, phdr));
+    }
+    auxv.push((abi::AT_PHENT, u64::from(elf.program_header_size())));
+    auxv.push((abi::AT_PHNUM, u64::from(elf.program_header_count())));
+    auxv.push((abi::AT_PAGESZ, abi::PAGE_SIZE));
+    auxv.push((abi::AT_ENTRY, elf.entry()));
+    let stack_pointer = setup_stack(&mut address_space, argv, envp, &auxv)?;
+
+    Ok(Program {
+        address_space,
+        entry: VirtAddr::new(elf.entry()),
+        stack_pointer,
+    })
+}
+
+/// Loads and runs the ELF executable `image` until it exits or faults, then
+/// frees its memory and returns how it ended.
+///
+/// Requires `userspace::init`. Must not be called while another program runs.
+pub fn exec(image: &[u8], argv: &[&str], envp: &[&str]) -> Result<ExitStatus, LoadError> {
+    let program = load(image, argv, envp)?;
+    let status = unsafe {
+        program.address_space.activate();
+        let status = super::run(program.entry, program.stack_pointer);
+        memory::activate_kernel_address_space();
+        status
+    };
+    Ok(status)
+}
@@ -1,7 +1,7 @@
 use core::fmt::{self, Write};
 
+use crate::sync::IrqSpinLock;
 use lazy_static::lazy_static;
-use spin::Mutex;
 use volatile::Volatile;
 use x86_64::instructions::interrupts;
 
@@ -127,7 +127,7 @@ impl Writer {
 }
 
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
+    pub static ref WRITER: IrqSpinLock<Writer> = IrqSpinLock::new(Writer {
         column_position: 0,
         color_code: ColorCode::new(Color::Yellow, Color::Black),
         buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
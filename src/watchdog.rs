@@ -0,0 +1,159 @@
+use crate::{backtrace, cpu, percpu, serial, time};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+use x86_64::{structures::idt::InterruptStackFrame, PrivilegeLevel};
+
+// WATCHDOG:
+//
+// The executor feeds the watchdog every time it gets back to its main loop, which
+// happens at least once per timer tick even when idle (the timer interrupt wakes it
+// from `hlt`). If a task's `poll` never returns, the feed counter stops moving and
+// the timer interrupt reports the stuck task.
+//
+// Every CPU's executor feeds its own counter. The timer interrupt checks the CPU
+// it interrupted, which is only the BSP as APs get no timer interrupts.
+//
+// A hang with interrupts disabled never reaches the timer check, for that case an
+// NMI (e.g. `nmi` in the QEMU monitor) dumps the same report.
+
+/// Marker for "no task is being polled"
+const NO_TASK: u64 = u64::MAX;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Ticks without progress before the watchdog fires, set by `enable`
+static TIMEOUT_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Frames of the last report, the interrupted `rip` first, 0 past the end
+static LAST_REPORT: [AtomicU64; REPORT_FRAMES] = [const { AtomicU64::new(0) }; REPORT_FRAMES];
+/// Number of reports written
+static REPORTS: AtomicU64 = AtomicU64::new(0);
+const REPORT_FRAMES: usize = backtrace::MAX_FRAMES + 1;
+
+percpu! {
+    /// Incremented by `feed`
+    static FEEDS: AtomicU64 = AtomicU64::new(0);
+    /// Value of `FEEDS` the timer saw last, and the tick it changed at
+    static LAST_FEEDS: AtomicU64 = AtomicU64::new(0);
+    static LAST_PROGRESS_TICK: AtomicU64 = AtomicU64::new(0);
+    /// Set once a stall was reported, so it is reported once instead of every tick
+    static REPORTED: AtomicBool = AtomicBool::new(false);
+    /// Id of the task currently being polled by the executor
+    static CURRENT_TASK: AtomicU64 = AtomicU64::new(NO_TASK);
+}
+
+/// Starts reporting when the executor makes no progress for `timeout`.
+pub fn enable(timeout: Duration) {
+    TIMEOUT_TICKS.store(time::duration_to_ticks(timeout), Ordering::Relaxed);
+    for (_, tick) in LAST_PROGRESS_TICK.iter() {
+        tick.store(time::ticks(), Ordering::Relaxed);
+    }
+    for (_, reported) in REPORTED.iter() {
+        reported.store(false, Ordering::Relaxed);
+    }
+    ENABLED.store(true, Ordering::Release);
+}
+
+pub fn disable() {
+    ENABLED.store(false, Ordering::Release);
+}
+
+/// Signals that the executor of the calling CPU made progress.
+pub fn feed() {
+    FEEDS.get().fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records the task the executor of the calling CPU is about to poll, `None` once the poll returned.
+pub fn set_current_task(task_id: Option<u64>) {
+    CURRENT_TASK
+        .get()
+        .store(task_id.unwrap_or(NO_TASK), Ordering::Relaxed);
+}
+
+/// Called by the timer interrupt handler with the interrupted frame pointer.
+///
+/// Must not block or allocate.
+pub(crate) fn check(stack_frame: &InterruptStackFrame, rbp: u64) {
+    if !ENABLED.load(Ordering::Acquire) {
+        return;
+    }
+
+    let now = time::ticks();
+    let feeds = FEEDS.get().load(Ordering::Relaxed);
+    if LAST_FEEDS.get().swap(feeds, Ordering::Relaxed) != feeds {
+        LAST_PROGRESS_TICK.get().store(now, Ordering::Relaxed);
+        REPORTED.get().store(false, Ordering::Relaxed);
+        return;
+    }
+
+    let stalled = now - LAST_PROGRESS_TICK.get().load(Ordering::Relaxed);
+    if stalled >= TIMEOUT_TICKS.load(Ordering::Relaxed)
+        && !REPORTED.get().swap(true, Ordering::Relaxed)
+    {
+        report("WATCHDOG: executor made no progress", stack_frame, rbp);
+    }
+}
+
+/// Called by the NMI handler, dumps the state of the interrupted code.
+pub(crate) fn handle_nmi(stack_frame: &InterruptStackFrame, rbp: u64) {
+    report("NMI received", stack_frame, rbp);
+}
+
+/// Number of reports written so far
+pub fn reports() -> u64 {
+    REPORTS.load(Ordering::Acquire)
+}
+
+/// Backtrace of the last report: the interrupted `rip`, then the return
+/// addresses of its callers, 0 past the last frame
+pub fn last_report() -> [u64; REPORT_FRAMES] {
+    core::array::from_fn(|depth| LAST_REPORT[depth].load(Ordering::Relaxed))
+}
+
+/// Writes the stuck task, the interrupted register state and a backtrace of the
+/// interrupted code, whose frame pointer is `rbp`, to serial.
+///
+/// Bypasses the serial lock, since the interrupted code may hold it.
+fn report(reason: &str, stack_frame: &InterruptStackFrame, rbp: u64) {
+    let mut port = unsafe { serial::emergency_port() };
+    let _ = writeln!(port, "\n{} (cpu {})", reason, cpu::current_id());
+    if ENABLED.load(Ordering::Relaxed) {
+        let stalled = time::ticks() - LAST_PROGRESS_TICK.get().load(Ordering::Relaxed);
+        let _ = writeln!(
+            port,
+            "last progress {:?} ago",
+            time::ticks_to_duration(stalled)
+        );
+    }
+    match CURRENT_TASK.get().load(Ordering::Relaxed) {
+        NO_TASK => {
+            let _ = writeln!(port, "no task was being polled");
+        }
+        task_id => {
+            let _ = writeln!(port, "stuck in poll of task {}", task_id);
+        }
+    }
+    let _ = writeln!(port, "{:#?}", stack_frame);
+
+    let rip = stack_frame.instruction_pointer.as_u64();
+    // user stacks aren't walked, their frames may be anywhere or unmapped
+    let rbp = if stack_frame.code_segment.rpl() == PrivilegeLevel::Ring0 {
+        rbp
+    } else {
+        0
+    };
+    backtrace::print_from(&mut port, rip, rbp);
+
+    LAST_REPORT[0].store(rip, Ordering::Relaxed);
+    let mut depth = 1;
+    backtrace::walk_from(rbp, |return_address| {
+        LAST_REPORT[depth].store(return_address, Ordering::Relaxed);
+        depth += 1;
+    });
+    for frame in &LAST_REPORT[depth..] {
+        frame.store(0, Ordering::Relaxed);
+    }
+    REPORTS.fetch_add(1, Ordering::Release);
+}
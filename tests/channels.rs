@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(atlas::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{rc::Rc, vec::Vec};
+use core::{cell::RefCell, panic::PanicInfo};
+
+use atlas::task::{
+    channel::{broadcast, mpsc, oneshot, watch},
+    executor,
+};
+use bootloader::{entry_point, BootInfo};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    atlas::test_init(boot_info);
+
+    test_main();
+    atlas::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    atlas::test_panic_handler(info)
+}
+
+#[test_case]
+/// validate that a full mpsc channel makes senders wait and keeps the order
+fn mpsc_back_pressure() {
+    let (sender, mut receiver) = mpsc::channel(2);
+    assert_eq!(sender.try_send(0), Ok(()));
+    assert_eq!(sender.try_send(1), Ok(()));
+    assert_eq!(sender.try_send(2), Err(mpsc::TrySendError::Full(2)));
+    assert_eq!(receiver.try_recv(), Ok(0));
+    drop(receiver);
+    assert_eq!(sender.try_send(3), Err(mpsc::TrySendError::Closed(3)));
+
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let log = received.clone();
+    atlas::run_tasks(async move {
+        let (sender, mut receiver) = mpsc::channel(2);
+        executor::spawn(async move {
+            for value in 0..5 {
+                sender.send(value).await.unwrap();
+            }
+        })
+        .detach();
+        while let Some(value) = receiver.recv().await {
+            log.borrow_mut().push(value);
+        }
+    });
+    assert_eq!(*received.borrow(), [0, 1, 2, 3, 4]);
+}
+
+#[test_case]
+/// validate that a oneshot delivers its value or reports a dropped sender
+fn oneshot_send_and_drop() {
+    atlas::run_tasks(async {
+        let (sender, receiver) = oneshot::channel();
+        executor::spawn(async move { sender.send(7).unwrap() }).detach();
+        assert_eq!(receiver.await, Ok(7));
+
+        let (sender, receiver) = oneshot::channel::<u32>();
+        drop(sender);
+        assert_eq!(receiver.await, Err(oneshot::RecvError));
+
+        let (sender, receiver) = oneshot::channel();
+        drop(receiver);
+        assert_eq!(sender.send(1), Err(1));
+    });
+}
+
+#[test_case]
+/// validate that broadcast receivers see every value or learn they lagged
+fn broadcast_lag() {
+    let (sender, mut fast) = broadcast::channel(2);
+    let mut slow = sender.subscribe();
+    for value in 0..3 {
+        assert_eq!(sender.send(value), Ok(2));
+        assert_eq!(fast.try_recv(), Ok(value));
+    }
+    assert_eq!(slow.try_recv(), Err(broadcast::TryRecvError::Lagged(1)));
+    assert_eq!(slow.try_recv(), Ok(1));
+    assert_eq!(slow.try_recv(), Ok(2));
+    assert_eq!(slow.try_recv(), Err(broadcast::TryRecvError::Empty));
+    drop(sender);
+    assert_eq!(fast.try_recv(), Err(broadcast::TryRecvError::Closed));
+}
+
+#[test_case]
+/// validate that watch receivers wake on changes and see the latest value
+fn watch_changes() {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let log = seen.clone();
+    atlas::run_tasks(async move {
+        let (sender, mut receiver) = watch::channel(0);
+        executor::spawn(async move {
+            while receiver.changed().await.is_ok() {
+                log.borrow_mut().push(*receiver.borrow());
+            }
+        })
+        .detach();
+        for value in 1..=3 {
+            sender.send(value);
+            // the receiver only runs after both sends and sees the latest
+            sender.send(value * 10);
+            executor::spawn(async {}).await.unwrap();
+        }
+    });
+    assert_eq!(*seen.borrow(), [10, 20, 30]);
+}
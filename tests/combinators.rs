@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(atlas::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::{future, panic::PanicInfo, time::Duration};
+
+use atlas::{
+    deferred,
+    task::{
+        channel::oneshot,
+        combinator::{join, join_all, select, select_all, Either},
+        executor::{self, Executor},
+        timer::{self, Elapsed},
+    },
+    time,
+};
+use bootloader::{entry_point, BootInfo};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    atlas::test_init(boot_info);
+    // timers fire through deferred work
+    deferred::init();
+
+    test_main();
+    atlas::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    atlas::test_panic_handler(info)
+}
+
+#[test_case]
+/// validate that join and join_all wait for every future and keep the order
+fn join_outputs() {
+    let outputs = Executor::new().block_on(async {
+        let (sender, receiver) = oneshot::channel();
+        executor::spawn(async move {
+            timer::sleep(Duration::from_millis(20)).await;
+            sender.send(2).unwrap();
+        })
+        .detach();
+        let (first, second) = join(async { 1 }, receiver).await;
+        let rest = join_all((3..6).map(|value| async move { value })).await;
+        (first, second.unwrap(), rest)
+    });
+    assert_eq!(outputs, (1, 2, Vec::from([3, 4, 5])));
+}
+
+#[test_case]
+/// validate that select completes with the first future and drops the other
+fn select_first() {
+    let (winner, index) = Executor::new().block_on(async {
+        let winner = select(timer::sleep(Duration::from_millis(500)), async { "ready" }).await;
+        let index = select_all([
+            timer::sleep(Duration::from_millis(500)),
+            timer::sleep(Duration::from_millis(10)),
+        ])
+        .await
+        .1;
+        (winner, index)
+    });
+    assert_eq!(winner, Either::Right("ready"));
+    assert_eq!(index, 1);
+    assert_eq!(timer::pending_timers(), 0);
+}
+
+#[test_case]
+/// validate that timeout fires after the duration and lets fast futures through
+fn timeout_elapses() {
+    let start = time::ticks();
+    let (slow, fast) = Executor::new().block_on(async {
+        let slow = timer::timeout(Duration::from_millis(50), future::pending::<()>()).await;
+        let fast = timer::timeout(Duration::from_millis(50), async { 7 }).await;
+        (slow, fast)
+    });
+    assert_eq!(slow, Err(Elapsed));
+    assert_eq!(fast, Ok(7));
+    assert!(time::ticks() - start >= time::duration_to_ticks(Duration::from_millis(50)));
+}
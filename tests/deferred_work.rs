@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(atlas::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use atlas::deferred::{Work, WorkQueue};
+use bootloader::{entry_point, BootInfo};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    atlas::test_init(boot_info);
+
+    test_main();
+    atlas::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    atlas::test_panic_handler(info)
+}
+
+static SUM: AtomicUsize = AtomicUsize::new(0);
+
+fn add_to_sum(value: usize) {
+    SUM.fetch_add(value, Ordering::Relaxed);
+}
+
+#[test_case]
+/// validate that queued work runs once, in order of scheduling
+fn run_scheduled_work() {
+    static QUEUE: WorkQueue = WorkQueue::new("run", 4);
+    QUEUE.init();
+    SUM.store(0, Ordering::Relaxed);
+
+    for value in 1..=3 {
+        assert!(QUEUE.schedule(Work::new(add_to_sum, value)).is_ok());
+    }
+    assert!(QUEUE.has_pending());
+    assert_eq!(QUEUE.run_pending(), 3);
+    assert_eq!(SUM.load(Ordering::Relaxed), 6);
+    assert!(!QUEUE.has_pending());
+    assert_eq!(QUEUE.run_pending(), 0);
+}
+
+#[test_case]
+/// validate that a full or uninitialized queue drops work and counts it
+fn overflow_accounting() {
+    static QUEUE: WorkQueue = WorkQueue::new("overflow", 2);
+    assert!(QUEUE.schedule(Work::new(add_to_sum, 1)).is_err());
+
+    QUEUE.init();
+    assert!(QUEUE.schedule(Work::new(add_to_sum, 1)).is_ok());
+    assert!(QUEUE.schedule(Work::new(add_to_sum, 1)).is_ok());
+    assert!(QUEUE.schedule(Work::new(add_to_sum, 1)).is_err());
+    QUEUE.run_pending();
+
+    let stats = QUEUE.stats();
+    assert_eq!(stats.scheduled, 2);
+    assert_eq!(stats.completed, 2);
+    assert_eq!(stats.overflows, 2);
+}
@@ -0,0 +1,324 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(atlas::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{rc::Rc, string::String, vec::Vec};
+use core::{
+    cell::RefCell,
+    future,
+    panic::PanicInfo,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Poll,
+    time::Duration,
+};
+
+use atlas::{
+    task::{
+        executor::{self, Executor},
+        local::{self, AccessError},
+        metrics, JoinError, LocalKey, Priority, Task,
+    },
+    time,
+};
+use bootloader::{entry_point, BootInfo};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    atlas::test_init(boot_info);
+
+    test_main();
+    atlas::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    atlas::test_panic_handler(info)
+}
+
+#[test_case]
+/// validate that a task can spawn subtasks through a `Spawner`
+fn spawn_from_task() {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    executor.spawn(Task::new(async move {
+        for _ in 0..3 {
+            spawner.spawn(async {
+                RUNS.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        RUNS.fetch_add(1, Ordering::Relaxed);
+    }));
+    executor.run_until_stalled();
+    assert_eq!(RUNS.load(Ordering::Relaxed), 4);
+}
+
+#[test_case]
+/// validate that the global `spawn` reaches the executor running on this CPU
+fn global_spawn() {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        executor::spawn(async {
+            executor::spawn(async {
+                RUNS.fetch_add(1, Ordering::Relaxed);
+            });
+            RUNS.fetch_add(1, Ordering::Relaxed);
+        });
+    }));
+    executor.run_until_stalled();
+    assert_eq!(RUNS.load(Ordering::Relaxed), 2);
+}
+
+#[test_case]
+/// validate that awaiting a `JoinHandle` yields the output of the task
+fn join_task_output() {
+    static RESULT: AtomicUsize = AtomicUsize::new(0);
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        let handle = executor::spawn(async { 6 * 7 });
+        RESULT.store(handle.await.unwrap(), Ordering::Relaxed);
+    }));
+    executor.run_until_stalled();
+    assert_eq!(RESULT.load(Ordering::Relaxed), 42);
+}
+
+#[test_case]
+/// validate that a task dropped with its executor reports cancellation
+fn join_cancelled_task() {
+    let mut executor = Executor::new();
+    let (task, handle) = Task::with_join_handle(future::pending::<usize>());
+    executor.spawn(task);
+    executor.run_until_stalled();
+    assert!(!handle.is_finished());
+    drop(executor);
+    assert!(handle.is_finished());
+
+    static CANCELLED: AtomicUsize = AtomicUsize::new(0);
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+        if handle.await == Err(JoinError::Cancelled) {
+            CANCELLED.fetch_add(1, Ordering::Relaxed);
+        }
+    }));
+    executor.run_until_stalled();
+    assert_eq!(CANCELLED.load(Ordering::Relaxed), 1);
+}
+
+#[test_case]
+/// validate that `JoinHandle::abort` drops the task and wakes its joiner
+fn abort_task() {
+    static CANCELLED: AtomicUsize = AtomicUsize::new(0);
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        let handle = executor::spawn(future::pending::<()>());
+        handle.abort();
+        if handle.await == Err(JoinError::Cancelled) {
+            CANCELLED.fetch_add(1, Ordering::Relaxed);
+        }
+    }));
+    executor.run_until_stalled();
+    assert_eq!(CANCELLED.load(Ordering::Relaxed), 1);
+    assert_eq!(executor.task_count(), 0);
+}
+
+#[test_case]
+/// validate that `Executor::cancel` drops the future and ignores later wakeups
+fn cancel_task() {
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    struct DropCounter;
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let mut executor = Executor::new();
+    let counter = DropCounter;
+    let (task, handle) = Task::with_join_handle(async move {
+        let _counter = counter;
+        future::pending::<()>().await
+    });
+    let id = task.id();
+    executor.spawn(task);
+    executor.run_until_stalled();
+    assert_eq!(DROPPED.load(Ordering::Relaxed), 0);
+
+    assert!(executor.cancel(id));
+    assert_eq!(DROPPED.load(Ordering::Relaxed), 1);
+    assert!(handle.is_finished());
+    assert!(!executor.cancel(id));
+    assert_eq!(executor.task_count(), 0);
+    // the stale abort wakeup is dropped by the executor
+    handle.abort();
+    executor.run_until_stalled();
+}
+
+#[test_case]
+/// validate that repeated wakeups poll a task once and that more ready tasks
+/// than the wake queue holds all run
+fn wake_burst() {
+    static POLLS: AtomicUsize = AtomicUsize::new(0);
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(future::poll_fn(|cx| {
+        if POLLS.fetch_add(1, Ordering::Relaxed) == 0 {
+            for _ in 0..1000 {
+                cx.waker().wake_by_ref();
+            }
+            return Poll::Pending;
+        }
+        Poll::Ready(())
+    })));
+    for _ in 0..500 {
+        executor.spawn(Task::new(async {
+            RUNS.fetch_add(1, Ordering::Relaxed);
+        }));
+    }
+    executor.run_until_stalled();
+    assert_eq!(POLLS.load(Ordering::Relaxed), 2);
+    assert_eq!(RUNS.load(Ordering::Relaxed), 500);
+    assert_eq!(executor.task_count(), 0);
+}
+
+#[test_case]
+/// validate that tasks are listed with their metrics while alive
+fn task_metrics() {
+    static POLLS: AtomicUsize = AtomicUsize::new(0);
+    let mut executor = Executor::new();
+    executor.spawn(
+        Task::new(future::poll_fn(|cx| {
+            if POLLS.fetch_add(1, Ordering::Relaxed) < 2 {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let info = metrics::list()
+                .into_iter()
+                .find(|info| info.name == "metrics test")
+                .expect("task not listed");
+            assert_eq!(info.polls, 2);
+            assert!(info.last_woken.is_some());
+            Poll::Ready(())
+        }))
+        .named("metrics test"),
+    );
+    executor.run_until_stalled();
+    assert_eq!(POLLS.load(Ordering::Relaxed), 3);
+    assert!(metrics::list()
+        .iter()
+        .all(|info| info.name != "metrics test"));
+}
+
+#[test_case]
+/// validate that the task table lists live tasks by name with their polls
+fn task_table() {
+    fn yield_then_pend(yields: usize) -> impl future::Future<Output = ()> {
+        let mut polls = 0;
+        future::poll_fn(move |cx| {
+            polls += 1;
+            if polls <= yields {
+                cx.waker().wake_by_ref();
+            }
+            Poll::Pending
+        })
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(yield_then_pend(1)).named("table test a"));
+    executor.spawn(Task::new(yield_then_pend(4)).named("table test b"));
+    executor.run_until_stalled();
+
+    let mut table = String::new();
+    metrics::write_to(&mut table).unwrap();
+    for (name, polls) in [("table test a", "2"), ("table test b", "5")] {
+        let row = table
+            .lines()
+            .find(|row| row.ends_with(name))
+            .expect("task not listed");
+        assert_eq!(row.split_whitespace().nth(2), Some(polls));
+    }
+    drop(executor);
+}
+
+#[test_case]
+/// validate that a poll longer than the budget is counted
+fn poll_budget() {
+    // the TSC is calibrated during the first second
+    while time::cycles_to_duration(1).is_none() {
+        x86_64::instructions::hlt();
+    }
+    metrics::set_poll_budget(Some(Duration::from_millis(1)));
+    let before = metrics::over_budget_polls();
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        let busy = time::duration_to_cycles(Duration::from_millis(5)).unwrap();
+        let start = time::tsc();
+        while time::tsc() - start < busy {
+            core::hint::spin_loop();
+        }
+    }));
+    executor.run_until_stalled();
+    metrics::set_poll_budget(None);
+    assert_eq!(metrics::over_budget_polls(), before + 1);
+}
+
+#[test_case]
+/// validate that ready tasks are polled in order of priority, then spawn order
+fn priority_order() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut executor = Executor::new();
+    for (name, priority) in [
+        ("low", Priority::Low),
+        ("normal", Priority::Normal),
+        ("high", Priority::High),
+        ("high 2", Priority::High),
+    ] {
+        let log = log.clone();
+        executor
+            .spawn(Task::new(async move { log.borrow_mut().push(name) }).with_priority(priority));
+    }
+    executor.run_until_stalled();
+    assert_eq!(*log.borrow(), ["high", "high 2", "normal", "low"]);
+}
+
+#[test_case]
+/// validate that task-local values are scoped to their future and that the
+/// current task is known while it is polled
+fn task_local_values() {
+    static REQUEST: LocalKey<usize> = LocalKey::new();
+    static SEEN: AtomicUsize = AtomicUsize::new(0);
+    assert_eq!(REQUEST.try_with(|_| ()), Err(AccessError));
+    assert!(local::current().is_none());
+
+    let mut executor = Executor::new();
+    executor.spawn(
+        Task::new(REQUEST.scope(1, async {
+            let yielded = REQUEST.scope(2, async {
+                // polled twice, so the value is set again on the second poll
+                let mut yielded = false;
+                future::poll_fn(|cx| {
+                    if yielded {
+                        return Poll::Ready(());
+                    }
+                    yielded = true;
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                })
+                .await;
+                REQUEST.get()
+            });
+            SEEN.fetch_add(yielded.await * 10 + REQUEST.get(), Ordering::Relaxed);
+            assert_eq!(local::current().map(|task| task.name), Some("local test"));
+        }))
+        .named("local test"),
+    );
+    executor.run_until_stalled();
+    assert_eq!(SEEN.load(Ordering::Relaxed), 21);
+    assert!(REQUEST.try_with(|_| ()).is_err());
+    assert!(local::current().is_none());
+}
@@ -0,0 +1,104 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::{arch::asm, panic::PanicInfo};
+
+use atlas::{
+    exit_qemu,
+    gdt::{self, IST_STACK_PAGES, PAGE_FAULT_IST_INDEX},
+    memory::{self, stack},
+    serial_print, serial_println, QemuExitCode,
+};
+use bootloader::{entry_point, BootInfo};
+use lazy_static::lazy_static;
+use x86_64::{
+    instructions::interrupts,
+    registers::control::Cr2,
+    structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+};
+
+entry_point!(main);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    atlas::test_panic_handler(info)
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+    volatile::Volatile::new(0).read(); // prevent tail recursion optimizations
+}
+
+extern "C" fn overflow() -> ! {
+    stack_overflow();
+    panic!("Execution continued after stack overflow");
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: PageFaultErrorCode,
+) {
+    let rsp: u64;
+    unsafe { asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack)) };
+    let end = gdt::ist_stack(PAGE_FAULT_IST_INDEX).as_u64();
+    let start = end - IST_STACK_PAGES * 4096;
+    assert!(
+        (start..end).contains(&rsp),
+        "page fault handler runs on {:#x}, not on its IST stack",
+        rsp
+    );
+    let accessed = Cr2::read().expect("non-canonical fault address");
+    assert!(stack::is_guard_page(accessed), "fault outside a guard page");
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    atlas::hlt_loop();
+}
+
+extern "x86-interrupt" fn test_double_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    panic!("stack overflow raised a double fault instead of a page fault");
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.page_fault
+                .set_handler_fn(test_page_fault_handler)
+                .set_stack_index(PAGE_FAULT_IST_INDEX);
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt
+    };
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("ist_stack_overflow::stack_overflow...\t");
+
+    let (mapper, frame_allocator) = atlas::test_init(boot_info);
+    memory::install(mapper, frame_allocator);
+    gdt::init_ist_stacks();
+
+    // the test IDT only handles the faults, no device interrupts
+    interrupts::disable();
+    TEST_IDT.load();
+
+    // the boot stack has no guard page, so overflow a stack that has one
+    let stack = stack::alloc_stack(4).expect("stack allocation failed");
+    unsafe {
+        asm!(
+            "mov rsp, {stack}",
+            "call {overflow}",
+            stack = in(reg) stack.end().as_u64(),
+            overflow = sym overflow,
+            options(noreturn),
+        );
+    }
+}
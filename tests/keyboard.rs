@@ -0,0 +1,64 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(atlas::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use atlas::{
+    interrupts::{stats, InterruptIndex},
+    task::keyboard::{self, ScancodeStream},
+};
+use bootloader::{entry_point, BootInfo};
+use x86_64::instructions::{hlt, port::Port};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    atlas::test_init(boot_info);
+    // sets up the scancode queue
+    let _scancodes = ScancodeStream::new();
+
+    test_main();
+    atlas::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    atlas::test_panic_handler(info)
+}
+
+/// Has the PS/2 controller raise a keyboard interrupt for `scancode`
+fn press(scancode: u8) {
+    const WRITE_OUTPUT_BUFFER: u8 = 0xd2;
+    const INPUT_FULL: u8 = 1 << 1;
+    let mut status: Port<u8> = Port::new(0x64);
+    let mut data: Port<u8> = Port::new(0x60);
+    let keyboard = InterruptIndex::Keyboard as u8;
+    let before = stats::count(keyboard);
+    unsafe {
+        while status.read() & INPUT_FULL != 0 {}
+        status.write(WRITE_OUTPUT_BUFFER);
+        while status.read() & INPUT_FULL != 0 {}
+        data.write(scancode);
+    }
+    while stats::count(keyboard) == before {
+        hlt();
+    }
+}
+
+#[test_case]
+/// validate that `read_bytes` hands out one character per byte of `buf`
+fn read_bytes_in_short_reads() {
+    // press and release 'a', then 'b'
+    for scancode in [0x1e, 0x9e, 0x30, 0xb0] {
+        press(scancode);
+    }
+    let mut buf = [0u8; 1];
+    assert_eq!(keyboard::read_bytes(&mut buf), 1);
+    assert_eq!(buf, *b"a");
+    assert_eq!(keyboard::read_bytes(&mut buf), 1);
+    assert_eq!(buf, *b"b");
+    assert_eq!(keyboard::read_bytes(&mut buf), 0);
+}
@@ -0,0 +1,173 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(atlas::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, task::Wake};
+use core::{
+    panic::PanicInfo,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use atlas::{
+    interrupts::{stats, InterruptIndex},
+    memory,
+    sync::{
+        lockdep::{self, Report},
+        IrqSpinLock, TicketLock,
+    },
+    task::keyboard::ScancodeStream,
+    thread,
+};
+use bootloader::{entry_point, BootInfo};
+use futures_util::Stream;
+use x86_64::instructions::{hlt, port::Port};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    let (mapper, frame_allocator) = atlas::test_init(boot_info);
+    memory::install(mapper, frame_allocator);
+    thread::init();
+    lockdep::enable();
+
+    test_main();
+    atlas::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    atlas::test_panic_handler(info)
+}
+
+static FIRST: TicketLock<u32> = TicketLock::new(0);
+static SECOND: IrqSpinLock<u32> = IrqSpinLock::new(0);
+static THIRD: TicketLock<u32> = TicketLock::new(0);
+
+#[test_case]
+/// validate that nesting locks in a consistent order is not reported
+fn consistent_order() {
+    let before = lockdep::report_count();
+    for _ in 0..2 {
+        let _first = FIRST.lock();
+        let _second = SECOND.lock();
+    }
+    assert_eq!(lockdep::report_count(), before);
+}
+
+#[test_case]
+/// validate that taking locks in the reverse order is reported, also through a third lock
+fn order_inversion() {
+    let before = lockdep::report_count();
+    {
+        let _second = SECOND.lock();
+        let _third = THIRD.lock();
+    }
+    assert_eq!(lockdep::report_count(), before);
+    {
+        // FIRST → SECOND → THIRD was seen, so THIRD → FIRST closes a cycle
+        let _third = THIRD.lock();
+        let _first = FIRST.lock();
+    }
+    assert_eq!(lockdep::report_count(), before + 1);
+    match lockdep::last_report() {
+        Some(Report::OrderInversion { held, acquired }) => {
+            assert_eq!(held.address, &THIRD as *const _ as usize);
+            assert_eq!(acquired.address, &FIRST as *const _ as usize);
+        }
+        report => panic!("unexpected report {:?}", report),
+    }
+
+    // reported only once
+    let _third = THIRD.lock();
+    let _first = FIRST.lock();
+    assert_eq!(lockdep::report_count(), before + 1);
+}
+
+#[test_case]
+/// validate that taking a lock the CPU already holds is reported
+fn recursive_locking() {
+    static LOCK: TicketLock<u32> = TicketLock::new(0);
+    static HELD: AtomicBool = AtomicBool::new(false);
+    static TAKING: AtomicBool = AtomicBool::new(false);
+    // the checker tracks CPUs, so a thread of the same CPU holding the lock
+    // counts, and it releases the lock instead of deadlocking
+    let holder = thread::spawn("holder", || {
+        let _lock = LOCK.lock();
+        HELD.store(true, Ordering::SeqCst);
+        while !TAKING.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+    });
+    while !HELD.load(Ordering::SeqCst) {
+        thread::yield_now();
+    }
+    let before = lockdep::report_count();
+    TAKING.store(true, Ordering::SeqCst);
+    // spins until the timer switches to the holder, which drops the lock
+    drop(LOCK.lock());
+    assert_eq!(lockdep::report_count(), before + 1);
+    match lockdep::last_report() {
+        Some(Report::Recursive { lock }) => {
+            assert_eq!(lock.address, &LOCK as *const _ as usize);
+        }
+        report => panic!("unexpected report {:?}", report),
+    }
+    thread::join(holder);
+}
+
+/// Takes `IRQ_LOCK` when woken, from the keyboard interrupt handler
+struct LockingWaker;
+
+static IRQ_LOCK: TicketLock<u32> = TicketLock::new(0);
+
+impl Wake for LockingWaker {
+    fn wake(self: Arc<Self>) {
+        drop(IRQ_LOCK.lock());
+    }
+}
+
+/// Has the PS/2 controller raise a keyboard interrupt for `scancode`
+fn press(scancode: u8) {
+    const WRITE_OUTPUT_BUFFER: u8 = 0xd2;
+    const INPUT_FULL: u8 = 1 << 1;
+    let mut status: Port<u8> = Port::new(0x64);
+    let mut data: Port<u8> = Port::new(0x60);
+    let keyboard = InterruptIndex::Keyboard as u8;
+    let before = stats::count(keyboard);
+    unsafe {
+        while status.read() & INPUT_FULL != 0 {}
+        status.write(WRITE_OUTPUT_BUFFER);
+        while status.read() & INPUT_FULL != 0 {}
+        data.write(scancode);
+    }
+    while stats::count(keyboard) == before {
+        hlt();
+    }
+}
+
+#[test_case]
+/// validate that a lock taken in an interrupt handler and with interrupts enabled is reported
+fn irq_unsafe_lock() {
+    let mut scancodes = ScancodeStream::new();
+    let waker = Waker::from(Arc::new(LockingWaker));
+    let mut cx = Context::from_waker(&waker);
+    assert_eq!(Pin::new(&mut scancodes).poll_next(&mut cx), Poll::Pending);
+    // the handler wakes the stream, taking the lock in interrupt context
+    press(0x1e);
+
+    let before = lockdep::report_count();
+    drop(IRQ_LOCK.lock());
+    assert_eq!(lockdep::report_count(), before + 1);
+    match lockdep::last_report() {
+        Some(Report::IrqUnsafe { lock }) => {
+            assert_eq!(lock.address, &IRQ_LOCK as *const _ as usize);
+        }
+        report => panic!("unexpected report {:?}", report),
+    }
+}
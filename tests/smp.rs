@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(atlas::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use atlas::{acpi, cpu, memory, smp};
+use bootloader::{entry_point, BootInfo};
+
+extern crate alloc;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    let (mapper, frame_allocator) = atlas::test_init(boot_info);
+    memory::install(mapper, frame_allocator);
+    smp::init();
+
+    test_main();
+    atlas::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    atlas::test_panic_handler(info)
+}
+
+#[test_case]
+/// validate that every enabled CPU of the MADT came online
+fn all_cpus_online() {
+    let madt = acpi::madt().expect("no MADT");
+    let enabled = madt
+        .cpus
+        .iter()
+        .filter(|cpu| cpu.enabled && usize::from(cpu.apic_id) < cpu::MAX_CPUS)
+        .count();
+    assert!(enabled > 1, "QEMU runs with a single CPU, check its -smp");
+    assert_eq!(smp::cpu_count(), enabled);
+    assert_eq!(smp::online_mask().count_ones() as usize, enabled);
+}
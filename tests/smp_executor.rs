@@ -0,0 +1,98 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(atlas::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use atlas::{
+    cpu, deferred, memory, smp,
+    task::{
+        combinator::join_all,
+        executor::{self, Executor},
+        timer,
+    },
+    time,
+};
+use bootloader::{entry_point, BootInfo};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    let (mapper, frame_allocator) = atlas::test_init(boot_info);
+    memory::install(mapper, frame_allocator);
+    // timers fire through deferred work
+    deferred::init();
+    smp::init();
+
+    test_main();
+    atlas::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    atlas::test_panic_handler(info)
+}
+
+/// CPUs that polled a task of `shared_tasks_are_stolen`
+static POLLED_ON: AtomicU64 = AtomicU64::new(0);
+
+/// Keeps the CPU busy until every online CPU polled a shared task, so that
+/// the other tasks can only run if the idle CPUs steal them
+fn wait_for_every_cpu() {
+    POLLED_ON.fetch_or(1 << cpu::current_id(), Ordering::AcqRel);
+    let deadline = time::ticks() + time::duration_to_ticks(Duration::from_secs(5));
+    while POLLED_ON.load(Ordering::Acquire) != smp::online_mask() && time::ticks() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+#[test_case]
+/// validate that idle APs steal shared tasks queued on the BSP (QEMU runs with -smp 4)
+fn shared_tasks_are_stolen() {
+    let handles: Vec<_> = (0..16)
+        .map(|_| {
+            executor::spawn_shared(async {
+                wait_for_every_cpu();
+                cpu::current_id()
+            })
+        })
+        .collect();
+    let cpus = Executor::new().block_on(join_all(handles));
+    let mut mask = 0u64;
+    for cpu in cpus {
+        mask |= 1 << cpu.expect("shared task failed");
+    }
+    assert_eq!(mask, smp::online_mask(), "not every CPU polled a task");
+}
+
+#[test_case]
+/// validate that pinned tasks run on their CPU, also after being woken elsewhere
+fn pinned_tasks_stay_on_their_cpu() {
+    let online = smp::online_mask();
+    let handles: Vec<_> = (0..cpu::MAX_CPUS)
+        .filter(|cpu| online & (1 << cpu) != 0)
+        .map(|cpu| {
+            executor::spawn_on(cpu, async move {
+                let first = cpu::current_id();
+                // the timer wakes the task from the BSP
+                timer::sleep(Duration::from_millis(20)).await;
+                (cpu, first, cpu::current_id())
+            })
+        })
+        .collect();
+    assert_eq!(handles.len(), smp::cpu_count());
+    for result in Executor::new().block_on(join_all(handles)) {
+        let (cpu, first, second) = result.expect("pinned task failed");
+        assert_eq!(first, cpu);
+        assert_eq!(second, cpu);
+    }
+}
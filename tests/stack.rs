@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(atlas::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use atlas::memory::{self, stack};
+use bootloader::{entry_point, BootInfo};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    let (mapper, frame_allocator) = atlas::test_init(boot_info);
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    atlas::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    atlas::test_panic_handler(info)
+}
+
+#[test_case]
+/// validate that only the page below a live stack is a guard page
+fn guard_page_of_live_stack() {
+    let bounds = stack::alloc_stack(2).expect("stack allocation failed");
+    assert!(stack::is_guard_page(bounds.start() - 1u64));
+    assert!(!stack::is_guard_page(bounds.start()));
+    assert!(!stack::is_guard_page(bounds.end() - 1u64));
+    stack::free_stack(bounds);
+}
+
+#[test_case]
+/// validate that the guard page of a freed stack no longer counts as one
+fn freed_stack_has_no_guard_page() {
+    let bounds = stack::alloc_stack(2).expect("stack allocation failed");
+    stack::free_stack(bounds);
+    assert!(!stack::is_guard_page(bounds.start() - 1u64));
+}
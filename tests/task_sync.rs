@@ -0,0 +1,176 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(atlas::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{rc::Rc, vec::Vec};
+use core::{
+    cell::RefCell,
+    future::Future,
+    panic::PanicInfo,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use atlas::task::{
+    executor,
+    sync::{Mutex, Notify, OnceCell, RwLock, Semaphore},
+};
+use bootloader::{entry_point, BootInfo};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    atlas::test_init(boot_info);
+
+    test_main();
+    atlas::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    atlas::test_panic_handler(info)
+}
+
+/// Lets the other ready tasks run once
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+fn yield_now() -> YieldNow {
+    YieldNow(false)
+}
+
+/// Runs the tasks created by `spawn_all` and returns the events they logged
+fn run_logged(spawn_all: impl FnOnce(Rc<RefCell<Vec<u32>>>) + 'static) -> Vec<u32> {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let spawn_log = log.clone();
+    atlas::run_tasks(async move { spawn_all(spawn_log) });
+    log.take()
+}
+
+#[test_case]
+/// validate that a mutex held across an await parks the other tasks in order
+fn mutex_across_await() {
+    static LOCK: Mutex<u32> = Mutex::new(0);
+    let log = run_logged(|log| {
+        for id in 0..3 {
+            let log = log.clone();
+            executor::spawn(async move {
+                let mut value = LOCK.lock().await;
+                log.borrow_mut().push(id);
+                yield_now().await;
+                *value += 1;
+            })
+            .detach();
+        }
+    });
+    assert_eq!(log, [0, 1, 2]);
+    assert_eq!(*LOCK.try_lock().unwrap(), 3);
+}
+
+#[test_case]
+/// validate that a waiting writer blocks readers arriving after it
+fn rwlock_writer_not_starved() {
+    static LOCK: RwLock<u32> = RwLock::new(0);
+    let log = run_logged(|log| {
+        for id in 0..3 {
+            let log = log.clone();
+            executor::spawn(async move {
+                if id == 1 {
+                    *LOCK.write().await += 1;
+                    log.borrow_mut().push(id);
+                } else {
+                    let value = LOCK.read().await;
+                    yield_now().await;
+                    log.borrow_mut().push(id * 10 + *value);
+                }
+            })
+            .detach();
+        }
+    });
+    assert_eq!(log, [0, 1, 21]);
+}
+
+#[test_case]
+/// validate that permits limit concurrency and are returned on drop
+fn semaphore_permits() {
+    static PERMITS: Semaphore = Semaphore::new(2);
+    let log = run_logged(|log| {
+        for id in 0..4 {
+            let log = log.clone();
+            executor::spawn(async move {
+                let _permit = PERMITS.acquire().await;
+                log.borrow_mut().push(PERMITS.available_permits() as u32);
+                yield_now().await;
+                log.borrow_mut().push(id);
+            })
+            .detach();
+        }
+    });
+    assert_eq!(log, [1, 0, 0, 1, 0, 0, 2, 3]);
+    assert_eq!(PERMITS.available_permits(), 2);
+}
+
+#[test_case]
+/// validate that a notification without waiter is kept for the next one
+fn notify_permit() {
+    static NOTIFY: Notify = Notify::new();
+    let log = run_logged(|log| {
+        NOTIFY.notify_one();
+        let waiter_log = log.clone();
+        executor::spawn(async move {
+            NOTIFY.notified().await;
+            waiter_log.borrow_mut().push(1);
+            NOTIFY.notified().await;
+            waiter_log.borrow_mut().push(3);
+        })
+        .detach();
+        executor::spawn(async move {
+            yield_now().await;
+            log.borrow_mut().push(2);
+            NOTIFY.notify_waiters();
+        })
+        .detach();
+    });
+    assert_eq!(log, [1, 2, 3]);
+}
+
+#[test_case]
+/// validate that concurrent `get_or_init` calls run one initializer
+fn once_cell_init_once() {
+    static CELL: OnceCell<u32> = OnceCell::new();
+    let log = run_logged(|log| {
+        for id in 0..3 {
+            let log = log.clone();
+            executor::spawn(async move {
+                let init_log = log.clone();
+                let value = CELL
+                    .get_or_init(|| async move {
+                        yield_now().await;
+                        init_log.borrow_mut().push(100 + id);
+                        id
+                    })
+                    .await;
+                log.borrow_mut().push(*value);
+            })
+            .detach();
+        }
+    });
+    assert_eq!(log, [100, 0, 0, 0]);
+    assert_eq!(CELL.set(5), Err(5));
+}
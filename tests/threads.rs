@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(atlas::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use atlas::{
+    memory,
+    thread::{self, policy::Policy, ThreadState},
+    time,
+};
+use bootloader::{entry_point, BootInfo};
+use x86_64::instructions::interrupts;
+
+extern crate alloc;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    let (mapper, frame_allocator) = atlas::test_init(boot_info);
+    memory::install(mapper, frame_allocator);
+    thread::init();
+
+    test_main();
+    atlas::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    atlas::test_panic_handler(info)
+}
+
+#[test_case]
+/// validate that join returns once the thread ran to completion
+fn spawn_and_join() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    let id = thread::spawn("worker", || DONE.store(true, Ordering::SeqCst));
+    thread::join(id);
+    assert!(DONE.load(Ordering::SeqCst));
+    assert!(thread::list().iter().all(|info| info.id != id));
+}
+
+#[test_case]
+/// validate that a thread spinning without yielding doesn't starve the others
+fn preemption() {
+    static STOP: AtomicBool = AtomicBool::new(false);
+    static SPINS: AtomicU64 = AtomicU64::new(0);
+    let spinner = thread::spawn("spinner", || {
+        while !STOP.load(Ordering::SeqCst) {
+            SPINS.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+    // never yields either, the spinner only runs if the timer preempts us
+    while SPINS.load(Ordering::SeqCst) == 0 {
+        core::hint::spin_loop();
+    }
+    STOP.store(true, Ordering::SeqCst);
+    thread::join(spinner);
+}
+
+#[test_case]
+/// validate that sleep blocks for at least the requested time
+fn sleep() {
+    let start = time::ticks();
+    thread::sleep(Duration::from_millis(50));
+    assert!(time::ticks() - start >= time::duration_to_ticks(Duration::from_millis(50)));
+}
+
+#[test_case]
+/// validate that a real-time thread runs before a fair one that became ready earlier
+fn fifo_runs_first() {
+    static ORDER: AtomicU64 = AtomicU64::new(0);
+    static FAIR_SLOT: AtomicU64 = AtomicU64::new(0);
+    static FIFO_SLOT: AtomicU64 = AtomicU64::new(0);
+    // no preemption before both are queued
+    let (fair, fifo) = interrupts::without_interrupts(|| {
+        let fair = thread::spawn("fair", || {
+            FAIR_SLOT.store(ORDER.fetch_add(1, Ordering::SeqCst), Ordering::SeqCst)
+        });
+        let fifo = thread::spawn_with_policy("fifo", Policy::Fifo { priority: 10 }, || {
+            FIFO_SLOT.store(ORDER.fetch_add(1, Ordering::SeqCst), Ordering::SeqCst)
+        });
+        (fair, fifo)
+    });
+    thread::join(fair);
+    thread::join(fifo);
+    assert!(FIFO_SLOT.load(Ordering::SeqCst) < FAIR_SLOT.load(Ordering::SeqCst));
+}
+
+#[test_case]
+/// validate that a parked thread stays blocked until it is unparked
+fn park_and_unpark() {
+    static WOKEN: AtomicBool = AtomicBool::new(false);
+    let main = thread::current();
+    let parked = thread::spawn("parked", move || {
+        thread::park();
+        WOKEN.store(true, Ordering::SeqCst);
+        thread::unpark(main);
+    });
+    thread::sleep(Duration::from_millis(20));
+    assert!(!WOKEN.load(Ordering::SeqCst));
+    assert!(thread::list()
+        .iter()
+        .any(|info| info.id == parked && info.state == ThreadState::Parked));
+    thread::unpark(parked);
+    // returns once the thread unparked us, or right away if it already did
+    thread::park();
+    thread::join(parked);
+    assert!(WOKEN.load(Ordering::SeqCst));
+}
@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(atlas::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use atlas::{
+    cpu,
+    memory::{self, stack, tlb, tlb::TlbBatch},
+    smp,
+};
+use bootloader::{entry_point, BootInfo};
+use x86_64::{registers::control::Cr3, structures::paging::Page, VirtAddr};
+
+extern crate alloc;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    let (mapper, frame_allocator) = atlas::test_init(boot_info);
+    memory::install(mapper, frame_allocator);
+    smp::init();
+
+    test_main();
+    atlas::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    atlas::test_panic_handler(info)
+}
+
+#[test_case]
+/// validate that unmapping kernel memory is acknowledged by every other online CPU
+fn kernel_unmap_reaches_all_cpus() {
+    assert!(smp::cpu_count() > 1, "test requires -smp");
+    let me = cpu::current_id();
+    let others =
+        || (0..cpu::MAX_CPUS).filter(|&cpu| cpu != me && smp::online_mask() & (1 << cpu) != 0);
+    let mut before = [0; cpu::MAX_CPUS];
+    for cpu in others() {
+        before[cpu] = tlb::received(cpu);
+    }
+
+    let bounds = stack::alloc_stack(4).expect("stack allocation failed");
+    stack::free_stack(bounds);
+
+    for cpu in others() {
+        assert_eq!(tlb::received(cpu), before[cpu] + 1);
+    }
+    assert_eq!(tlb::received(me), 0);
+}
+
+#[test_case]
+/// validate that a batch for an address space no other CPU has loaded sends no IPIs
+fn user_unmap_stays_local() {
+    let total = || (0..cpu::MAX_CPUS).map(tlb::received).sum::<u64>();
+    let before = total();
+    // some frame that is not the level 4 table of any CPU
+    let (kernel_frame, _) = Cr3::read();
+    let mut batch = TlbBatch::user(kernel_frame + 1024);
+    batch.add(Page::containing_address(VirtAddr::new(
+        memory::USER_SPACE_START,
+    )));
+    drop(batch);
+    assert_eq!(total(), before);
+}
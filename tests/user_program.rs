@@ -0,0 +1,289 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(atlas::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use alloc::vec::Vec;
+use atlas::{
+    cpu,
+    elf::{PF_R, PF_X, PT_LOAD},
+    gdt,
+    memory::{self, USER_SPACE_START},
+    process::{self, ExitStatus, ProcessError},
+    smp,
+    task::executor::{self, Executor},
+    userspace::{
+        self,
+        loader::{self, LoadError},
+    },
+};
+use bootloader::{entry_point, BootInfo};
+use x86_64::structures::idt::ExceptionVector;
+
+extern crate alloc;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    let (mapper, frame_allocator) = atlas::test_init(boot_info);
+    memory::install(mapper, frame_allocator);
+    userspace::init();
+    smp::init();
+
+    test_main();
+    atlas::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    atlas::test_panic_handler(info)
+}
+
+/// Offset of the code in images built by `executable`
+const CODE_OFFSET: u64 = 64 + 56;
+
+/// Builds a minimal ELF executable with a single read + execute segment
+/// containing the headers followed by `code`, loaded at `USER_SPACE_START`.
+fn executable(code: &[u8]) -> Vec<u8> {
+    let size = CODE_OFFSET + code.len() as u64;
+    let mut image = Vec::new();
+    // file header
+    image.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    image.extend_from_slice(&[0; 8]);
+    image.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+    image.extend_from_slice(&0x3eu16.to_le_bytes()); // x86_64
+    image.extend_from_slice(&1u32.to_le_bytes());
+    image.extend_from_slice(&(USER_SPACE_START + CODE_OFFSET).to_le_bytes());
+    image.extend_from_slice(&64u64.to_le_bytes()); // program headers
+    image.extend_from_slice(&0u64.to_le_bytes()); // section headers
+    image.extend_from_slice(&0u32.to_le_bytes());
+    for half in [64u16, 56, 1, 0, 0, 0] {
+        image.extend_from_slice(&half.to_le_bytes());
+    }
+    // program header
+    image.extend_from_slice(&PT_LOAD.to_le_bytes());
+    image.extend_from_slice(&(PF_R | PF_X).to_le_bytes());
+    for word in [0, USER_SPACE_START, USER_SPACE_START, size, size, 0x1000] {
+        image.extend_from_slice(&word.to_le_bytes());
+    }
+    image.extend_from_slice(code);
+    image
+}
+
+/// Program that exits with its argc
+const EXIT_WITH_ARGC: [u8; 11] = [
+    0x48, 0x8b, 0x3c, 0x24, // mov rdi, [rsp]
+    0xb8, 0x02, 0x00, 0x00, 0x00, // mov eax, 2 (exit)
+    0x0f, 0x05, // syscall
+];
+
+#[test_case]
+/// validate that a program can make syscalls and its exit code is returned
+fn write_and_exit() {
+    let code = [
+        0x48, 0x8d, 0x35, 0x1a, 0x00, 0x00, 0x00, // lea rsi, [rip + message]
+        0xbf, 0x01, 0x00, 0x00, 0x00, // mov edi, STDOUT
+        0xba, 0x03, 0x00, 0x00, 0x00, // mov edx, 3
+        0x31, 0xc0, // xor eax, eax (write)
+        0x0f, 0x05, // syscall
+        0xbf, 0x07, 0x00, 0x00, 0x00, // mov edi, 7
+        0xb8, 0x02, 0x00, 0x00, 0x00, // mov eax, 2 (exit)
+        0x0f, 0x05, // syscall
+        b'h', b'i', b'\n', // message
+    ];
+    let image = executable(&code);
+    assert_eq!(
+        loader::exec(&image, &["hi"], &[]),
+        Ok(ExitStatus::Exited(7))
+    );
+}
+
+#[test_case]
+/// validate that argc is passed at the initial stack pointer
+fn exit_with_argc() {
+    let image = executable(&EXIT_WITH_ARGC);
+    assert_eq!(
+        loader::exec(&image, &["prog", "a", "b"], &["HOME=/"]),
+        Ok(ExitStatus::Exited(3))
+    );
+}
+
+#[test_case]
+/// validate that every application processor runs programs and their syscalls
+fn syscalls_on_every_cpu() {
+    let image = executable(&EXIT_WITH_ARGC);
+    let bsp = cpu::current_id();
+    let aps = (0..cpu::MAX_CPUS).filter(|&cpu| cpu != bsp && smp::online_mask() & (1 << cpu) != 0);
+    for ap in aps {
+        let pid = process::spawn("argc", &image, &["argc", "x"], &[]).expect("spawn failed");
+        let handle = executor::spawn_on(ap, async move { (cpu::current_id(), process::wait(pid)) });
+        let (cpu, status) = Executor::new().block_on(handle).expect("task failed");
+        assert_eq!(cpu, ap);
+        assert_eq!(status, Ok(ExitStatus::Exited(2)));
+    }
+}
+
+#[test_case]
+/// validate that images that are no ELF files are rejected
+fn reject_garbage() {
+    assert!(matches!(
+        loader::exec(&[0; 128], &[], &[]),
+        Err(LoadError::Elf(_))
+    ));
+}
+
+#[test_case]
+/// validate that waiting runs a spawned process and reaps it
+fn spawn_and_wait() {
+    let image = executable(&EXIT_WITH_ARGC);
+    let pid = process::spawn("argc", &image, &["argc", "x"], &[]).expect("spawn failed");
+    assert!(process::list().iter().any(|info| info.pid == pid));
+    assert_eq!(process::wait(pid), Ok(ExitStatus::Exited(2)));
+    assert!(process::list().iter().all(|info| info.pid != pid));
+    assert_eq!(process::wait(pid), Err(ProcessError::NotFound));
+}
+
+#[test_case]
+/// validate that a killed process is never run and reports being killed
+fn kill_before_start() {
+    let image = executable(&EXIT_WITH_ARGC);
+    let pid = process::spawn("victim", &image, &[], &[]).expect("spawn failed");
+    assert_eq!(process::kill(pid), Ok(()));
+    assert_eq!(process::wait(pid), Ok(ExitStatus::Killed));
+}
+
+#[test_case]
+/// validate that a program loading GS doesn't break interrupts and syscalls,
+/// and keeps its GS
+fn load_gs_and_syscall() {
+    let selector = gdt::selectors().user_data_selector.0;
+    let mut code = [
+        0xb8, 0x00, 0x00, 0x00, 0x00, // mov eax, user data selector
+        0x8e, 0xe8, // mov gs, eax
+        0xbf, 0x1e, 0x00, 0x00, 0x00, // mov edi, 30
+        0xb8, 0x03, 0x00, 0x00, 0x00, // mov eax, 3 (sleep)
+        0x0f, 0x05, // syscall
+        0xb9, 0x00, 0x00, 0x00, 0x01, // mov ecx, 0x1000000
+        0xff, 0xc9, // loop: dec ecx, interrupted in ring 3 meanwhile
+        0x75, 0xfc, // jnz loop
+        0xb8, 0x03, 0x00, 0x00, 0x00, // mov eax, 3 (sleep)
+        0xbf, 0x01, 0x00, 0x00, 0x00, // mov edi, 1
+        0x0f, 0x05, // syscall
+        0x8c, 0xef, // mov edi, gs
+        0xb8, 0x02, 0x00, 0x00, 0x00, // mov eax, 2 (exit)
+        0x0f, 0x05, // syscall
+    ];
+    code[1..3].copy_from_slice(&selector.to_le_bytes());
+    let image = executable(&code);
+    let pid = process::spawn("gs", &image, &[], &[]).expect("spawn failed");
+    assert_eq!(
+        process::wait(pid),
+        Ok(ExitStatus::Exited(i32::from(selector)))
+    );
+}
+
+#[test_case]
+/// validate that a faulting child is stopped and `wait` reports the fault
+fn faulting_child() {
+    let programs: [(&[u8], ExceptionVector); 4] = [
+        (&[0x31, 0xc9, 0xf7, 0xf1], ExceptionVector::Division), // xor ecx, ecx; div ecx
+        (&[0x0f, 0x0b], ExceptionVector::InvalidOpcode),        // ud2
+        (&[0xf4], ExceptionVector::GeneralProtection),          // hlt
+        (
+            &[0x48, 0x8b, 0x04, 0x25, 0x00, 0x00, 0x00, 0x00], // mov rax, [0]
+            ExceptionVector::Page,
+        ),
+    ];
+    for (code, vector) in programs {
+        let image = executable(code);
+        let pid = process::spawn("faulty", &image, &[], &[]).expect("spawn failed");
+        assert_eq!(process::wait(pid), Ok(ExitStatus::Faulted(vector as u8)));
+    }
+    // the kernel survived, user programs still run
+    let image = executable(&EXIT_WITH_ARGC);
+    assert_eq!(
+        loader::exec(&image, &["argc"], &[]),
+        Ok(ExitStatus::Exited(1))
+    );
+}
+
+#[test_case]
+/// validate that a failed `mmap` gives its address range back
+fn failed_mmap_releases_range() {
+    let code = [
+        0x31, 0xff, // xor edi, edi
+        0x48, 0xbe, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, // mov rsi, 4 GiB
+        0xba, 0x03, 0x00, 0x00, 0x00, // mov edx, PROT_READ | PROT_WRITE
+        0xb8, 0x05, 0x00, 0x00, 0x00, // mov eax, 5 (mmap)
+        0x0f, 0x05, // syscall
+        0x48, 0x83, 0xf8, 0xf4, // cmp rax, -NoMem
+        0x75, 0x2e, // jne fail
+        0x31, 0xff, // xor edi, edi
+        0xbe, 0x00, 0x10, 0x00, 0x00, // mov esi, 4096
+        0xba, 0x03, 0x00, 0x00, 0x00, // mov edx, PROT_READ | PROT_WRITE
+        0xb8, 0x05, 0x00, 0x00, 0x00, // mov eax, 5 (mmap)
+        0x0f, 0x05, // syscall
+        0x48, 0xb9, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, // mov rcx, MMAP_START
+        0x48, 0x29, 0xc8, // sub rax, rcx
+        0x48, 0xc1, 0xe8, 0x0c, // shr rax, 12
+        0x48, 0x89, 0xc7, // mov rdi, rax
+        0xb8, 0x02, 0x00, 0x00, 0x00, // mov eax, 2 (exit)
+        0x0f, 0x05, // syscall
+        0xbf, 0xff, 0xff, 0xff, 0xff, // fail: mov edi, -1
+        0xb8, 0x02, 0x00, 0x00, 0x00, // mov eax, 2 (exit)
+        0x0f, 0x05, // syscall
+    ];
+    assert_eq!(process::MMAP_START, 0x2000_0000_0000);
+    let image = executable(&code);
+    let pid = process::spawn("mmap", &image, &[], &[]).expect("spawn failed");
+    // the page after the failed mmap starts where the failed one did
+    assert_eq!(process::wait(pid), Ok(ExitStatus::Exited(0)));
+}
+
+#[test_case]
+/// validate that `munmap` gives its range back and `mmap` rejects `PROT_NONE`
+fn munmap_releases_range() {
+    let code = [
+        0x31, 0xff, // xor edi, edi
+        0xbe, 0x00, 0x20, 0x00, 0x00, // mov esi, 8192
+        0xba, 0x03, 0x00, 0x00, 0x00, // mov edx, PROT_READ | PROT_WRITE
+        0xb8, 0x05, 0x00, 0x00, 0x00, // mov eax, 5 (mmap)
+        0x0f, 0x05, // syscall
+        0x48, 0x89, 0xc3, // mov rbx, rax
+        0x31, 0xff, // xor edi, edi
+        0xbe, 0x00, 0x10, 0x00, 0x00, // mov esi, 4096
+        0xba, 0x03, 0x00, 0x00, 0x00, // mov edx, PROT_READ | PROT_WRITE
+        0xb8, 0x05, 0x00, 0x00, 0x00, // mov eax, 5 (mmap)
+        0x0f, 0x05, // syscall
+        0x48, 0x89, 0xdf, // mov rdi, rbx
+        0xbe, 0x00, 0x20, 0x00, 0x00, // mov esi, 8192
+        0xb8, 0x06, 0x00, 0x00, 0x00, // mov eax, 6 (munmap)
+        0x0f, 0x05, // syscall
+        0x31, 0xff, // xor edi, edi
+        0xbe, 0x00, 0x10, 0x00, 0x00, // mov esi, 4096
+        0x31, 0xd2, // xor edx, edx (PROT_NONE)
+        0xb8, 0x05, 0x00, 0x00, 0x00, // mov eax, 5 (mmap)
+        0x0f, 0x05, // syscall
+        0x48, 0x83, 0xf8, 0xea, // cmp rax, -Inval
+        0x75, 0x20, // jne fail
+        0x31, 0xff, // xor edi, edi
+        0xbe, 0x00, 0x10, 0x00, 0x00, // mov esi, 4096
+        0xba, 0x03, 0x00, 0x00, 0x00, // mov edx, PROT_READ | PROT_WRITE
+        0xb8, 0x05, 0x00, 0x00, 0x00, // mov eax, 5 (mmap)
+        0x0f, 0x05, // syscall
+        0x48, 0x29, 0xd8, // sub rax, rbx
+        0x48, 0x89, 0xc7, // mov rdi, rax
+        0xb8, 0x02, 0x00, 0x00, 0x00, // mov eax, 2 (exit)
+        0x0f, 0x05, // syscall
+        0xbf, 0xff, 0xff, 0xff, 0xff, // fail: mov edi, -1
+        0xb8, 0x02, 0x00, 0x00, 0x00, // mov eax, 2 (exit)
+        0x0f, 0x05, // syscall
+    ];
+    let image = executable(&code);
+    let pid = process::spawn("munmap", &image, &[], &[]).expect("spawn failed");
+    // the page after the munmap reuses the start of the unmapped range
+    assert_eq!(process::wait(pid), Ok(ExitStatus::Exited(0)));
+}
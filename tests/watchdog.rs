@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(atlas::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+
+use atlas::{apic, cpu, memory, smp, task::executor, watchdog};
+use bootloader::{entry_point, BootInfo};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    let (mapper, frame_allocator) = atlas::test_init(boot_info);
+    memory::install(mapper, frame_allocator);
+    smp::init();
+
+    test_main();
+    atlas::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    atlas::test_panic_handler(info)
+}
+
+/// Code the NMI interrupts, kept small so its address range is known
+#[inline(never)]
+fn spin_until_reported(reports: u64) {
+    while watchdog::reports() == reports {
+        core::hint::spin_loop();
+    }
+}
+
+#[test_case]
+/// validate that the NMI report, written on the NMI's own stack, walks the
+/// frames of the interrupted code
+fn nmi_report_shows_interrupted_code() {
+    let reports = watchdog::reports();
+    let bsp = cpu::current_id() as u8;
+    let other = (0..cpu::MAX_CPUS)
+        .find(|&cpu| cpu != usize::from(bsp) && smp::online_mask() & (1 << cpu) != 0)
+        .expect("test needs a second CPU");
+    executor::spawn_on(other, async move { apic::send_nmi(bsp) }).detach();
+    spin_until_reported(reports);
+
+    let start = spin_until_reported as fn(u64) as usize as u64;
+    let frames = watchdog::last_report();
+    assert!(
+        frames
+            .iter()
+            .any(|&frame| (start..start + 0x100).contains(&frame)),
+        "interrupted function missing from {:x?}",
+        frames
+    );
+}